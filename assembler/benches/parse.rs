@@ -0,0 +1,47 @@
+//! Parses a large generated program and prints the throughput:
+//! `cargo bench --bench parse [lines]`.
+//!
+//! On the default 500k lines the parser before the line-oriented rewrite
+//! managed about 240k-290k lines/s (5-6 MB/s); after it, about 550k-590k
+//! lines/s (12 MB/s) on the same machine.
+use riscv_assembler::parser::parse;
+use std::time::Instant;
+
+/// A program of about `lines` lines, with a bit of everything the parser
+/// sees in a real one.
+fn program(lines: usize) -> String {
+    let mut source = String::from(".equ STEP, 4\n.globl main\nmain:\n");
+    for n in 0..lines / 10 {
+        source += &format!("loop{}:\n", n);
+        source += "    addi a0, a0, STEP  # count up\n";
+        source += "    lw t0, 8(sp)\n";
+        source += "    sw t0, -4(sp)\n";
+        source += "    add a1, a1, t0\n";
+        source += "    slli a2, a1, 2\n";
+        source += &format!("    bne a0, a1, loop{}\n", n);
+        source += "    lui s1, 0x12345\n";
+        source += &format!("    jal ra, loop{}\n", n);
+        source += "    .word 1, 2, STEP * 3\n";
+    }
+    source
+}
+
+fn main() {
+    let lines = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(500_000);
+    let source = program(lines);
+    let start = Instant::now();
+    let file = parse(&source).unwrap();
+    let elapsed = start.elapsed();
+    let seconds = elapsed.as_secs_f64();
+    println!(
+        "{} lines ({} items) in {:.2?}: {:.0} lines/s, {:.1} MB/s",
+        source.lines().count(),
+        file.sections[0].items.len(),
+        elapsed,
+        source.lines().count() as f64 / seconds,
+        source.len() as f64 / seconds / 1e6
+    );
+}
@@ -0,0 +1 @@
+label: jal zero anotherLabel
\ No newline at end of file
@@ -0,0 +1,4 @@
+1: beq a0, zero, 1f
+1: bne a0, a1, 1b
+.word 1b, 1f
+1:
\ No newline at end of file
@@ -0,0 +1 @@
+addi a0, a0, MISSING + 1
\ No newline at end of file
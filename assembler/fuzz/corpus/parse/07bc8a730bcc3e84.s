@@ -0,0 +1,4 @@
+1: beq a0, zero, 1f
+addi a0, a0, -1
+jal zero, 1b
+1: .word 1b
\ No newline at end of file
@@ -0,0 +1 @@
+.insn r custom_0, 0, 1, a0, a1, a2
\ No newline at end of file
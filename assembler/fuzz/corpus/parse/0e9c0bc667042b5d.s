@@ -0,0 +1 @@
+.fill 1, 9, 0
\ No newline at end of file
@@ -0,0 +1,8 @@
+
+            .globl main
+            main: jal ra, printf
+            beq a0, zero, main
+            lui a0, %hi(msg)
+            .data
+            msg: .word main + 4
+            
\ No newline at end of file
@@ -0,0 +1 @@
+mac a0, a1, a2
\ No newline at end of file
@@ -0,0 +1,12 @@
+
+            _start: jal ra, f
+            jal ra, g
+            f: addi sp, sp, -4
+            sw s0, 0(sp)
+            addi s0, zero, 1
+            lw s0, 0(sp)
+            addi sp, sp, 4
+            jalr zero, 0(ra)
+            g: addi s1, zero, 1
+            jalr zero, 0(ra)
+        
\ No newline at end of file
@@ -0,0 +1,2 @@
+hello:
+ sll zero, ra, sp
\ No newline at end of file
@@ -0,0 +1,3 @@
+beq a0, zero, far
+.space 0x1000
+far: jal ra, 0
\ No newline at end of file
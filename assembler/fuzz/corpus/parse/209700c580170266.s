@@ -0,0 +1,7 @@
+
+            .equ TARGET, 0x1900
+            addi zero, zero, 0
+            .Lhi: auipc a0, %pcrel_hi(TARGET)
+            addi a0, a0, %pcrel_lo(.Lhi)
+            sw a1, %pcrel_lo(.Lhi)(a0)
+            
\ No newline at end of file
@@ -0,0 +1,6 @@
+
+            lw a0, msg_end - msg(zero)
+            .data
+            msg: .word 1, 2
+            msg_end: .word msg_end - msg
+            
\ No newline at end of file
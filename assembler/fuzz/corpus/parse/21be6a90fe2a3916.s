@@ -0,0 +1 @@
+.else
\ No newline at end of file
@@ -0,0 +1 @@
+.balign 6
\ No newline at end of file
@@ -0,0 +1,6 @@
+
+            .equ UART_BASE, 0x10000000
+            .set OFFSET, 4 * 2
+            lui t0, UART_BASE >> 12
+            addi t0, t0, OFFSET + 'A'
+            
\ No newline at end of file
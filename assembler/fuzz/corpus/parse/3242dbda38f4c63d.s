@@ -0,0 +1 @@
+addi a0, a0, (1 << 4) - 'A'
\ No newline at end of file
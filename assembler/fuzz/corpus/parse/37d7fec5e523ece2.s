@@ -0,0 +1,2 @@
+label:
+ blt s1, s2, label2
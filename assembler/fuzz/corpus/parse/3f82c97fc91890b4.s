@@ -0,0 +1 @@
+x: .8byte -1, 2
\ No newline at end of file
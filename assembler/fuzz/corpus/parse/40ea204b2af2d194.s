@@ -0,0 +1,2 @@
+.space SIZE
+SIZE: .word 4
\ No newline at end of file
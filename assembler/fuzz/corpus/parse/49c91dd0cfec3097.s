@@ -0,0 +1 @@
+.words 1
\ No newline at end of file
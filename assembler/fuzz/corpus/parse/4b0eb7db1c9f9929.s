@@ -0,0 +1 @@
+.insn i 0x0b, 8, a0, a1, 1
\ No newline at end of file
@@ -0,0 +1,15 @@
+
+            addi a0, a0, 1
+            .align 3
+            main: addi a0, a0, 2
+            .org 0x10
+            end: addi a0, a0, 3
+            .section .rodata
+            tbl: .word main, end
+            .balign 16, 0xFF
+            .bss
+            buf: .space 64
+            .data
+            ptr: .word buf
+            .space 2, 0xAB
+            
\ No newline at end of file
@@ -0,0 +1,3 @@
+jal ra, far
+.space 0x100000
+far: jal ra, 0
\ No newline at end of file
@@ -0,0 +1,12 @@
+
+            .section .rodata, "a", @progbits
+            .balign 8, 0xFF
+            .section .init, "ax"
+            .p2align 2
+            .bss
+            buf: .space 16
+            .text
+            .org 0x100
+            .section .rodata
+            .zero 4
+            
\ No newline at end of file
@@ -0,0 +1 @@
+beq a0, zero, 0x2000
\ No newline at end of file
@@ -0,0 +1 @@
+jal ra, .Lmissing
\ No newline at end of file
@@ -0,0 +1,3 @@
+addi a0, a0, 1
+addi a0, a0, 1
+.org 4
\ No newline at end of file
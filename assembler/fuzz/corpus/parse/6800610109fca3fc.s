@@ -0,0 +1 @@
+a: .byte a
\ No newline at end of file
@@ -0,0 +1,12 @@
+
+            _start: addi zero, zero, 0
+            add zero, a0, a1
+            slli a0, a0, 32
+            lui a0, 0x10000
+            addi a0, a0, 2
+            lw a1, 0(a0)
+            sh a1, 2(a0)
+            jal zero, _start
+            addi a0, a0, 1
+            unused: jalr zero, 0(ra)
+        
\ No newline at end of file
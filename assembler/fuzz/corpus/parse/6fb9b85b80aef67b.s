@@ -0,0 +1,3 @@
+.globl main, exit
+.local main
+main:
\ No newline at end of file
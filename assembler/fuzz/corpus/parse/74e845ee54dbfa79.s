@@ -0,0 +1,2 @@
+.if later
+later: .endif
\ No newline at end of file
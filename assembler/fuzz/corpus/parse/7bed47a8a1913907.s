@@ -0,0 +1,7 @@
+
+            .data
+            .byte 0x12, -1
+            .half 0x3456
+            .fill 3, 2, 0xABCD
+            .dword -2
+            
\ No newline at end of file
@@ -0,0 +1 @@
+.byte 256
\ No newline at end of file
@@ -0,0 +1,4 @@
+.globl main
+main:
+1: jal zero, 1b
+.Lnext: jal zero, .Lnext
\ No newline at end of file
@@ -0,0 +1 @@
+.float 1.5, -2
\ No newline at end of file
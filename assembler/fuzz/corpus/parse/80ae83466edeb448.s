@@ -0,0 +1,5 @@
+
+            .equ ADDR, 0x10000800
+            lui a0, %hi(ADDR)
+            addi a0, a0, %lo(ADDR)
+            
\ No newline at end of file
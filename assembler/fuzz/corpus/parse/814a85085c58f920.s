@@ -0,0 +1,2 @@
+loop: addi a0, a0, 1
+jal zero, loop
\ No newline at end of file
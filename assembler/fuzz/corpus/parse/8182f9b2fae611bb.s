@@ -0,0 +1,2 @@
+addi a0, a0, 1
+l: addi a0, a0, 1 addi
@@ -0,0 +1 @@
+hello: add zero, ra, sp
\ No newline at end of file
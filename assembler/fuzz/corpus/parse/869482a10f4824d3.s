@@ -0,0 +1 @@
+lw a0, BUF + 4(gp)
\ No newline at end of file
@@ -0,0 +1 @@
+.ascii "open
\ No newline at end of file
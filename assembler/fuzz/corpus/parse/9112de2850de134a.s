@@ -0,0 +1,2 @@
+.equ A, B
+.equ B, A
\ No newline at end of file
@@ -0,0 +1,7 @@
+
+            start: addi a0, zero, LEN
+            beq a0, zero, end
+            addi a0, a0, -1
+            .equ LEN, end - start
+            end:
+            
\ No newline at end of file
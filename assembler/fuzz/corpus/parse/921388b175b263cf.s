@@ -0,0 +1,2 @@
+.local helper
+jal ra, helper
\ No newline at end of file
@@ -0,0 +1 @@
+label: beq s1, s2, 123
\ No newline at end of file
@@ -0,0 +1,4 @@
+.if 1
+.else
+.elseif 1
+.endif
\ No newline at end of file
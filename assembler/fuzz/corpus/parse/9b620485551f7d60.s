@@ -0,0 +1 @@
+jal ra, missing
\ No newline at end of file
@@ -0,0 +1,7 @@
+a: b:
+
+  addi a0, a0, 1
+c:
+.data
+d: .word 1
+e:
\ No newline at end of file
@@ -0,0 +1,2 @@
+.if 1
+nop
\ No newline at end of file
@@ -0,0 +1,2 @@
+addi a0, a0, 1
+bogus a0
\ No newline at end of file
@@ -0,0 +1 @@
+jal s1 cool_label
\ No newline at end of file
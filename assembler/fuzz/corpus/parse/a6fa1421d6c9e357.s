@@ -0,0 +1 @@
+bne zero, s2, label2
\ No newline at end of file
@@ -0,0 +1 @@
+.insn r 0x0b, 0, 0x80, a0, a1, a2
\ No newline at end of file
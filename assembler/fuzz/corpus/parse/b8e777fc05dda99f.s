@@ -0,0 +1,2 @@
+.asciz ""
+.ascii "x"
\ No newline at end of file
@@ -0,0 +1,7 @@
+loop: sub a0, a1, a2
+            addi a0, a1, -5
+            lw a0, 8(sp)
+            sw a0, -4(sp)
+            bne a0, a1, loop
+            lui a0, 0x12345
+            jal ra, loop
\ No newline at end of file
@@ -0,0 +1 @@
+	blt s1, s2, 0xa23
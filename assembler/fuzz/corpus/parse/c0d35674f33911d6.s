@@ -0,0 +1 @@
+lui s1 0x12312A
\ No newline at end of file
@@ -0,0 +1 @@
+.string "unterminated
\ No newline at end of file
@@ -0,0 +1,3 @@
+addi a0, a0, 1
+
+bogus a0
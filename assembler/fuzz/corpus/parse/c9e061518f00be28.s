@@ -0,0 +1 @@
+op: .insn sb custom_1, 3, a0, a1, op
\ No newline at end of file
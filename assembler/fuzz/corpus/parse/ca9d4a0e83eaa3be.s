@@ -0,0 +1 @@
+.insn r 0x80, 0, 0, a0, a1, a2
\ No newline at end of file
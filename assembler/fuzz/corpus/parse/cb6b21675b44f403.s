@@ -0,0 +1,2 @@
+.bss
+addi a0, a0, 1
\ No newline at end of file
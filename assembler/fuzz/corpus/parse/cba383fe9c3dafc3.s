@@ -0,0 +1 @@
+msg: .string "Hello, world!\n", "\x41\101\t\""
\ No newline at end of file
@@ -0,0 +1,7 @@
+loop: .insn r 0x33, 0, 0x20, a0, a1, a2
+            .insn i 0x13, 0, a0, a1, -5
+            .insn i 0x03, 2, a0, 8(sp)
+            .insn s 0x23, 2, a0, -4(sp)
+            .insn sb 0x63, 1, a0, a1, loop
+            .insn u 0x37, a0, 0x12345
+            .insn j 0x6f, ra, loop
\ No newline at end of file
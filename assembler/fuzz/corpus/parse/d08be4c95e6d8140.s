@@ -0,0 +1 @@
+start: addi a0, a0, %pcrel_lo(start)
\ No newline at end of file
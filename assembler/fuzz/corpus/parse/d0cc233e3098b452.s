@@ -0,0 +1,8 @@
+# startup
+            .equ UART_BASE, 0x10000000 # comment
+            main: lui t0, UART_BASE >> 12
+            jal zero main
+            done:
+            .data
+            msg: .word '#', done - main
+            
\ No newline at end of file
@@ -0,0 +1 @@
+label: jalr zero, 0xabc(ra)
\ No newline at end of file
@@ -0,0 +1,17 @@
+
+            .equ DEBUG, 0
+            .if DEBUG
+            this isn't an instruction
+            .elseif UART == 2
+            addi a0, a0, 2
+            .else
+            addi a0, a0, 3
+            .endif
+            .ifndef UART
+            .ifdef DEBUG
+            nested, but skipped
+            .endif
+            .else
+            addi a1, a1, 1
+            .endif
+        
\ No newline at end of file
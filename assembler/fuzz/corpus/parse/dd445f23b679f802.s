@@ -0,0 +1 @@
+label: lw s1, 123(s2)
\ No newline at end of file
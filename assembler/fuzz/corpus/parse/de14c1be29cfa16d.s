@@ -0,0 +1 @@
+beq a0, zero, 1b
\ No newline at end of file
@@ -0,0 +1,2 @@
+hello:
+jal zero 0x12312A
\ No newline at end of file
@@ -0,0 +1 @@
+lb s1, 0x1b3(s2)
@@ -0,0 +1 @@
+2: jal zero, 2f
\ No newline at end of file
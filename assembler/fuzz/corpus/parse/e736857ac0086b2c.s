@@ -0,0 +1,2 @@
+	 label: 
+ lhu s1, 0b101 (  s2 )  
@@ -0,0 +1 @@
+hello: addi zero, ra, 0b101010
\ No newline at end of file
@@ -0,0 +1,9 @@
+
+            _start: lui a0, %hi(__data_load)
+            addi a0, a0, %lo(__data_load)
+            lui a1, %hi(x)
+            .data
+            x: .word __data_end
+            .bss
+            y: .space 8
+        
\ No newline at end of file
@@ -0,0 +1 @@
+e: .double 2.5e-3
\ No newline at end of file
@@ -0,0 +1 @@
+.ascii "a # b"
\ No newline at end of file
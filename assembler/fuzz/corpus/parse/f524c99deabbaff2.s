@@ -0,0 +1 @@
+.2byte 1
\ No newline at end of file
@@ -0,0 +1 @@
+a: .word a * 2
\ No newline at end of file
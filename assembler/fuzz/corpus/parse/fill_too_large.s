@@ -0,0 +1 @@
+.fill 0x10000000, 8, 0
@@ -0,0 +1 @@
+.org 0x40000000
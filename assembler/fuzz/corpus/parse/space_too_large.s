@@ -0,0 +1,2 @@
+.data
+buf: .space 0xFFFFFFF0
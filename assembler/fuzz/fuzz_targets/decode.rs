@@ -0,0 +1,12 @@
+//! Arbitrary words through the decoder, which has to give back an
+//! instruction that encodes to the same word.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use riscv_assembler::{decode, encode};
+
+fuzz_target!(|word: u32| {
+    if let Some(data) = decode(word) {
+        assert_eq!(encode(&data), Ok(word), "{:?}", data);
+    }
+});
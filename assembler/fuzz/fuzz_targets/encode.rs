@@ -0,0 +1,42 @@
+//! Arbitrary instructions, as a parser bug or a library user could make
+//! them.
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use riscv_assembler::instructions::{generate_instruction, Encoding, Format, InstructionData};
+use riscv_assembler::{decode, encode};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    mne: String,
+    rd: Option<u32>,
+    rs1: Option<u32>,
+    rs2: Option<u32>,
+    imm: Option<u32>,
+    custom: Option<(u8, u32, u32, u32)>,
+}
+
+fuzz_target!(|input: Input| {
+    let formats = [Format::R, Format::I, Format::S, Format::B, Format::U, Format::J];
+    let data = InstructionData {
+        mne: input.mne,
+        rd: input.rd,
+        rs1: input.rs1,
+        rs2: input.rs2,
+        imm: input.imm,
+        custom: input.custom.map(|(format, opcode, funct3, funct7)| Encoding {
+            format: formats[format as usize % formats.len()],
+            opcode,
+            funct3,
+            funct7,
+        }),
+    };
+    let _ = generate_instruction(data.clone()).map(|instruction| instruction.translate());
+    if let Ok(word) = encode(&data) {
+        // Whatever encodes has to decode to something that encodes the same
+        if let Some(decoded) = decode(word) {
+            assert_eq!(encode(&decoded), Ok(word));
+        }
+    }
+});
@@ -0,0 +1,17 @@
+//! Source text through everything the CLI can do with it.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use riscv_assembler::hazard::{self, Pipeline};
+use riscv_assembler::{assemble_file, assembler, lint, parse, Options};
+
+fuzz_target!(|source: &str| {
+    let options = Options::default();
+    if let Ok(mut file) = parse(source, &options) {
+        let _ = lint::lint(&file, &[], "_start");
+        let _ = hazard::hazards(&file, &Pipeline::default());
+        let _ = assembler::object(&file, &options.assembler);
+        let _ = hazard::insert_nops(&mut file, &Pipeline::default());
+        let _ = assemble_file(file, &options);
+    }
+});
@@ -1,2 +1,1541 @@
-const START_ADDRESS: u32 = 0x00000000;
+use std::collections::HashMap;
 
+use crate::expr::{hi, lo, BinOp, Expr, Reloc};
+use crate::instructions::types::Imm;
+use crate::instructions::isa::{self, decode, Syntax};
+use crate::instructions::{generate_instruction, Format, InstructionData};
+use crate::link::{self, link, padding, Image};
+use crate::object::{Definition, Field, Object, ObjectSection, Relocation, Symbol, Target};
+use crate::parser::{num_to_bytes, Binding, Constant, Data, DataSize, FullFile, Item, Section, Text};
+use crate::relax::{self, Relaxation};
+use crate::Diagnostic;
+
+/// Every section starts at least word aligned.
+const SECTION_ALIGN: u32 = 4;
+
+/// The most a single `.space`, `.fill`, `.org` or alignment can reserve.
+/// The bytes are built in memory, so anything larger is refused before
+/// allocating them.
+const MAX_RESERVE: u32 = 256 << 20;
+
+type Symbols = HashMap<String, i64>;
+
+/// Bytes taken by the branches and jumps that had to be relaxed, by section
+/// and item.
+type Sizes = HashMap<(usize, usize), u32>;
+
+/// How to assemble a file.
+#[derive(Debug)]
+pub struct Options {
+    /// Rewrite branches and jumps that can't reach their target, rather
+    /// than failing.
+    pub relax: bool,
+    /// Where `assemble` places the sections.
+    pub link: link::Options,
+    /// Fail on anything that holds an absolute address, which would be
+    /// wrong once the program is copied somewhere else.
+    pub pic: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            relax: true,
+            link: link::Options::default(),
+            pic: false,
+        }
+    }
+}
+
+/// What a symbol or expression stands for before linking: a number, or an
+/// offset from where a section or another file's symbol ends up.
+#[derive(Debug, PartialEq, Clone)]
+struct Value {
+    target: Option<Target>,
+    offset: i64,
+}
+
+type Values = HashMap<String, Value>;
+
+/// An immediate, or what the linker needs to work it out.
+enum Resolved {
+    Imm(i64),
+    Relocation(Target, i64),
+}
+
+/// Where the items of a section go, relative to its start.
+struct SectionLayout {
+    align: u32,
+    size: u32,
+    addresses: Vec<u32>,
+}
+
+impl Value {
+    fn absolute(offset: i64) -> Value {
+        Value {
+            target: None,
+            offset,
+        }
+    }
+
+    fn at(index: usize, offset: u32) -> Value {
+        Value {
+            target: Some(Target::Section(index)),
+            offset: offset as i64,
+        }
+    }
+
+    fn constant(&self) -> Result<i64, String> {
+        match self.target {
+            None => Ok(self.offset),
+            Some(_) => Err(NOT_RELOCATABLE.to_string()),
+        }
+    }
+
+    /// The value as an immediate, if it doesn't depend on the link.
+    fn resolved(self) -> Resolved {
+        match self.target {
+            None => Resolved::Imm(self.offset),
+            Some(target) => Resolved::Relocation(target, self.offset),
+        }
+    }
+
+    /// The distance from `from`, if both are in the same place.
+    fn relative_to(self, from: &Value) -> Resolved {
+        match self.target {
+            Some(target) if Some(&target) == from.target.as_ref() => {
+                Resolved::Imm(self.offset - from.offset)
+            }
+            target => Resolved::Relocation(target.unwrap_or(Target::Absolute), self.offset),
+        }
+    }
+}
+
+const NOT_RELOCATABLE: &str = "an address can only have a constant added or subtracted";
+
+fn define<V>(symbols: &mut HashMap<String, V>, name: &str, value: V) -> Result<(), String> {
+    match symbols.insert(name.to_string(), value) {
+        Some(_) => Err(format!("symbol `{}` is defined more than once", name)),
+        None => Ok(()),
+    }
+}
+
+fn lookup(symbols: &Symbols) -> impl Fn(&str) -> Result<i64, String> + '_ {
+    move |name| {
+        symbols
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("undefined symbol `{}`", name))
+    }
+}
+
+/// Like `lookup`, but a symbol this file doesn't define is left for the
+/// linker, and `.` is `here`.
+fn lookup_at<'a>(values: &'a Values, here: &'a Value) -> impl Fn(&str) -> Result<Value, String> + 'a {
+    move |name| match name {
+        "." => Ok(here.clone()),
+        _ => Ok(values.get(name).cloned().unwrap_or(Value {
+            target: Some(Target::Symbol(name.to_string())),
+            offset: 0,
+        })),
+    }
+}
+
+/// Evaluates an expression that may refer to addresses. Those are only known
+/// once linked, so the result can be at most one address plus a constant.
+fn eval_value<F>(expr: &Expr, lookup: &F) -> Result<Value, String>
+where
+    F: Fn(&str) -> Result<Value, String>,
+{
+    match expr {
+        Expr::Num(n) => Ok(Value::absolute(*n)),
+        Expr::Sym(s) => lookup(s),
+        Expr::Bin(op @ (BinOp::Add | BinOp::Sub), l, r) => {
+            let (l, r) = (eval_value(l, lookup)?, eval_value(r, lookup)?);
+            let offset = op.apply(l.offset, r.offset)?;
+            match (op, l.target, r.target) {
+                (_, target, None) | (BinOp::Add, None, target) => Ok(Value { target, offset }),
+                // The distance between two addresses in the same place
+                (BinOp::Sub, Some(l), Some(r)) if l == r => Ok(Value::absolute(offset)),
+                _ => Err(NOT_RELOCATABLE.to_string()),
+            }
+        }
+        Expr::Bin(op, l, r) => {
+            let l = eval_value(l, lookup)?.constant()?;
+            let r = eval_value(r, lookup)?.constant()?;
+            op.apply(l, r).map(Value::absolute)
+        }
+        Expr::Neg(_) | Expr::Not(_) | Expr::Reloc(..) => {
+            let constant = |name: &str| lookup(name)?.constant();
+            expr.eval(&constant).map(Value::absolute)
+        }
+    }
+}
+
+/// Branches and jumps take a target, so a symbolic immediate is turned into
+/// an offset from the instruction.
+fn is_pc_relative(instruction: &InstructionData) -> bool {
+    matches!(instruction.format(), Format::B | Format::J)
+}
+
+/// Checks that `value` fits the immediate of `instruction` before it's cut
+/// down to the field. Branch and jump offsets are checked once they're laid
+/// out, since they can be relaxed.
+fn check_imm(instruction: &InstructionData, value: i64) -> Result<(), String> {
+    let shift = instruction.custom.is_none()
+        && isa::lookup(&instruction.mne).is_some_and(|opcode| opcode.syntax == Syntax::Shift);
+    let (what, range) = match instruction.format() {
+        _ if shift => ("shift amount", 0..=31),
+        Format::I | Format::S => ("immediate", -2048..=2047),
+        Format::U => ("immediate", 0..=0xFFFFF),
+        _ => return Ok(()),
+    };
+    match range.contains(&value) {
+        true => Ok(()),
+        false => Err(format!("{} {} is out of range {}..={}", what, value, range.start(), range.end())),
+    }
+}
+
+/// The bits of an instruction that hold its immediate.
+fn imm_field(instruction: &InstructionData) -> Field {
+    match instruction.format() {
+        Format::B => Field::B,
+        Format::J => Field::J,
+        Format::U => Field::U,
+        Format::S => Field::S,
+        Format::I | Format::R => Field::I,
+    }
+}
+
+/// Fails if a relocation puts an absolute address in the code or data,
+/// rather than the distance to it.
+fn check_pic(relocations: &[Relocation]) -> Result<(), String> {
+    for relocation in relocations {
+        let error = match (relocation.reloc, relocation.field) {
+            (Some(Reloc::PcrelHi | Reloc::PcrelLo), _) | (None, Field::B | Field::J) => continue,
+            (Some(Reloc::Hi | Reloc::Lo), _) => {
+                "`%hi` and `%lo` of an address aren't position-independent, use `la` or `%pcrel_hi` and `%pcrel_lo`"
+            }
+            (None, Field::Word) => {
+                "an address in `.word` isn't position-independent, store its distance from another label instead"
+            }
+            (None, _) => "an address as an immediate isn't position-independent",
+        };
+        return Err(error.to_string());
+    }
+    Ok(())
+}
+
+/// `addi zero, zero, 0`
+pub fn nop() -> Vec<u8> {
+    0x0000_0013u32.to_be_bytes().to_vec()
+}
+
+/// The bytes of a data directive, and relocations for the values that are
+/// addresses, at offsets from its start.
+fn data_bytes<F>(data: &Data, lookup: &F) -> Result<(Vec<u8>, Vec<Relocation>), String>
+where
+    F: Fn(&str) -> Result<Value, String>,
+{
+    let mut bytes = data.data.clone();
+    let mut relocations = vec![];
+    for expr in &data.exprs {
+        let value = eval_value(expr, lookup)?;
+        match value.target {
+            Some(_) if data.size != DataSize::Word => {
+                return Err("only `.word` can hold an address".to_string());
+            }
+            Some(target) => {
+                relocations.push(Relocation {
+                    offset: bytes.len() as u32,
+                    field: Field::Word,
+                    reloc: None,
+                    target,
+                    addend: value.offset,
+                });
+                bytes.extend([0; 4]);
+            }
+            None => bytes.extend(num_to_bytes(value.offset, data.size.bytes())?),
+        }
+    }
+    Ok((bytes, relocations))
+}
+
+/// Evaluates every constant it can, adding them to `symbols`, and returns
+/// the ones that still refer to something unknown. Constants may refer to
+/// each other in any order, so this keeps going until nothing changes.
+fn eval_constants<'a, V, F>(
+    consts: &'a [Constant],
+    symbols: &mut HashMap<String, V>,
+    eval: F,
+) -> Result<Vec<&'a Constant>, Diagnostic>
+where
+    F: Fn(&Expr, &HashMap<String, V>) -> Result<V, String>,
+{
+    let mut pending: Vec<_> = consts.iter().collect();
+    loop {
+        let before = pending.len();
+        let mut unresolved = vec![];
+        for constant in pending {
+            let value = eval(&constant.expr, symbols);
+            match value {
+                Ok(value) => define(symbols, &constant.name, value)
+                    .map_err(|e| Diagnostic::at(constant.location.line, e))?,
+                Err(_) => unresolved.push(constant),
+            }
+        }
+        if unresolved.is_empty() || unresolved.len() == before {
+            return Ok(unresolved);
+        }
+        pending = unresolved;
+    }
+}
+
+/// Evaluates the `.equ`/`.set` constants once labels are known.
+fn resolve_constants(file: &FullFile, values: &mut Values) -> Result<(), Diagnostic> {
+    let unresolved = eval_constants(&file.consts, values, |expr, values| {
+        eval_value(expr, &|name: &str| {
+            values
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("undefined symbol `{}`", name))
+        })
+    })?;
+    let constant = match unresolved.first() {
+        Some(constant) => constant,
+        None => return Ok(()),
+    };
+    let defined = |s: &str| values.contains_key(s) || file.consts.iter().any(|c| c.name == s);
+    let known = |s: &str| Ok(values.get(s).cloned().unwrap_or(Value::absolute(0)));
+    let message = match constant.expr.symbols().into_iter().find(|s| !defined(s)) {
+        Some(s) => format!("`{}`: undefined symbol `{}`", constant.name, s),
+        None => match eval_value(&constant.expr, &known) {
+            Err(e) => format!("`{}`: {}", constant.name, e),
+            Ok(_) => format!("`{}` is defined in terms of itself", constant.name),
+        },
+    };
+    Err(Diagnostic::at(constant.location.line, message))
+}
+
+/// Evaluates an expression that decides the layout, so it can only use
+/// constants that don't depend on labels.
+fn absolute(expr: &Expr, consts: &Symbols, what: &str) -> Result<u32, String> {
+    let value = expr
+        .eval(&lookup(consts))
+        .map_err(|e| format!("{} must be a constant: {}", what, e))?;
+    u32::try_from(value).map_err(|_| format!("{} {} is out of range", what, value))
+}
+
+/// The alignment of an `.align`, which pads by up to that many bytes.
+fn align_bytes(bytes: &Expr, consts: &Symbols) -> Result<u32, String> {
+    match absolute(bytes, consts, "alignment")? {
+        n if n.is_power_of_two() => reserve("alignment", n),
+        n => Err(format!("alignment {} is not a power of two", n)),
+    }
+}
+
+/// Bytes of each value of a `.fill`, which can be up to 8.
+fn fill_size(size: &Expr, consts: &Symbols) -> Result<u32, String> {
+    match absolute(size, consts, "`.fill` size")? {
+        size @ 0..=8 => Ok(size),
+        size => Err(format!("`.fill` size {} is larger than 8", size)),
+    }
+}
+
+/// The label that goes with an item, if it has one.
+pub fn item_label(item: &Item) -> Option<&str> {
+    match item {
+        Item::Text(text) => text.label.as_deref(),
+        Item::Data(data) => data.label.as_deref(),
+        Item::Label(label, _) => Some(label),
+        _ => None,
+    }
+}
+
+/// Checks the bytes reserved by a `.space`, `.fill`, `.org` or alignment
+/// against the cap.
+fn reserve(what: &str, size: u32) -> Result<u32, String> {
+    match size {
+        size if size > MAX_RESERVE => Err(format!(
+            "{} of {:#x} bytes is larger than the {:#x} allowed",
+            what, size, MAX_RESERVE
+        )),
+        size => Ok(size),
+    }
+}
+
+fn item_size(item: &Item, address: u32, consts: &Symbols) -> Result<u32, String> {
+    match item {
+        Item::Text(_) => Ok(4),
+        // Only the length matters here, the values may still refer to labels
+        Item::Data(data) => Ok(data_bytes(data, &|_| Ok(Value::absolute(0)))?.0.len() as u32),
+        Item::Label(..) => Ok(0),
+        Item::Align { bytes, .. } => Ok(padding(address, align_bytes(bytes, consts)?)),
+        Item::Space { size, .. } => reserve("`.space`", absolute(size, consts, "`.space` size")?),
+        Item::Fill { repeat, size, .. } => {
+            let repeat = absolute(repeat, consts, "`.fill` repeat")?;
+            let size = repeat
+                .checked_mul(fill_size(size, consts)?)
+                .ok_or_else(|| "`.fill` doesn't fit in the address space".to_string())?;
+            reserve("`.fill`", size)
+        }
+        Item::Org(offset, _) => {
+            let target = absolute(offset, consts, "`.org` offset")?;
+            match target.checked_sub(address) {
+                Some(size) => reserve("`.org`", size),
+                None => Err(format!("`.org` can't move back to {:#x}", target)),
+            }
+        }
+    }
+}
+
+/// Gives every item an offset from the start of its section and defines
+/// its label. The section will be aligned to its largest `.align`, so the
+/// padding comes out the same wherever it is placed.
+fn layout_section(
+    section: &Section,
+    index: usize,
+    consts: &Symbols,
+    sizes: &Sizes,
+    values: &mut Values,
+) -> Result<SectionLayout, Diagnostic> {
+    let mut align = SECTION_ALIGN;
+    for item in &section.items {
+        if let Item::Align { bytes, location, .. } = item {
+            let bytes = align_bytes(bytes, consts).map_err(|e| Diagnostic::at(location.line, e))?;
+            align = align.max(bytes);
+        }
+    }
+
+    let mut address = 0u32;
+    let mut addresses = vec![];
+    for (n, item) in section.items.iter().enumerate() {
+        let at = |e: String| Diagnostic::at(item.location().line, e);
+        match item {
+            Item::Text(_) | Item::Data(_) if section.nobits => {
+                return Err(at(format!("`{}` can only reserve space", section.name)));
+            }
+            _ => {}
+        }
+        addresses.push(address);
+        if let Some(label) = item_label(item) {
+            define(values, label, Value::at(index, address)).map_err(at)?;
+        }
+        let size = match sizes.get(&(index, n)) {
+            Some(&size) => size,
+            None => item_size(item, address, consts).map_err(at)?,
+        };
+        address = address
+            .checked_add(size)
+            .ok_or_else(|| at(format!("`{}` doesn't fit in the address space", section.name)))?;
+    }
+    Ok(SectionLayout {
+        align,
+        size: address,
+        addresses,
+    })
+}
+
+/// The target of every `%pcrel_hi`, by the section and offset of its
+/// `auipc`.
+fn pcrel_hi_targets<'a>(
+    file: &'a FullFile,
+    layouts: &[SectionLayout],
+) -> HashMap<(usize, u32), &'a Expr> {
+    let mut targets = HashMap::new();
+    for (index, (section, layout)) in file.sections.iter().zip(layouts).enumerate() {
+        for (item, &address) in section.items.iter().zip(&layout.addresses) {
+            if let Item::Text(Text {
+                imm_expr: Some(Expr::Reloc(Reloc::PcrelHi, target)),
+                ..
+            }) = item
+            {
+                targets.insert((index, address), target.as_ref());
+            }
+        }
+    }
+    targets
+}
+
+/// `%pcrel_lo(label)` is the low part of the offset computed by the
+/// `%pcrel_hi` at `label`, so that both use the `auipc`'s address.
+fn resolve_pcrel_lo(
+    label: &Expr,
+    here: &Value,
+    values: &Values,
+    pcrel_hi: &HashMap<(usize, u32), &Expr>,
+) -> Result<Resolved, String> {
+    let auipc = eval_value(label, &lookup_at(values, here))?;
+    let target = match &auipc {
+        Value {
+            target: Some(Target::Section(index)),
+            offset,
+        } => u32::try_from(*offset)
+            .ok()
+            .and_then(|offset| pcrel_hi.get(&(*index, offset))),
+        _ => None,
+    };
+    let target = target.ok_or_else(|| "no %pcrel_hi at the label of %pcrel_lo".to_string())?;
+    let value = eval_value(target, &lookup_at(values, &auipc))?;
+    match value.relative_to(&auipc) {
+        Resolved::Imm(offset) => Ok(Resolved::Imm(offset)),
+        // The linker finds the %pcrel_hi relocation at the auipc
+        Resolved::Relocation(..) => Ok(auipc.resolved()),
+    }
+}
+
+/// The immediate of an instruction at offset `pc` of section `index`, or a
+/// relocation for the linker when it depends on where things end up.
+fn resolve_instruction(
+    text: &Text,
+    index: usize,
+    pc: u32,
+    values: &Values,
+    pcrel_hi: &HashMap<(usize, u32), &Expr>,
+) -> Result<(InstructionData, Option<Relocation>), String> {
+    let mut instruction = text.instruction.clone();
+    let here = Value::at(index, pc);
+    let eval = |expr| eval_value(expr, &lookup_at(values, &here));
+    let label_dst = text.label_dst.clone().map(Expr::Sym);
+    let (reloc, resolved) = match label_dst.as_ref().or(text.imm_expr.as_ref()) {
+        None => {
+            // The parser only keeps literals that fit in 32 bits
+            if let Some(imm) = instruction.imm {
+                check_imm(&instruction, imm as i32 as i64)?;
+            }
+            return Ok((instruction, None));
+        }
+        Some(Expr::Reloc(Reloc::PcrelLo, label)) => (
+            Some(Reloc::PcrelLo),
+            resolve_pcrel_lo(label, &here, values, pcrel_hi)?,
+        ),
+        Some(Expr::Reloc(Reloc::PcrelHi, expr)) => {
+            (Some(Reloc::PcrelHi), eval(expr)?.relative_to(&here))
+        }
+        Some(Expr::Reloc(reloc, expr)) => (Some(*reloc), eval(expr)?.resolved()),
+        Some(expr) if is_pc_relative(&instruction) => (None, eval(expr)?.relative_to(&here)),
+        Some(expr) => (None, eval(expr)?.resolved()),
+    };
+
+    match resolved {
+        Resolved::Imm(value) => {
+            let imm = match reloc {
+                Some(Reloc::Hi | Reloc::PcrelHi) => hi(value),
+                Some(Reloc::Lo | Reloc::PcrelLo) => lo(value),
+                None => value,
+            };
+            check_imm(&instruction, imm)?;
+            instruction.imm = Some(imm as Imm);
+            Ok((instruction, None))
+        }
+        Resolved::Relocation(target, addend) => {
+            instruction.imm = Some(0);
+            let relocation = Relocation {
+                offset: pc,
+                field: imm_field(&instruction),
+                reloc,
+                target,
+                addend,
+            };
+            Ok((instruction, Some(relocation)))
+        }
+    }
+}
+
+/// The bytes of an item that layout gave `size` bytes at `address`, and the
+/// relocations they need.
+fn item_bytes(
+    item: &Item,
+    (index, section): (usize, &Section),
+    address: u32,
+    size: u32,
+    values: &Values,
+    pcrel_hi: &HashMap<(usize, u32), &Expr>,
+) -> Result<(Vec<u8>, Vec<Relocation>), String> {
+    let here = Value::at(index, address);
+    let size = size as usize;
+    let fill = |fill: &Option<Expr>| match fill {
+        Some(fill) => eval_value(fill, &lookup_at(values, &here))?
+            .constant()
+            .map(|n| (vec![n as u8; size], vec![])),
+        None => Ok((vec![0; size], vec![])),
+    };
+    match item {
+        Item::Text(text) => {
+            let (instruction, relocation) =
+                resolve_instruction(text, index, address, values, pcrel_hi)?;
+            if relocation.is_none() && size == 4 && is_pc_relative(&instruction) {
+                let offset = instruction.imm.unwrap_or(0) as i32 as i64;
+                if !relax::reaches(instruction.format() == Format::B, offset) {
+                    return Err(format!("offset {} is out of range", offset));
+                }
+            }
+            let mut bytes = vec![];
+            for instruction in relax::expand(instruction, size as u32) {
+                bytes.extend(generate_instruction(instruction)?.translate());
+            }
+            Ok((bytes, relocation.into_iter().collect()))
+        }
+        Item::Data(data) => {
+            let (bytes, mut relocations) = data_bytes(data, &lookup_at(values, &here))?;
+            for relocation in &mut relocations {
+                relocation.offset += address;
+            }
+            Ok((bytes, relocations))
+        }
+        Item::Align { fill: None, .. } if section.executable => {
+            // Get back to a word boundary first, then pad with nops
+            let mut bytes = vec![0; size % 4];
+            bytes.extend(nop().repeat(size / 4));
+            Ok((bytes, vec![]))
+        }
+        Item::Align { fill: value, .. } | Item::Space { fill: value, .. } => fill(value),
+        Item::Fill {
+            size: width, value, ..
+        } => {
+            let constant = |expr| eval_value(expr, &lookup_at(values, &here))?.constant();
+            let width = constant(width)? as usize;
+            let bytes = match width {
+                0 => vec![],
+                _ => num_to_bytes(constant(value)?, width)?.repeat(size / width),
+            };
+            Ok((bytes, vec![]))
+        }
+        Item::Label(..) | Item::Org(..) => fill(&None),
+    }
+}
+
+/// Whether each symbol named by `.globl` or `.local` is global, the last
+/// one winning.
+fn bindings(file: &FullFile, values: &Values) -> Result<HashMap<String, bool>, String> {
+    let global: HashMap<_, _> = file
+        .bindings
+        .iter()
+        .map(|(name, binding)| (name.clone(), *binding == Binding::Global))
+        .collect();
+    for (name, &is_global) in &global {
+        match values.get(name) {
+            None if !is_global => {
+                return Err(format!("`{}` is declared .local but never defined", name));
+            }
+            Some(Value {
+                target: Some(Target::Symbol(other)),
+                ..
+            }) if is_global => {
+                return Err(format!(
+                    "`{}` can't be global, it's defined in terms of `{}` from another file",
+                    name, other
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(global)
+}
+
+/// Every label and constant, and the symbols from other files that the
+/// relocations refer to.
+fn object_symbols(
+    file: &FullFile,
+    values: &Values,
+    sections: &[ObjectSection],
+) -> Result<Vec<Symbol>, String> {
+    let global = bindings(file, values)?;
+    // `.L` labels, and the numeric labels named like them, belong to the file
+    let is_local = |name: &str| name.starts_with(".L") && global.get(name) != Some(&true);
+    let mut symbols: Vec<Symbol> = values
+        .iter()
+        .filter(|(name, _)| !is_local(name))
+        .filter_map(|(name, value)| {
+            let definition = match &value.target {
+                None => Definition::Absolute(value.offset),
+                Some(Target::Section(index)) => Definition::Section {
+                    index: *index,
+                    offset: value.offset,
+                },
+                Some(Target::Symbol(_) | Target::Absolute) => return None,
+            };
+            Some(Symbol {
+                name: name.clone(),
+                global: global.get(name) == Some(&true),
+                definition: Some(definition),
+            })
+        })
+        .collect();
+
+    let mut undefined: Vec<&String> = sections
+        .iter()
+        .flat_map(|section| &section.relocations)
+        .filter_map(|relocation| match &relocation.target {
+            Target::Symbol(name) => Some(name),
+            _ => None,
+        })
+        .chain(global.iter().filter(|(_, &g)| g).map(|(name, _)| name))
+        .filter(|name| !values.contains_key(*name))
+        .collect();
+    undefined.sort();
+    undefined.dedup();
+    if let Some(name) = undefined.iter().find(|name| is_local(name)) {
+        return Err(format!("undefined symbol `{}`", name));
+    }
+    symbols.extend(undefined.into_iter().map(|name| Symbol {
+        name: name.clone(),
+        global: true,
+        definition: None,
+    }));
+    symbols.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(symbols)
+}
+
+/// How far each branch and jump whose target is in its own section has
+/// to go, by section and item.
+fn branch_distances(
+    file: &FullFile,
+    layouts: &[SectionLayout],
+    values: &Values,
+) -> Vec<((usize, usize), i64)> {
+    let mut distances = vec![];
+    for (index, (section, layout)) in file.sections.iter().zip(layouts).enumerate() {
+        for (n, (item, &address)) in section.items.iter().zip(&layout.addresses).enumerate() {
+            let text = match item {
+                // Only the branches and jumps the assembler knows can be rewritten
+                Item::Text(text) if text.instruction.custom.is_none() && is_pc_relative(&text.instruction) => text,
+                _ => continue,
+            };
+            let target = match (&text.label_dst, &text.imm_expr) {
+                (Some(label), _) => Expr::Sym(label.clone()),
+                (None, Some(expr)) => expr.clone(),
+                // A literal offset is what the programmer asked for
+                (None, None) => continue,
+            };
+            let here = Value::at(index, address);
+            let value = eval_value(&target, &lookup_at(values, &here));
+            if let Ok(value) = value {
+                if let Resolved::Imm(distance) = value.relative_to(&here) {
+                    distances.push(((index, n), distance));
+                }
+            }
+        }
+    }
+    distances
+}
+
+/// The constants that don't depend on labels, which are all the layout can
+/// use.
+fn layout_constants(file: &FullFile) -> Result<Symbols, Diagnostic> {
+    let mut consts = Symbols::new();
+    eval_constants(&file.consts, &mut consts, |expr, consts| {
+        expr.eval(&lookup(consts))
+    })?;
+    Ok(consts)
+}
+
+/// Where each item of each section starts, from the start of its section.
+pub fn item_offsets(file: &FullFile) -> Result<Vec<Vec<u32>>, Diagnostic> {
+    let (layouts, ..) = layout(file, &layout_constants(file)?, &Options::default())?;
+    Ok(layouts.into_iter().map(|layout| layout.addresses).collect())
+}
+
+/// Where each item of each section ends up when the file is linked on its
+/// own, followed by where the section ends.
+pub fn item_addresses(file: &FullFile, options: &Options) -> Result<Vec<Vec<u32>>, Diagnostic> {
+    let (layouts, ..) = layout(file, &layout_constants(file)?, options)?;
+    let (object, _) = object(file, options)?;
+    let bases = link::section_addresses(&[object], &options.link)?;
+    Ok(layouts
+        .into_iter()
+        .zip(&bases[0])
+        .map(|(layout, base)| {
+            let ends = layout.addresses.into_iter().chain([layout.size]);
+            ends.map(|offset| base + offset).collect()
+        })
+        .collect())
+}
+
+/// An instruction word of a linked program.
+#[derive(Debug, PartialEq)]
+pub struct Word {
+    /// The section and item of the file it came from. A relaxed branch or
+    /// jump has several words from one item.
+    pub section: usize,
+    pub item: usize,
+    pub address: u32,
+    pub word: u32,
+    pub mne: String,
+    pub format: Format,
+    pub syntax: Syntax,
+}
+
+/// Links `file` on its own, returning the image and every instruction word
+/// in it, by section and then address.
+pub fn linked_words(file: &FullFile, options: &Options) -> Result<(Image, Vec<Word>), Diagnostic> {
+    let addresses = item_addresses(file, options)?;
+    let (object, _) = object(file, options)?;
+    let image = link::link_image(&[object], &options.link)?;
+
+    let mut words = vec![];
+    for (index, (section, addresses)) in file.sections.iter().zip(&addresses).enumerate() {
+        for (n, item) in section.items.iter().enumerate() {
+            let text = match item {
+                Item::Text(text) => text,
+                _ => continue,
+            };
+            for address in (addresses[n]..addresses[n + 1]).step_by(4) {
+                let bytes = image
+                    .bytes_at(address, 4)
+                    .ok_or_else(|| format!("{:#010x} isn't in the image", address))?;
+                let word = u32::from_be_bytes(bytes.try_into().unwrap());
+                // Custom instructions don't decode, but all of a word is theirs
+                let (mne, format, syntax) = match decode(word).and_then(|data| isa::lookup(&data.mne)) {
+                    Some(opcode) => (opcode.mne, opcode.encoding.format, opcode.syntax),
+                    None => {
+                        let format = text.instruction.format();
+                        (text.instruction.mne.as_str(), format, Syntax::of(format))
+                    }
+                };
+                words.push(Word {
+                    section: index,
+                    item: n,
+                    address,
+                    word,
+                    mne: mne.to_string(),
+                    format,
+                    syntax,
+                });
+            }
+        }
+    }
+    Ok((image, words))
+}
+
+/// Lays out every section, growing the branches and jumps that can't reach
+/// their target until nothing changes. Growing one can push others out of
+/// reach, but sizes only go up, so this always ends.
+fn layout(
+    file: &FullFile,
+    consts: &Symbols,
+    options: &Options,
+) -> Result<(Vec<SectionLayout>, Values, Vec<Relaxation>), Diagnostic> {
+    let mut sizes = Sizes::new();
+    loop {
+        let mut values = Values::new();
+        let layouts = file
+            .sections
+            .iter()
+            .enumerate()
+            .map(|(index, section)| layout_section(section, index, consts, &sizes, &mut values))
+            .collect::<Result<Vec<_>, Diagnostic>>()?;
+        resolve_constants(file, &mut values)?;
+
+        let mut relaxed = vec![];
+        let mut first_line = None;
+        let mut grown = false;
+        for ((index, n), distance) in branch_distances(file, &layouts, &values) {
+            let text = match &file.sections[index].items[n] {
+                Item::Text(text) => text,
+                _ => unreachable!(),
+            };
+            let mne = &text.instruction.mne;
+            let size = sizes.get(&(index, n)).copied().unwrap_or(4);
+            let needed = size.max(relax::size_for(mne, distance));
+            if needed > size {
+                sizes.insert((index, n), needed);
+                grown = true;
+            }
+            if needed > 4 {
+                first_line.get_or_insert(text.location.line);
+                relaxed.push(Relaxation {
+                    section: file.sections[index].name.clone(),
+                    offset: layouts[index].addresses[n],
+                    mne: mne.clone(),
+                    distance,
+                    size: needed,
+                });
+            }
+        }
+        if !options.relax && !relaxed.is_empty() {
+            let sites: Vec<_> = relaxed.iter().map(|r| r.to_string()).collect();
+            let message = format!("out of range:\n{}", sites.join("\n"));
+            return Err(Diagnostic::at(first_line.unwrap_or(0), message));
+        }
+        if !grown {
+            return Ok((layouts, values, relaxed));
+        }
+    }
+}
+
+/// Assembles a file into a relocatable object. Each section is laid out
+/// from address zero, and anything that depends on where a section ends up
+/// or on a symbol from another file is left to the linker. Also returns
+/// the branches and jumps that were relaxed.
+pub fn object(file: &FullFile, options: &Options) -> Result<(Object, Vec<Relaxation>), Diagnostic> {
+    let (layouts, values, relaxed) = layout(file, &layout_constants(file)?, options)?;
+    let pcrel_hi = pcrel_hi_targets(file, &layouts);
+
+    let mut sections = vec![];
+    for (index, (section, layout)) in file.sections.iter().zip(&layouts).enumerate() {
+        let mut data = vec![];
+        let mut relocations = vec![];
+        let ends = layout.addresses.iter().skip(1).chain([&layout.size]);
+        for ((item, &address), &end) in section.items.iter().zip(&layout.addresses).zip(ends) {
+            if section.nobits {
+                break;
+            }
+            let size = end - address;
+            let bytes = item_bytes(item, (index, section), address, size, &values, &pcrel_hi)
+                .and_then(|(bytes, relocs)| {
+                    if options.pic {
+                        check_pic(&relocs)?;
+                    }
+                    Ok((bytes, relocs))
+                });
+            let (bytes, relocs) = bytes.map_err(|e| {
+                let message = match item {
+                    Item::Text(text) => format!("{}+{:#x} `{}`: {}", section.name, address, text.instruction.mne, e),
+                    _ => format!("{}+{:#x}: {}", section.name, address, e),
+                };
+                Diagnostic::at(item.location().line, message)
+            })?;
+            data.extend(bytes);
+            relocations.extend(relocs);
+        }
+        sections.push(ObjectSection {
+            name: section.name.clone(),
+            executable: section.executable,
+            nobits: section.nobits,
+            align: layout.align,
+            size: layout.size,
+            data,
+            relocations,
+        });
+    }
+    let symbols = object_symbols(file, &values, &sections)?;
+    Ok((Object::new(sections, symbols), relaxed))
+}
+
+/// Assembles a file and links it on its own. Sections that only reserve
+/// space, like .bss, come last and aren't part of the output.
+pub fn assemble(file: &FullFile, options: &Options) -> Result<(Vec<u8>, Vec<Relaxation>), Diagnostic> {
+    let (object, relaxed) = object(file, options)?;
+    check_defined(file, &object, &options.link)?;
+    Ok((link(&[object], &options.link)?, relaxed))
+}
+
+/// Fails on the first instruction that uses a symbol that neither the file
+/// nor the linker defines, which a file linked on its own can't have.
+fn check_defined(file: &FullFile, object: &Object, options: &link::Options) -> Result<(), Diagnostic> {
+    let mut undefined: Vec<&str> = object
+        .symbols
+        .iter()
+        .filter(|symbol| symbol.definition.is_none())
+        .map(|symbol| symbol.name.as_str())
+        .collect();
+    if !undefined.is_empty() {
+        let linked = link::linked_symbols(std::slice::from_ref(object), options)?;
+        undefined.retain(|name| !linked.contains_key(*name));
+    }
+    for item in file.sections.iter().flat_map(|section| &section.items) {
+        let (mut used, location): (Vec<&str>, _) = match item {
+            Item::Text(text) => (text.imm_expr.iter().flat_map(Expr::symbols).collect(), text.location),
+            Item::Data(data) => (data.exprs.iter().flat_map(Expr::symbols).collect(), data.location),
+            _ => continue,
+        };
+        if let Item::Text(Text { label_dst: Some(label), .. }) = item {
+            used.push(label);
+        }
+        if let Some(name) = used.into_iter().find(|name| undefined.contains(name)) {
+            return Err(Diagnostic::at(location.line, format!("undefined symbol `{}`", name)));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom::CustomInstructions;
+    use crate::linker_script::LinkerScript;
+    use crate::parser::{self, parse};
+
+    fn assemble_str(source: &str) -> Result<Vec<u8>, String> {
+        let binary = assemble(&parse(source).unwrap(), &Options::default())?;
+        Ok(binary.0)
+    }
+
+    fn words(binary: &[u8]) -> Vec<u32> {
+        binary
+            .chunks(4)
+            .map(|w| u32::from_be_bytes(w.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn equ_test() {
+        let binary = assemble_str(
+            "
+            .equ UART_BASE, 0x10000000
+            .set OFFSET, 4 * 2
+            lui t0, UART_BASE >> 12
+            addi t0, t0, OFFSET + 'A'
+            ",
+        )
+        .unwrap();
+        // lui t0, 0x10000; addi t0, t0, 73
+        assert_eq!(words(&binary), vec![0x100002B7, 0x04928293]);
+    }
+
+    #[test]
+    fn forward_reference_test() {
+        let binary = assemble_str(
+            "
+            start: addi a0, zero, LEN
+            beq a0, zero, end
+            addi a0, a0, -1
+            .equ LEN, end - start
+            end:
+            ",
+        )
+        .unwrap();
+        // LEN is 12, and the branch jumps 8 bytes forward
+        assert_eq!(words(&binary), vec![0x00C00513, 0x00050463, 0xFFF50513]);
+    }
+
+    #[test]
+    fn data_label_test() {
+        let binary = assemble_str(
+            "
+            lw a0, msg_end - msg(zero)
+            .data
+            msg: .word 1, 2
+            msg_end: .word msg_end - msg
+            ",
+        )
+        .unwrap();
+        // msg is at 4 and msg_end at 12
+        assert_eq!(words(&binary), vec![0x00802503, 1, 2, 8]);
+    }
+
+    #[test]
+    fn undefined_symbol_test() {
+        let err = assemble_str("addi a0, a0, MISSING + 1").unwrap_err();
+        assert!(err.contains("undefined symbol `MISSING`"));
+        let err = assemble_str(".equ A, B\n.equ B, A").unwrap_err();
+        assert!(err.contains("defined in terms of itself"));
+    }
+
+    #[test]
+    fn hi_lo_test() {
+        let binary = assemble_str(
+            "
+            .equ ADDR, 0x10000800
+            lui a0, %hi(ADDR)
+            addi a0, a0, %lo(ADDR)
+            ",
+        )
+        .unwrap();
+        // lui a0, 0x10001; addi a0, a0, -2048
+        assert_eq!(words(&binary), vec![0x10001537, 0x80050513]);
+    }
+
+    #[test]
+    fn imm_range_test() {
+        // addi a0, a0, -2048; addi a0, a0, 2047; sw a0, -2048(sp); lw a0, 2047(sp)
+        let binary = assemble_str(
+            "
+            addi a0, a0, -2048
+            addi a0, a0, 2047
+            sw a0, -2048(sp)
+            lw a0, 2047(sp)
+            ",
+        )
+        .unwrap();
+        assert_eq!(words(&binary), vec![0x80050513, 0x7FF50513, 0x80A12023, 0x7FF12503]);
+        // lui a0, 0xfffff; slli a0, a0, 31
+        let binary = assemble_str("lui a0, 0xFFFFF\nslli a0, a0, 31").unwrap();
+        assert_eq!(words(&binary), vec![0xFFFFF537, 0x01F51513]);
+
+        let err = |source| assemble_str(source).unwrap_err();
+        assert_eq!(err("addi a0, a0, -2049"), "line 1: .text+0x0 `addi`: immediate -2049 is out of range -2048..=2047");
+        assert!(err("addi a0, a0, 2048").contains("immediate 2048 is out of range"));
+        assert!(err("sw a0, -2049(sp)").contains("immediate -2049 is out of range"));
+        assert!(err("lw a0, 4096(sp)").contains("immediate 4096 is out of range"));
+        assert!(err("lui a0, 0x123456").contains("immediate 1193046 is out of range 0..=1048575"));
+        assert!(err("lui a0, -1").contains("immediate -1 is out of range"));
+        assert!(err("slli a0, a0, 32").contains("shift amount 32 is out of range 0..=31"));
+        assert!(err("slli a0, a0, -1").contains("shift amount -1 is out of range"));
+        assert!(err("addi a0, a0, 1 << 63").contains("is out of range"));
+        assert!(err(".equ BIG, 2048\naddi a0, a0, BIG").contains("immediate 2048 is out of range"));
+        assert!(err("addi a0, a0, end - start\nstart: .space 4096\nend:").contains("immediate 4096"));
+    }
+
+    #[test]
+    fn pic_test() {
+        let source = "
+            _start: la a0, msg
+            call f
+            f: jalr zero, 0(ra)
+            .data
+            msg: .word 7
+            size: .word size - msg
+            ";
+        let parser_options = parser::Options {
+            pic: true,
+            ..parser::Options::default()
+        };
+        let file = parser::parse_with(source, &parser_options).unwrap();
+        let assemble_at = |text_base| {
+            let options = Options {
+                pic: true,
+                link: link::Options {
+                    text_base: Some(text_base),
+                    ..link::Options::default()
+                },
+                ..Options::default()
+            };
+            assemble(&file, &options).map(|(binary, _)| binary)
+        };
+        let binary = assemble_at(0).unwrap();
+        assert_eq!(words(&binary[..20]), vec![0x00000517, 0x01450513, 0x00000097, 0x008080E7, 0x00008067]);
+        assert_eq!(assemble_at(0x1000).unwrap(), binary);
+
+        // Constants are the same wherever the program is, so they aren't
+        // loaded relative to the PC
+        let source = "
+            .equ UART, 0x10000000
+            .equ TX, UART + 4
+            la a0, UART
+            la a1, TX
+            la a2, 0x1000
+            lw a3, msg
+            sw a3, msg, t0
+            .data
+            msg: .word 7
+            ";
+        let file = parser::parse_with(source, &parser_options).unwrap();
+        let assemble_at = |text_base| {
+            let options = Options {
+                pic: true,
+                link: link::Options {
+                    text_base: Some(text_base),
+                    ..link::Options::default()
+                },
+                ..Options::default()
+            };
+            assemble(&file, &options).map(|(binary, _)| binary)
+        };
+        let binary = assemble_at(0).unwrap();
+        assert_eq!(
+            words(&binary[..40]),
+            vec![
+                0x10000537, 0x00050513, 0x100005B7, 0x00458593, 0x00001637, 0x00060613,
+                0x00000697, 0x0106A683, 0x00000297, 0x00D2A423,
+            ]
+        );
+        assert_eq!(assemble_at(0x1000).unwrap(), binary);
+
+        let pic = Options {
+            pic: true,
+            ..Options::default()
+        };
+        let err = assemble(&parse("lui a0, %hi(x)\nx:").unwrap(), &pic).unwrap_err();
+        assert_eq!(err.line, Some(1));
+        assert!(err.message.contains(".text+0x0 `lui`: `%hi` and `%lo` of an address aren't position-independent"));
+        let err = assemble(&parse(".data\n.word x\nx:").unwrap(), &pic).unwrap_err();
+        assert_eq!(err.line, Some(2));
+        assert!(err.message.contains(".data+0x0: an address in `.word` isn't position-independent"));
+        assert!(assemble(&parse("jal ra, x\nx:").unwrap(), &pic).is_ok());
+    }
+
+    #[test]
+    fn pcrel_test() {
+        let binary = assemble_str(
+            "
+            .equ TARGET, 0x1900
+            addi zero, zero, 0
+            .Lhi: auipc a0, %pcrel_hi(TARGET)
+            addi a0, a0, %pcrel_lo(.Lhi)
+            sw a1, %pcrel_lo(.Lhi)(a0)
+            ",
+        )
+        .unwrap();
+        // TARGET - .Lhi is 0x18FC, split into 2 << 12 and -0x704
+        assert_eq!(
+            words(&binary),
+            vec![0x00000013, 0x00002517, 0x8FC50513, 0x8EB52E23]
+        );
+    }
+
+    #[test]
+    fn pcrel_lo_without_hi_test() {
+        let err = assemble_str("start: addi a0, a0, %pcrel_lo(start)").unwrap_err();
+        assert!(err.contains("no %pcrel_hi"));
+    }
+
+    #[test]
+    fn layout_directives_test() {
+        let binary = assemble_str(
+            "
+            addi a0, a0, 1
+            .align 3
+            main: addi a0, a0, 2
+            .org 0x10
+            end: addi a0, a0, 3
+            .section .rodata
+            tbl: .word main, end
+            .balign 16, 0xFF
+            .bss
+            buf: .space 64
+            .data
+            ptr: .word buf
+            .space 2, 0xAB
+            ",
+        )
+        .unwrap();
+        assert_eq!(binary.len(), 0x36);
+        assert_eq!(
+            words(&binary[..0x34]),
+            vec![
+                // .text, padded with a nop and .org
+                0x00150513, 0x00000013, 0x00250513, 0x00000000, 0x00350513,
+                // .rodata is aligned to its .balign
+                0x00000000, 0x00000000, 0x00000000, 0x00000008, 0x00000010, 0xFFFFFFFF, 0xFFFFFFFF,
+                // .data, with .bss after it at 0x38
+                0x00000038,
+            ]
+        );
+        assert_eq!(binary[0x34..], [0xAB, 0xAB]);
+    }
+
+    #[test]
+    fn layout_errors_test() {
+        let err = assemble_str("addi a0, a0, 1\naddi a0, a0, 1\n.org 4").unwrap_err();
+        assert_eq!(err, "line 3: `.org` can't move back to 0x4");
+        let err = assemble_str("addi a0, a0, 1\n.balign 6").unwrap_err();
+        assert_eq!(err, "line 2: alignment 6 is not a power of two");
+        let err = assemble_str(".bss\naddi a0, a0, 1").unwrap_err();
+        assert_eq!(err, "line 2: `.bss` can only reserve space");
+        let err = assemble_str("a: addi a0, a0, 1\na: .word 0").unwrap_err();
+        assert_eq!(err, "line 2: symbol `a` is defined more than once");
+        let err = assemble_str(".space SIZE\nSIZE: .word 4").unwrap_err();
+        assert!(err.contains("must be a constant"));
+        let err = assemble_str(".space 0xFFFFFFF0").unwrap_err();
+        assert!(err.starts_with("line 1: `.space` of 0xfffffff0 bytes is larger than"));
+        let err = assemble_str("\n.fill 0x10000000, 8, 0").unwrap_err();
+        assert!(err.starts_with("line 2: `.fill` of 0x80000000 bytes"));
+        let err = assemble_str(".org 0x40000000").unwrap_err();
+        assert!(err.starts_with("line 1: `.org` of 0x40000000 bytes"));
+        let err = assemble_str("addi a0, a0, 1\n.balign 0x80000000").unwrap_err();
+        assert_eq!(err, "line 2: alignment of 0x80000000 bytes is larger than the 0x10000000 allowed");
+        let err = assemble_str(".data\n.byte 1\n.p2align 30").unwrap_err();
+        assert!(err.starts_with("line 3: alignment of 0x40000000 bytes"));
+        let err = assemble_str(".align 31").unwrap_err();
+        assert!(err.starts_with("line 1: alignment of 0x80000000 bytes"));
+        assert!(assemble_str(".align 32").unwrap_err().starts_with("line 1: alignment"));
+        let err = assemble_str("nop:\n.equ A, B\n.equ B, A + 1").unwrap_err();
+        assert_eq!(err, "line 2: `A` is defined in terms of itself");
+    }
+
+    const SCRIPT: &str = r#"
+        entry = "_start"
+
+        [memory]
+        ROM = { origin = 0x80000000, length = 0x20 }
+        RAM = { origin = 0x10000000, length = 0x100 }
+
+        [[sections]]
+        name = ".text*"
+        region = "ROM"
+
+        [[sections]]
+        name = ".data"
+        region = "RAM"
+        load = "ROM"
+
+        [[sections]]
+        name = ".bss"
+        region = "RAM"
+    "#;
+
+    fn assemble_with(source: &str, options: link::Options) -> Result<Vec<u8>, String> {
+        let options = Options {
+            link: options,
+            ..Options::default()
+        };
+        let binary = assemble(&parse(source).unwrap(), &options)?;
+        Ok(binary.0)
+    }
+
+    fn script() -> link::Options {
+        link::Options {
+            script: Some(LinkerScript::from_toml(SCRIPT).unwrap()),
+            ..link::Options::default()
+        }
+    }
+
+    #[test]
+    fn base_options_test() {
+        let options = link::Options {
+            text_base: Some(0x80000000),
+            data_base: Some(0x80000100),
+            script: None,
+        };
+        let binary = assemble_with("lui a0, %hi(x)\n.data\nx: .word .", options).unwrap();
+        assert_eq!(binary.len(), 0x104);
+        assert_eq!(words(&binary)[0] >> 12, 0x80000);
+        assert_eq!(words(&binary[0x100..]), vec![0x80000100]);
+
+        // .data in RAM far from .text in ROM is stored right after .text
+        let options = link::Options {
+            text_base: Some(0x80000000),
+            data_base: Some(0x10000000),
+            script: None,
+        };
+        let source = "
+            _start: lui a0, %hi(__data_load)
+            addi a0, a0, %lo(__data_load)
+            .data
+            x: .word x
+        ";
+        let binary = assemble_with(source, options).unwrap();
+        assert_eq!(words(&binary), vec![0x80000537, 0x00850513, 0x10000000]);
+        let options = link::Options {
+            data_base: Some(0x10000000),
+            ..link::Options::default()
+        };
+        let binary = assemble_with("addi zero, zero, 0\n.data\n.word 1", options).unwrap();
+        assert_eq!(words(&binary), vec![0x00000013, 1]);
+    }
+
+    #[test]
+    fn linker_script_test() {
+        let source = "
+            _start: lui a0, %hi(__data_load)
+            addi a0, a0, %lo(__data_load)
+            lui a1, %hi(x)
+            .data
+            x: .word __data_end
+            .bss
+            y: .space 8
+        ";
+        let binary = assemble_with(source, script()).unwrap();
+        // .data runs from RAM, but is stored in ROM right after .text
+        assert_eq!(binary.len(), 16);
+        let words = words(&binary);
+        assert_eq!(words[0] >> 12, 0x80000);
+        assert_eq!(words[1] >> 20, 12);
+        assert_eq!(words[2] >> 12, 0x10000);
+        assert_eq!(words[3], 0x10000004);
+    }
+
+    #[test]
+    fn linker_script_errors_test() {
+        let err = assemble_with("_start: .space 0x24", script()).unwrap_err();
+        assert!(err.contains("`.text` overflows region `ROM` by 4 bytes"));
+        let err = assemble_with("_start: .space 0x1c\n.data\n.word 1, 2", script()).unwrap_err();
+        assert!(err.contains("`.data` overflows region `ROM` by 4 bytes"));
+        let err = assemble_with(".space 4\n_start: .space 4", script()).unwrap_err();
+        assert!(err.contains("entry `_start` is at 0x80000004"));
+        let err = assemble_with("main: .space 4", script()).unwrap_err();
+        assert!(err.contains("undefined symbol `_start`"));
+        let options = link::Options {
+            script: Some(LinkerScript::from_toml(&SCRIPT.replace("load = \"ROM\"", "")).unwrap()),
+            ..link::Options::default()
+        };
+        let err = assemble_with("_start: .space 4\n.data\n.word 1", options).unwrap_err();
+        assert!(err.contains("`.text` is stored 0x6ffffffc bytes past `.data`, give one of them a `load` region"));
+
+        let options = link::Options {
+            text_base: Some(0x100),
+            data_base: Some(0x104),
+            script: None,
+        };
+        let err = assemble_with(".space 8\n.data\n.word 1", options).unwrap_err();
+        assert!(err.contains("sections `.text` and `.data` overlap"));
+    }
+
+    #[test]
+    fn object_test() {
+        let file = parse(
+            "
+            .globl main
+            main: jal ra, printf
+            beq a0, zero, main
+            lui a0, %hi(msg)
+            .data
+            msg: .word main + 4
+            ",
+        )
+        .unwrap();
+        let (object, _) = object(&file, &Options::default()).unwrap();
+        let text = &object.sections[0];
+        // The branch stays within .text, so only the others need the linker
+        assert_eq!(
+            text.relocations,
+            vec![
+                Relocation {
+                    offset: 0,
+                    field: Field::J,
+                    reloc: None,
+                    target: Target::Symbol("printf".to_string()),
+                    addend: 0,
+                },
+                Relocation {
+                    offset: 8,
+                    field: Field::U,
+                    reloc: Some(Reloc::Hi),
+                    target: Target::Section(1),
+                    addend: 0,
+                },
+            ]
+        );
+        assert_eq!(words(&text.data)[1], 0xFE050EE3);
+        assert_eq!(object.sections[1].relocations[0].addend, 4);
+        assert_eq!(
+            object.symbols,
+            vec![
+                Symbol {
+                    name: "main".to_string(),
+                    global: true,
+                    definition: Some(Definition::Section { index: 0, offset: 0 }),
+                },
+                Symbol {
+                    name: "msg".to_string(),
+                    global: false,
+                    definition: Some(Definition::Section { index: 1, offset: 0 }),
+                },
+                Symbol {
+                    name: "printf".to_string(),
+                    global: true,
+                    definition: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn object_errors_test() {
+        let err = assemble_str(".local helper
+jal ra, helper").unwrap_err();
+        assert!(err.contains("`helper` is declared .local but never defined"));
+        let err = assemble_str("a: .word a * 2").unwrap_err();
+        assert!(err.contains("an address can only have a constant added"));
+        let err = assemble_str("a: .byte a").unwrap_err();
+        assert!(err.contains("only `.word` can hold an address"));
+    }
+
+    #[test]
+    fn data_directives_test() {
+        let binary = assemble_str(
+            "
+            .data
+            .byte 0x12, -1
+            .half 0x3456
+            .fill 3, 2, 0xABCD
+            .dword -2
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            binary,
+            vec![
+                0x12, 0xFF, 0x34, 0x56, 0xAB, 0xCD, 0xAB, 0xCD, 0xAB, 0xCD, 0xFF, 0xFF, 0xFF, 0xFF,
+                0xFF, 0xFF, 0xFF, 0xFE,
+            ]
+        );
+        let err = assemble_str(".byte 256").unwrap_err();
+        assert!(err.contains("256 doesn't fit in 1 bytes"));
+        let err = assemble_str(".fill 1, 9, 0").unwrap_err();
+        assert!(err.contains("`.fill` size 9 is larger than 8"));
+    }
+
+    #[test]
+    fn relax_test() {
+        let file = parse("beq a0, zero, far\n.space 0x1000\nfar: jal ra, 0").unwrap();
+        let (binary, relaxed) = assemble(&file, &Options::default()).unwrap();
+        // The branch becomes a bne over a jal, which moves `far` along
+        assert_eq!(words(&binary[..8]), vec![0x00051463, 0x0040106F]);
+        assert_eq!(binary.len(), 0x100C);
+        assert_eq!(
+            relaxed,
+            vec![Relaxation {
+                section: ".text".to_string(),
+                offset: 0,
+                mne: "beq".to_string(),
+                distance: 0x1008,
+                size: 8,
+            }]
+        );
+
+        let binary = assemble_str("jal ra, far\n.space 0x100000\nfar: jal ra, 0").unwrap();
+        assert_eq!(words(&binary[..8]), vec![0x00100097, 0x008080E7]);
+
+        let options = Options {
+            relax: false,
+            ..Options::default()
+        };
+        let err = assemble(&file, &options).unwrap_err();
+        assert_eq!(err.line, Some(1));
+        assert!(err.message.contains(".text+0x0 `beq` to a target 4100 bytes away"));
+        let err = assemble_str("beq a0, zero, 0x2000").unwrap_err();
+        assert!(err.contains("offset 8192 is out of range"));
+    }
+
+    #[test]
+    fn local_labels_test() {
+        let binary =
+            assemble_str("1: beq a0, zero, 1f\naddi a0, a0, -1\njal zero, 1b\n1: .word 1b").unwrap();
+        assert_eq!(words(&binary), vec![0x00050663, 0xFFF50513, 0xFF9FF06F, 12]);
+
+        let file = parse(".globl main\nmain:\n1: jal zero, 1b\n.Lnext: jal zero, .Lnext").unwrap();
+        let (object, _) = object(&file, &Options::default()).unwrap();
+        let names: Vec<_> = object.symbols.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["main"]);
+        let err = assemble_str("jal ra, .Lmissing").unwrap_err();
+        assert!(err.contains("undefined symbol `.Lmissing`"));
+    }
+
+    #[test]
+    fn insn_test() {
+        // Each form encodes like the base instruction it spells out
+        let insn = assemble_str(
+            "loop: .insn r 0x33, 0, 0x20, a0, a1, a2
+            .insn i 0x13, 0, a0, a1, -5
+            .insn i 0x03, 2, a0, 8(sp)
+            .insn s 0x23, 2, a0, -4(sp)
+            .insn sb 0x63, 1, a0, a1, loop
+            .insn u 0x37, a0, 0x12345
+            .insn j 0x6f, ra, loop",
+        )
+        .unwrap();
+        let base = assemble_str(
+            "loop: sub a0, a1, a2
+            addi a0, a1, -5
+            lw a0, 8(sp)
+            sw a0, -4(sp)
+            bne a0, a1, loop
+            lui a0, 0x12345
+            jal ra, loop",
+        )
+        .unwrap();
+        assert_eq!(words(&insn), words(&base));
+
+        let binary = assemble_str(".insn r custom_0, 0, 1, a0, a1, a2").unwrap();
+        assert_eq!(words(&binary), vec![0x02C5850B]);
+        assert!(parse(".insn r 0x80, 0, 0, a0, a1, a2").is_err());
+    }
+
+    #[test]
+    fn custom_instructions_test() {
+        let options = parser::Options {
+            instructions: CustomInstructions::from_toml(
+                "[[instructions]]\nname = \"mac\"\nformat = \"r\"\nopcode = 0x0b\nfunct7 = 1",
+            )
+            .unwrap(),
+            ..parser::Options::default()
+        };
+        let file = parser::parse_with("mac a0, a1, a2", &options).unwrap();
+        let (binary, _) = assemble(&file, &Options::default()).unwrap();
+        assert_eq!(binary, assemble_str(".insn r custom_0, 0, 1, a0, a1, a2").unwrap());
+        assert!(parse("mac a0, a1, a2").is_err());
+    }
+}
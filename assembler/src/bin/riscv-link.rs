@@ -0,0 +1,60 @@
+use clap::Parser;
+use riscv_assembler::link::{link, parse_address, Options};
+use riscv_assembler::linker_script::LinkerScript;
+use riscv_assembler::object::Object;
+use std::fs;
+use std::process;
+
+/// Links objects written by `riscv-assembler -c` into a binary
+#[derive(Parser)]
+#[clap(author, version, long_about = None)]
+struct Cli {
+    #[clap(value_parser, required = true, help = "Object files, linked in this order")]
+    objects: Vec<String>,
+
+    #[clap(short = 'o', long, value_parser, help = "Output binary executable")]
+    output: String,
+
+    #[clap(long, value_parser = parse_address, help = "Address of .text")]
+    text_base: Option<u32>,
+
+    #[clap(long, value_parser = parse_address, help = "Address of .data")]
+    data_base: Option<u32>,
+
+    #[clap(short = 'T', long, value_parser, help = "TOML linker script")]
+    script: Option<String>,
+}
+
+fn fail(path: &str, e: String) -> ! {
+    eprintln!("{}: {}", path, e);
+    process::exit(1);
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let objects: Vec<Object> = cli
+        .objects
+        .iter()
+        .map(|path| {
+            fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|s| Object::from_json(&s))
+                .unwrap_or_else(|e| fail(path, e))
+        })
+        .collect();
+    let script = cli
+        .script
+        .as_ref()
+        .map(|path| LinkerScript::read(path).unwrap_or_else(|e| fail(path, e)));
+    let options = Options {
+        text_base: cli.text_base,
+        data_base: cli.data_base,
+        script,
+    };
+
+    match link(&objects, &options) {
+        Ok(binary) => fs::write(&cli.output, binary).unwrap_or_else(|e| fail(&cli.output, e.to_string())),
+        Err(e) => fail("riscv-link", e),
+    }
+}
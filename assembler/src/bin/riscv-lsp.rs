@@ -0,0 +1,12 @@
+use std::io;
+use std::process;
+
+/// A language server for RISC-V assembly, spoken over stdin and stdout
+fn main() {
+    let stdin = io::stdin();
+    let code = riscv_assembler::lsp::run(stdin.lock(), io::stdout().lock()).unwrap_or_else(|e| {
+        eprintln!("riscv-lsp: {}", e);
+        1
+    });
+    process::exit(code);
+}
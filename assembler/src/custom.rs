@@ -0,0 +1,142 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+
+use crate::instructions::isa::lookup;
+use crate::instructions::{Encoding, Format};
+
+/// Instructions a CPU adds to the base ISA, written in TOML. Each one is
+/// used like a built-in instruction of its format:
+///
+/// ```toml
+/// [[instructions]]
+/// name = "mac"
+/// format = "r"
+/// opcode = 0x0b
+/// funct3 = 0
+/// funct7 = 1
+/// ```
+#[derive(Debug, Deserialize, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct CustomInstructions {
+    #[serde(default)]
+    pub instructions: Vec<Definition>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Definition {
+    pub name: String,
+    pub format: Format,
+    pub opcode: u32,
+    #[serde(default)]
+    pub funct3: u32,
+    #[serde(default)]
+    pub funct7: u32,
+}
+
+impl Definition {
+    pub fn encoding(&self) -> Encoding {
+        Encoding {
+            format: self.format,
+            opcode: self.opcode,
+            funct3: self.funct3,
+            funct7: self.funct7,
+        }
+    }
+}
+
+/// Checks that the fixed fields fit and that the format has them.
+pub fn check_fields(custom: &Encoding) -> Result<(), String> {
+    let has_funct3 = !matches!(custom.format, Format::U | Format::J);
+    let has_funct7 = custom.format == Format::R;
+    if custom.opcode >= 1 << 7 {
+        return Err(format!("opcode {:#x} doesn't fit in 7 bits", custom.opcode));
+    }
+    match (custom.funct3, custom.funct7) {
+        (funct3, _) if funct3 >= 1 << 3 => Err(format!("funct3 {} doesn't fit in 3 bits", funct3)),
+        (_, funct7) if funct7 >= 1 << 7 => Err(format!("funct7 {} doesn't fit in 7 bits", funct7)),
+        (funct3, _) if funct3 != 0 && !has_funct3 => {
+            Err(format!("format {:?} has no funct3", custom.format))
+        }
+        (_, funct7) if funct7 != 0 && !has_funct7 => {
+            Err(format!("format {:?} has no funct7", custom.format))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl CustomInstructions {
+    pub fn from_toml(s: &str) -> Result<CustomInstructions, String> {
+        let set: CustomInstructions = toml::from_str(s).map_err(|e| e.to_string())?;
+        let mut names = HashSet::new();
+        for definition in &set.instructions {
+            let name = definition.name.to_lowercase();
+            if lookup(&name).is_some() {
+                return Err(format!("`{}` is already an instruction", definition.name));
+            }
+            if !names.insert(name) {
+                return Err(format!("`{}` is defined more than once", definition.name));
+            }
+            check_fields(&definition.encoding()).map_err(|e| format!("`{}`: {}", definition.name, e))?;
+        }
+        Ok(set)
+    }
+
+    pub fn read(path: &str) -> Result<CustomInstructions, String> {
+        let set = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        CustomInstructions::from_toml(&set)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Definition> {
+        self.instructions
+            .iter()
+            .find(|definition| definition.name.eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_toml_test() {
+        let set = CustomInstructions::from_toml(
+            r#"
+            [[instructions]]
+            name = "mac"
+            format = "r"
+            opcode = 0x0b
+            funct7 = 1
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            set.get("MAC").unwrap().encoding(),
+            Encoding {
+                format: Format::R,
+                opcode: 0x0b,
+                funct3: 0,
+                funct7: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn errors_test() {
+        let err = CustomInstructions::from_toml(
+            "[[instructions]]\nname = \"add\"\nformat = \"r\"\nopcode = 0x0b",
+        )
+        .unwrap_err();
+        assert_eq!(err, "`add` is already an instruction");
+        let err = CustomInstructions::from_toml(
+            "[[instructions]]\nname = \"ld\"\nformat = \"u\"\nopcode = 0x2b\nfunct3 = 1",
+        )
+        .unwrap_err();
+        assert_eq!(err, "`ld`: format U has no funct3");
+        let err = CustomInstructions::from_toml(
+            "[[instructions]]\nname = \"ld\"\nformat = \"i\"\nopcode = 0x80",
+        )
+        .unwrap_err();
+        assert_eq!(err, "`ld`: opcode 0x80 doesn't fit in 7 bits");
+    }
+}
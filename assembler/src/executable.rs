@@ -0,0 +1,163 @@
+use riscv_executable::{Executable, Section, Symbol};
+
+use crate::assembler::{self, object};
+use crate::link::link_sections;
+use crate::parser::{FullFile, Item};
+use crate::relax::Relaxation;
+use crate::Diagnostic;
+
+/// `rv32i`, and `_xcustom` after it if `file` has instructions from `.insn`
+/// or a file of custom instructions, which only some CPUs will run.
+fn isa(file: &FullFile) -> String {
+    let custom = file.sections.iter().flat_map(|section| &section.items).any(|item| match item {
+        Item::Text(text) => text.instruction.custom.is_some(),
+        _ => false,
+    });
+    match custom {
+        true => "rv32i_xcustom".to_string(),
+        false => "rv32i".to_string(),
+    }
+}
+
+/// Assembles `file` and links it on its own into an executable, with a
+/// symbol table of its labels unless `strip`. Each stored section is loaded
+/// at its own address, which is where it runs unless the linker script gives
+/// it a `load` region, so sections can be far apart and the entry anywhere.
+/// Also returns the branches and jumps that were relaxed.
+pub fn executable(
+    file: &FullFile,
+    options: &assembler::Options,
+    strip: bool,
+) -> Result<(Executable, Vec<Relaxation>), Diagnostic> {
+    let (object, relaxed) = object(file, options)?;
+    let linked = link_sections(&[object], &options.link)?;
+    let sections = linked
+        .sections
+        .into_iter()
+        .filter(|(section, _)| section.size > 0)
+        .map(|(section, data)| {
+            let (address, data) = match section.nobits {
+                true => (section.start, vec![]),
+                false => (section.load, data),
+            };
+            Section {
+                address,
+                size: section.size,
+                executable: section.executable,
+                nobits: section.nobits,
+                data,
+            }
+        })
+        .collect();
+    let symbols = linked
+        .labels
+        .iter()
+        .map(|(name, value)| Symbol {
+            name: name.clone(),
+            value: *value,
+        })
+        .collect();
+    let executable = Executable {
+        isa: isa(file),
+        entry: linked.entry,
+        sections,
+        symbols: (!strip).then_some(symbols),
+    };
+    Ok((executable, relaxed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link;
+    use crate::linker_script::LinkerScript;
+    use crate::parser::parse;
+
+    #[test]
+    fn executable_test() {
+        let source = "
+        .data
+count:  .word 3
+        .bss
+buffer: .space 8
+        .text
+        addi a0, zero, 1
+_start: lui a0, %hi(count)
+.Lloop: jal zero, .Lloop
+";
+        let file = parse(source).unwrap();
+        let (executable, _) = executable(&file, &assembler::Options::default(), false).unwrap();
+        assert_eq!(executable.isa, "rv32i");
+        assert_eq!(executable.entry, 4);
+        let sections: Vec<_> = executable
+            .sections
+            .iter()
+            .map(|s| (s.address, s.size, s.executable, s.nobits, s.data.len()))
+            .collect();
+        assert_eq!(sections, vec![(0, 12, true, false, 12), (12, 4, false, false, 4), (16, 8, false, true, 0)]);
+        assert_eq!(executable.sections[1].data, vec![0, 0, 0, 3]);
+        let symbols: Vec<_> = executable
+            .symbols
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|s| (s.name.as_str(), s.value))
+            .collect();
+        assert_eq!(symbols, vec![("_start", 4), ("count", 12), ("buffer", 16)]);
+
+        let bytes = executable.to_bytes();
+        assert_eq!(Executable::from_bytes(&bytes), Ok(executable));
+        let (stripped, _) = super::executable(&file, &assembler::Options::default(), true).unwrap();
+        assert_eq!(stripped.symbols, None);
+    }
+
+    #[test]
+    fn far_sections_test() {
+        let source = "
+        .data
+x:      .word 7
+        .text
+        lui a0, %hi(x)
+";
+        let file = parse(source).unwrap();
+        let options = assembler::Options {
+            link: link::Options {
+                text_base: Some(0x80000000),
+                data_base: Some(0x10000000),
+                script: None,
+            },
+            ..assembler::Options::default()
+        };
+        let (executable, _) = executable(&file, &options, false).unwrap();
+        assert_eq!(executable.entry, 0x10000000);
+        let sections: Vec<_> = executable.sections.iter().map(|s| (s.address, s.data.clone())).collect();
+        assert_eq!(sections, vec![(0x80000000, vec![0x10, 0, 0x05, 0x37]), (0x10000000, vec![0, 0, 0, 7])]);
+    }
+
+    #[test]
+    fn script_entry_test() {
+        let script = r#"
+            entry = "main"
+
+            [memory]
+            ROM = { origin = 0x80000000, length = 0x100 }
+
+            [[sections]]
+            name = ".text"
+            region = "ROM"
+        "#;
+        let file = parse("f: jalr zero, 0(ra)
+main: jal ra, f").unwrap();
+        let options = assembler::Options {
+            link: link::Options {
+                script: Some(LinkerScript::from_toml(script).unwrap()),
+                ..link::Options::default()
+            },
+            ..assembler::Options::default()
+        };
+        let (executable, _) = executable(&file, &options, false).unwrap();
+        assert_eq!(executable.entry, 0x80000004);
+        assert_eq!(executable.sections[0].address, 0x80000000);
+        assert!(crate::assembler::assemble(&file, &options).is_err());
+    }
+}
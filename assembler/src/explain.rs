@@ -0,0 +1,150 @@
+use crate::assembler::{self, linked_words};
+use crate::instructions::isa::{bit_fields, immediate, Syntax};
+use crate::instructions::Format;
+use crate::parser::{FullFile, Item};
+use crate::Diagnostic;
+
+/// The fields of `word` as a table, one column per field from the top bit
+/// down, with the bits it spans, its name, and its value in binary and hex:
+///
+/// ```text
+/// |31     |30:25    |24:20|19:15|14:12 |11:8    |7      |6:0    |
+/// |imm[12]|imm[10:5]|rs2  |rs1  |funct3|imm[4:1]|imm[11]|opcode |
+/// |0      |000000   |00000|01010|000   |0100    |0      |1100011|
+/// |0x0    |0x0      |0x0  |0xa  |0x0   |0x4     |0x0    |0x63   |
+/// ```
+pub fn diagram(word: u32, format: Format, syntax: Syntax) -> String {
+    let columns: Vec<[String; 4]> = bit_fields(format, syntax)
+        .iter()
+        .map(|field| {
+            let value = field.value(word);
+            let bits = match field.width() {
+                1 => field.low.to_string(),
+                _ => format!("{}:{}", field.high, field.low),
+            };
+            [
+                bits,
+                field.name.to_string(),
+                format!("{:0width$b}", value, width = field.width() as usize),
+                format!("{:#x}", value),
+            ]
+        })
+        .collect();
+    let mut rows = vec![String::new(); 4];
+    for column in &columns {
+        let width = column.iter().map(String::len).max().unwrap();
+        for (row, cell) in rows.iter_mut().zip(column) {
+            row.push_str(&format!("|{:width$}", cell, width = width));
+        }
+    }
+    rows.iter().map(|row| format!("{}|\n", row)).collect()
+}
+
+/// Every instruction of `file`, linked on its own, as its address, word and
+/// source line followed by the `diagram` of its fields and the immediate
+/// they add up to. `source` is the text `file` was parsed from, and lines
+/// that came from preprocessing are named by the file and line they came
+/// from. A branch or jump that was relaxed shows each instruction it became.
+pub fn explain(file: &FullFile, source: &str, options: &assembler::Options) -> Result<String, Diagnostic> {
+    let (_, words) = linked_words(file, options)?;
+    let lines: Vec<&str> = source.lines().collect();
+
+    let mut out = String::new();
+    let mut previous = None;
+    for word in &words {
+        let line = match &file.sections[word.section].items[word.item] {
+            Item::Text(text) => text.location.line,
+            _ => unreachable!(),
+        };
+        let first = previous != Some((word.section, word.item));
+        previous = Some((word.section, word.item));
+        let at = match file.origin(line) {
+            (Some(origin), origin_line) => format!("{}: line {}", origin, origin_line),
+            (None, _) => format!("line {}", line),
+        };
+        let from = match (first, line) {
+            (_, 0) => "not in the source".to_string(),
+            (true, _) => format!("{}: {}", at, lines.get(line - 1).map_or("", |l| l.trim())),
+            (false, _) => format!("relaxed from {}", at),
+        };
+        out.push_str(&format!("{:08x}: {:08x}  {}  ({})\n", word.address, word.word, word.mne, from));
+        out.push_str(&diagram(word.word, word.format, word.syntax));
+        match (word.format, word.syntax) {
+            (Format::R, _) | (_, Syntax::Shift) => {}
+            (Format::U, _) => out.push_str(&format!("imm = {:#x}\n", immediate(word.format, word.word))),
+            _ => out.push_str(&format!("imm = {}\n", immediate(word.format, word.word) as i32)),
+        }
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::preprocessor::{self, preprocess_with};
+    use std::path::Path;
+
+    #[test]
+    fn diagram_test() {
+        // beq a0, zero, 8
+        assert_eq!(
+            diagram(0x00050463, Format::B, Syntax::Branch),
+            "\
+|31     |30:25    |24:20|19:15|14:12 |11:8    |7      |6:0    |
+|imm[12]|imm[10:5]|rs2  |rs1  |funct3|imm[4:1]|imm[11]|opcode |
+|0      |000000   |00000|01010|000   |0100    |0      |1100011|
+|0x0    |0x0      |0x0  |0xa  |0x0   |0x4     |0x0    |0x63   |
+"
+        );
+        // srai a0, a0, 3
+        assert_eq!(
+            diagram(0x40355513, Format::I, Syntax::Shift).lines().nth(1),
+            Some("|funct7 |shamt|rs1  |funct3|rd   |opcode |")
+        );
+    }
+
+    #[test]
+    fn explain_test() {
+        let source = "\
+_start: addi a0, zero, -1
+        .data
+        .word 5
+        .text
+        jal ra, _start
+";
+        let out = explain(&parse(source).unwrap(), source, &assembler::Options::default()).unwrap();
+        let headers: Vec<_> = out.lines().filter(|line| !line.starts_with(['|', 'i']) && !line.is_empty()).collect();
+        assert_eq!(
+            headers,
+            vec![
+                "00000000: fff00513  addi  (line 1: _start: addi a0, zero, -1)",
+                "00000004: ffdff0ef  jal  (line 5: jal ra, _start)",
+            ]
+        );
+        assert!(out.contains("|imm[20]|imm[10:1] |imm[11]|imm[19:12]|rd   |opcode |\n"));
+        assert!(out.contains("imm = -4\n"));
+    }
+
+    #[test]
+    fn included_test() {
+        let read = |path: &Path| match path.to_str() {
+            Some("inc.h") => Ok("addi a1, zero, 2\n".to_string()),
+            _ => Err("missing".to_string()),
+        };
+        let source = "#define ONE 1\naddi a0, zero, ONE\n#include \"inc.h\"\n";
+        let preprocessed = preprocess_with(source, "main.S", &preprocessor::Options::default(), &read).unwrap();
+        let mut file = parse(&preprocessed.source).unwrap();
+        preprocessed.add_origins(&mut file);
+        let out = explain(&file, &preprocessed.source, &assembler::Options::default()).unwrap();
+        let headers: Vec<_> = out.lines().filter(|line| line.starts_with('0')).collect();
+        assert_eq!(
+            headers,
+            vec![
+                "00000000: 00100513  addi  (main.S: line 2: addi a0, zero, 1)",
+                "00000004: 00200593  addi  (inc.h: line 1: addi a1, zero, 2)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, take_while, take_while1, take_while_m_n};
+use nom::character::complete::{char, digit1, hex_digit1, none_of, one_of, satisfy, space0};
+use nom::combinator::{map, map_res, not, recognize};
+use nom::error::Error;
+use nom::multi::fold_many0;
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
+use nom::IResult;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl BinOp {
+    pub fn apply(self, l: i64, r: i64) -> Result<i64, String> {
+        match self {
+            BinOp::Add => Ok(l.wrapping_add(r)),
+            BinOp::Sub => Ok(l.wrapping_sub(r)),
+            BinOp::Mul => Ok(l.wrapping_mul(r)),
+            BinOp::Div | BinOp::Rem if r == 0 => Err("division by zero".to_string()),
+            BinOp::Div => l.checked_div(r).ok_or_else(|| "overflow".to_string()),
+            BinOp::Rem => l.checked_rem(r).ok_or_else(|| "overflow".to_string()),
+            BinOp::Shl => u32::try_from(r)
+                .ok()
+                .and_then(|r| l.checked_shl(r))
+                .ok_or_else(|| format!("invalid shift amount {}", r)),
+            BinOp::Shr => u32::try_from(r)
+                .ok()
+                .and_then(|r| l.checked_shr(r))
+                .ok_or_else(|| format!("invalid shift amount {}", r)),
+            BinOp::And => Ok(l & r),
+            BinOp::Or => Ok(l | r),
+            BinOp::Xor => Ok(l ^ r),
+            BinOp::Eq => Ok((l == r) as i64),
+            BinOp::Ne => Ok((l != r) as i64),
+            BinOp::Lt => Ok((l < r) as i64),
+            BinOp::Le => Ok((l <= r) as i64),
+            BinOp::Gt => Ok((l > r) as i64),
+            BinOp::Ge => Ok((l >= r) as i64),
+        }
+    }
+}
+
+/// Relocation operators that split an address between a `lui`/`auipc` and
+/// the 12 bit immediate of the instruction after it.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Reloc {
+    Hi,
+    Lo,
+    PcrelHi,
+    PcrelLo,
+}
+
+/// An integer expression as written in an operand or directive. Symbols are
+/// resolved once labels have been laid out, so forward references are fine.
+/// The symbol `.` is the address of the current instruction.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Num(i64),
+    Sym(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Bin(BinOp, Box<Expr>, Box<Expr>),
+    Reloc(Reloc, Box<Expr>),
+}
+
+/// Upper 20 bits of `value`, rounded up when bit 11 is set because the low
+/// part is sign extended.
+pub fn hi(value: i64) -> i64 {
+    ((value + 0x800) >> 12) & 0xFFFFF
+}
+
+/// Low 12 bits of `value` as a signed number, so that `(hi << 12) + lo`
+/// gives `value` back.
+pub fn lo(value: i64) -> i64 {
+    ((value & 0xFFF) ^ 0x800) - 0x800
+}
+
+impl Expr {
+    /// Evaluates the expression, looking up symbols with `lookup`.
+    pub fn eval<F>(&self, lookup: &F) -> Result<i64, String>
+    where
+        F: Fn(&str) -> Result<i64, String>,
+    {
+        match self {
+            Expr::Num(n) => Ok(*n),
+            Expr::Sym(s) => lookup(s),
+            Expr::Neg(e) => Ok(e.eval(lookup)?.wrapping_neg()),
+            Expr::Not(e) => Ok(!e.eval(lookup)?),
+            Expr::Bin(op, l, r) => op.apply(l.eval(lookup)?, r.eval(lookup)?),
+            Expr::Reloc(Reloc::Hi, e) => Ok(hi(e.eval(lookup)?)),
+            Expr::Reloc(Reloc::Lo, e) => Ok(lo(e.eval(lookup)?)),
+            Expr::Reloc(Reloc::PcrelHi, e) => Ok(hi(e.eval(lookup)? - lookup(".")?)),
+            // Needs the %pcrel_hi it points at, which only the assembler knows
+            Expr::Reloc(Reloc::PcrelLo, _) => {
+                Err("%pcrel_lo must be the whole operand".to_string())
+            }
+        }
+    }
+
+    /// Evaluates the expression if it doesn't reference any symbols.
+    pub fn constant(&self) -> Option<i64> {
+        self.eval(&|s: &str| Err(s.to_string())).ok()
+    }
+
+    /// Every symbol the expression refers to, in order of appearance.
+    pub fn symbols(&self) -> Vec<&str> {
+        match self {
+            Expr::Num(_) => vec![],
+            Expr::Sym(s) => vec![s.as_str()],
+            Expr::Reloc(Reloc::PcrelHi, e) => {
+                let mut syms = e.symbols();
+                syms.push(".");
+                syms
+            }
+            Expr::Neg(e) | Expr::Not(e) | Expr::Reloc(_, e) => e.symbols(),
+            Expr::Bin(_, l, r) => {
+                let mut syms = l.symbols();
+                syms.extend(r.symbols());
+                syms
+            }
+        }
+    }
+
+    /// The names of the symbols the expression refers to, for renaming.
+    pub fn symbols_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            Expr::Num(_) => vec![],
+            Expr::Sym(s) => vec![s],
+            Expr::Neg(e) | Expr::Not(e) | Expr::Reloc(_, e) => e.symbols_mut(),
+            Expr::Bin(_, l, r) => {
+                let mut syms = l.symbols_mut();
+                syms.extend(r.symbols_mut());
+                syms
+            }
+        }
+    }
+}
+
+pub fn parse_symbol(i: &str) -> IResult<&str, &str, Error<&str>> {
+    recognize(pair(
+        take_while1(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.' || c == '$'),
+        take_while(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'),
+    ))(i)
+}
+
+/// A reference to a numeric local label, like `1b` or `1f`. `0b1` is
+/// still a binary number, because a digit follows the `b`.
+fn parse_local_ref(i: &str) -> IResult<&str, &str, Error<&str>> {
+    recognize(terminated(
+        pair(digit1, one_of("bf")),
+        not(satisfy(|c: char| {
+            c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+        })),
+    ))(i)
+}
+
+fn parse_number(i: &str) -> IResult<&str, i64, Error<&str>> {
+    alt((
+        map_res(preceded(alt((tag("0x"), tag("0X"))), hex_digit1), |s| {
+            u64::from_str_radix(s, 16).map(|n| n as i64)
+        }),
+        map_res(
+            preceded(
+                alt((tag("0b"), tag("0B"))),
+                take_while1(|c| c == '0' || c == '1'),
+            ),
+            |s| u64::from_str_radix(s, 2).map(|n| n as i64),
+        ),
+        map_res(digit1, |s: &str| s.parse::<u64>().map(|n| n as i64)),
+    ))(i)
+}
+
+/// A C escape sequence, as the byte it stands for: `\n \t \r \0 \\ \' \"`
+/// and the rest of the single letter ones, `\xNN` in hex or `\NNN` in octal.
+pub fn parse_escape(i: &str) -> IResult<&str, u8, Error<&str>> {
+    let hex = preceded(
+        char('x'),
+        map_res(take_while_m_n(1, 2, |c: char| c.is_ascii_hexdigit()), |s| {
+            u8::from_str_radix(s, 16)
+        }),
+    );
+    let octal = map_res(take_while_m_n(1, 3, |c: char| c.is_digit(8)), |s| {
+        u8::from_str_radix(s, 8)
+    });
+    preceded(
+        char('\\'),
+        alt((
+            hex,
+            octal,
+            map(one_of("ntrabfv"), |c| match c {
+                'n' => b'\n',
+                't' => b'\t',
+                'r' => b'\r',
+                'a' => 0x07,
+                'b' => 0x08,
+                'f' => 0x0C,
+                _ => 0x0B,
+            }),
+            map(one_of("\\'\"?"), |c| c as u8),
+        )),
+    )(i)
+}
+
+fn parse_char(i: &str) -> IResult<&str, i64, Error<&str>> {
+    delimited(
+        char('\''),
+        alt((map(parse_escape, i64::from), map(none_of("\\'"), |c| c as i64))),
+        char('\''),
+    )(i)
+}
+
+fn parse_reloc(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    let op = preceded(
+        char('%'),
+        alt((
+            map(tag_no_case("pcrel_hi"), |_| Reloc::PcrelHi),
+            map(tag_no_case("pcrel_lo"), |_| Reloc::PcrelLo),
+            map(tag_no_case("hi"), |_| Reloc::Hi),
+            map(tag_no_case("lo"), |_| Reloc::Lo),
+        )),
+    );
+    let arg = delimited(
+        preceded(space0, char('(')),
+        parse_expr,
+        preceded(space0, char(')')),
+    );
+    map(pair(op, arg), |(reloc, e)| Expr::Reloc(reloc, Box::new(e)))(i)
+}
+
+fn parse_primary(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    preceded(
+        space0,
+        alt((
+            parse_reloc,
+            map(parse_local_ref, |s| Expr::Sym(s.to_string())),
+            map(parse_number, Expr::Num),
+            map(parse_char, Expr::Num),
+            map(parse_symbol, |s| Expr::Sym(s.to_string())),
+            delimited(char('('), parse_expr, preceded(space0, char(')'))),
+        )),
+    )(i)
+}
+
+fn parse_unary(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    alt((
+        map(preceded(preceded(space0, char('-')), parse_unary), |e| {
+            Expr::Neg(Box::new(e))
+        }),
+        map(preceded(preceded(space0, char('~')), parse_unary), |e| {
+            Expr::Not(Box::new(e))
+        }),
+        preceded(preceded(space0, char('+')), parse_unary),
+        parse_primary,
+    ))(i)
+}
+
+/// Parses one level of left associative binary operators.
+fn binary_level<'a, O, N>(
+    i: &'a str,
+    ops: O,
+    mut next: N,
+) -> IResult<&'a str, Expr, Error<&'a str>>
+where
+    O: FnMut(&'a str) -> IResult<&'a str, BinOp, Error<&'a str>> + Copy,
+    N: FnMut(&'a str) -> IResult<&'a str, Expr, Error<&'a str>> + Copy,
+{
+    let (i, first) = next(i)?;
+    fold_many0(
+        tuple((preceded(space0, ops), next)),
+        move || first.clone(),
+        |acc, (op, rhs)| Expr::Bin(op, Box::new(acc), Box::new(rhs)),
+    )(i)
+}
+
+fn parse_mul(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    binary_level(
+        i,
+        |i| {
+            alt((
+                map(char('*'), |_| BinOp::Mul),
+                map(char('/'), |_| BinOp::Div),
+                map(char('%'), |_| BinOp::Rem),
+            ))(i)
+        },
+        parse_unary,
+    )
+}
+
+fn parse_add(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    binary_level(
+        i,
+        |i| {
+            alt((
+                map(char('+'), |_| BinOp::Add),
+                map(char('-'), |_| BinOp::Sub),
+            ))(i)
+        },
+        parse_mul,
+    )
+}
+
+fn parse_shift(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    binary_level(
+        i,
+        |i| {
+            alt((
+                map(tag("<<"), |_| BinOp::Shl),
+                map(tag(">>"), |_| BinOp::Shr),
+            ))(i)
+        },
+        parse_add,
+    )
+}
+
+fn parse_relational(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    binary_level(
+        i,
+        |i| {
+            alt((
+                map(tag("<="), |_| BinOp::Le),
+                map(tag(">="), |_| BinOp::Ge),
+                map(char('<'), |_| BinOp::Lt),
+                map(char('>'), |_| BinOp::Gt),
+            ))(i)
+        },
+        parse_shift,
+    )
+}
+
+fn parse_equality(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    binary_level(
+        i,
+        |i| {
+            alt((
+                map(tag("=="), |_| BinOp::Eq),
+                map(tag("!="), |_| BinOp::Ne),
+            ))(i)
+        },
+        parse_relational,
+    )
+}
+
+fn parse_and(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    binary_level(i, |i| map(char('&'), |_| BinOp::And)(i), parse_equality)
+}
+
+fn parse_xor(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    binary_level(i, |i| map(char('^'), |_| BinOp::Xor)(i), parse_and)
+}
+
+/// Parses an integer expression with C operator precedence. Whitespace
+/// inside the expression is allowed, but line breaks are not.
+pub fn parse_expr(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    binary_level(i, |i| map(char('|'), |_| BinOp::Or)(i), parse_xor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(s: &str) -> i64 {
+        let (rest, expr) = parse_expr(s).unwrap();
+        assert_eq!(rest, "");
+        expr.constant().unwrap()
+    }
+
+    #[test]
+    fn literal_test() {
+        assert_eq!(eval("123"), 123);
+        assert_eq!(eval("0x1F"), 0x1F);
+        assert_eq!(eval("0b101"), 0b101);
+        assert_eq!(eval("'A'"), 65);
+        assert_eq!(eval("'\\n'"), 10);
+        assert_eq!(eval("'\\x7f' + '\\033'"), 0x7F + 27);
+    }
+
+    #[test]
+    fn precedence_test() {
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("1 << 4 | 1"), 17);
+        assert_eq!(eval("0xFF & ~0x0F ^ 1"), 0xF1);
+        assert_eq!(eval("-8 / 2 % 3"), -1);
+        assert_eq!(eval("1 + 2 << 3 >> 1"), 12);
+        assert_eq!(eval("1 << 2 == 4 & 3 > 2"), 1);
+        assert_eq!(eval("2 != 2 | 1 <= 0"), 0);
+    }
+
+    #[test]
+    fn symbol_test() {
+        let (_, expr) = parse_expr("end - start + 4").unwrap();
+        assert_eq!(expr.symbols(), vec!["end", "start"]);
+        assert_eq!(expr.constant(), None);
+        let value = expr.eval(&|s: &str| match s {
+            "end" => Ok(0x40),
+            "start" => Ok(0x10),
+            _ => Err(format!("undefined symbol `{}`", s)),
+        });
+        assert_eq!(value, Ok(0x34));
+    }
+
+    #[test]
+    fn local_ref_test() {
+        let (_, expr) = parse_expr("1f - 12b").unwrap();
+        assert_eq!(expr.symbols(), vec!["1f", "12b"]);
+        // A bare `0b` is a label, with digits after it a binary number
+        assert_eq!(parse_expr("0b").unwrap().1, Expr::Sym("0b".to_string()));
+        assert_eq!(eval("0b11"), 3);
+    }
+
+    #[test]
+    fn stops_at_operand_test() {
+        let (rest, expr) = parse_expr("8(sp)").unwrap();
+        assert_eq!(rest, "(sp)");
+        assert_eq!(expr, Expr::Num(8));
+    }
+
+    #[test]
+    fn div_zero_test() {
+        let (_, expr) = parse_expr("1 / (2 - 2)").unwrap();
+        assert_eq!(expr.eval(&|s: &str| Err(s.to_string())), Err("division by zero".to_string()));
+        let (_, expr) = parse_expr("(-0x7fffffffffffffff - 1) % -1").unwrap();
+        assert_eq!(expr.eval(&|s: &str| Err(s.to_string())), Err("overflow".to_string()));
+        let (_, expr) = parse_expr("(-0x7fffffffffffffff - 1) / -1").unwrap();
+        assert_eq!(expr.eval(&|s: &str| Err(s.to_string())), Err("overflow".to_string()));
+    }
+
+    #[test]
+    fn reloc_test() {
+        assert_eq!(eval("%hi(0x12345678)"), 0x12345);
+        assert_eq!(eval("%lo(0x12345678)"), 0x678);
+        // Bit 11 is set, so the upper part rounds up and the lower is negative
+        assert_eq!(eval("%hi(0x12345FFF)"), 0x12346);
+        assert_eq!(eval("%lo(0x12345FFF)"), -1);
+        assert_eq!(eval("%hi(0xFFFFF800)"), 0);
+        assert_eq!(eval("%lo(0xFFFFF800)"), -0x800);
+        assert_eq!(eval("%hi(0x1000 + 0x800) + 1"), 3);
+    }
+
+    #[test]
+    fn pcrel_test() {
+        let (_, expr) = parse_expr("%pcrel_hi(msg)").unwrap();
+        let value = expr.eval(&|s: &str| match s {
+            "msg" => Ok(0x1900),
+            "." => Ok(0x4),
+            _ => Err(format!("undefined symbol `{}`", s)),
+        });
+        assert_eq!(value, Ok(2));
+        let (_, expr) = parse_expr("%pcrel_lo(.Lhi)").unwrap();
+        assert!(expr.constant().is_none());
+    }
+}
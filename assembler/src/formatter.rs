@@ -0,0 +1,456 @@
+use crate::custom::CustomInstructions;
+use crate::expr::parse_expr;
+use crate::instructions::isa::{lookup, Syntax};
+use crate::instructions::Format;
+use crate::parser::{comment_start, is_statement, split_label, str_to_reg, REG_NAMES};
+
+/// Column that statements, and comments on a line of their own, start in.
+const INDENT: usize = 8;
+/// Mnemonics and directives are padded to this, so operands line up.
+const MNEMONIC_WIDTH: usize = 8;
+
+/// How to format a file.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Write numbered registers like `10` by their ABI name, `a0`.
+    pub abi_names: bool,
+    /// Custom instructions, formatted like the built-in ones of their format.
+    pub instructions: CustomInstructions,
+}
+
+/// What a line holds, each part laid out on its own.
+struct Line {
+    labels: Vec<String>,
+    statement: Option<String>,
+    comment: Option<String>,
+    /// A comment on a line of its own that started in column 0.
+    flush: bool,
+    /// A line of a `/* */` comment, kept as it is.
+    verbatim: Option<String>,
+}
+
+impl Line {
+    fn is_blank(&self) -> bool {
+        self.labels.is_empty() && self.statement.is_none() && self.comment.is_none() && self.verbatim.is_none()
+    }
+
+    /// The line without its comment. A statement that doesn't fit after its
+    /// labels goes on a line of its own.
+    fn code(&self) -> Vec<String> {
+        let indent = " ".repeat(INDENT);
+        let labels: Vec<_> = self.labels.iter().map(|label| format!("{}:", label)).collect();
+        let labels = labels.join(" ");
+        if let Some(verbatim) = &self.verbatim {
+            return vec![verbatim.clone()];
+        }
+        match &self.statement {
+            None if labels.is_empty() => vec![],
+            None => vec![labels],
+            Some(statement) if labels.is_empty() => vec![indent + statement],
+            Some(statement) if labels.len() < INDENT => {
+                vec![format!("{:<width$}{}", labels, statement, width = INDENT)]
+            }
+            Some(statement) => vec![labels, indent + statement],
+        }
+    }
+}
+
+/// The operands of an instruction, by the syntax it's written in.
+#[derive(Clone, Copy)]
+enum Operand {
+    Reg,
+    Imm,
+    /// `imm(rs1)`
+    Offset,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')
+}
+
+/// A number in one spelling: lower case `0x` and hex digits, `0b`, and no
+/// leading zeros in decimal. It stays in the base it was written in, and
+/// anything that isn't a number, like a symbol or `1b`, is left alone.
+fn number(token: &str) -> String {
+    let lower = token.to_ascii_lowercase();
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let local_ref = lower.ends_with(['b', 'f']) && digits(&lower[..lower.len() - 1]);
+    if !token.starts_with(|c: char| c.is_ascii_digit()) || local_ref {
+        token.to_string()
+    } else if lower.starts_with("0x") || lower.starts_with("0b") {
+        lower
+    } else {
+        match token.trim_start_matches('0') {
+            "" => "0".to_string(),
+            decimal => decimal.to_string(),
+        }
+    }
+}
+
+/// The length of the character literal at the start of `s`.
+fn char_literal(s: &str) -> usize {
+    let mut escape = false;
+    for (idx, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escape => escape = false,
+            '\\' => escape = true,
+            '\'' => return idx + 1,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+/// Respaces an expression: one space around binary operators, and none
+/// after unary ones or inside parentheses. A unary `+` is dropped.
+fn format_expr(expr: &str) -> String {
+    let mut out = String::new();
+    // Whether the last token ends an operand, so `+`, `-` and `%` after it
+    // are binary operators
+    let mut after_operand = false;
+    let mut i = 0;
+    while let Some(c) = expr[i..].chars().next() {
+        let rest = &expr[i..];
+        let len = if is_word(c) {
+            rest.find(|c| !is_word(c)).unwrap_or(rest.len())
+        } else if c == '\'' {
+            char_literal(rest)
+        } else if c == '%' && !after_operand {
+            1 + rest[1..].find(|c| !is_word(c)).unwrap_or(rest.len() - 1)
+        } else if ["<<", ">>", "<=", ">=", "==", "!="].iter().any(|op| rest.starts_with(op)) {
+            2
+        } else {
+            c.len_utf8()
+        };
+        let token = &rest[..len];
+        i += len;
+        match token {
+            _ if c.is_whitespace() => {}
+            _ if is_word(c) || c == '\'' => {
+                out += &number(token);
+                after_operand = true;
+            }
+            _ if c == '%' && !after_operand => out += &token.to_ascii_lowercase(),
+            "(" => out.push('('),
+            ")" => {
+                out.push(')');
+                after_operand = true;
+            }
+            "+" if !after_operand => {}
+            "-" | "~" if !after_operand => out += token,
+            op => {
+                out += &format!(" {} ", op);
+                after_operand = false;
+            }
+        }
+    }
+    out
+}
+
+/// An operand that's an expression is respaced, and anything else, like a
+/// string or a section flag, is kept as it is.
+fn expr(operand: &str) -> String {
+    match parse_expr(operand) {
+        Ok(("", _)) => format_expr(operand),
+        _ => operand.to_string(),
+    }
+}
+
+/// A register in lower case, by its ABI name if `abi_names`.
+fn reg(operand: &str, options: &Options) -> String {
+    match str_to_reg(operand) {
+        Some(reg) if options.abi_names => REG_NAMES[reg as usize].to_string(),
+        Some(_) => operand.to_ascii_lowercase(),
+        None => operand.to_string(),
+    }
+}
+
+/// Splits operands at the commas that aren't in parentheses or quotes.
+fn split_operands(s: &str) -> Vec<&str> {
+    if s.is_empty() {
+        return vec![];
+    }
+    let mut operands = vec![];
+    let (mut depth, mut start) = (0usize, 0);
+    let mut quote = None;
+    let mut escape = false;
+    for (idx, c) in s.char_indices() {
+        match quote {
+            Some(_) if escape => escape = false,
+            Some(_) if c == '\\' => escape = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                ',' if depth == 0 => {
+                    operands.push(s[start..idx].trim());
+                    start = idx + 1;
+                }
+                _ => {}
+            },
+        }
+    }
+    operands.push(s[start..].trim());
+    operands
+}
+
+fn instruction_operands(mne: &str, operands: &str, options: &Options) -> String {
+    let mut split = split_operands(operands);
+    let syntax = match (lookup(mne), options.instructions.get(mne)) {
+        (Some(opcode), _) => opcode.syntax,
+        (None, Some(definition)) if definition.format == Format::I && split.len() == 2 => Syntax::Load,
+        (None, Some(definition)) => Syntax::of(definition.format),
+        // `la rd, symbol` is written like `lui`
+        (None, None) if mne.eq_ignore_ascii_case("la") => Syntax::Upper,
+        (None, None) => return split.join(", "),
+    };
+    // `lui s1 0x12` gets its comma
+    if let (Syntax::Upper | Syntax::Jump, [only]) = (syntax, split.as_slice()) {
+        if let Some((rd, rest)) = only.split_once(char::is_whitespace) {
+            split = vec![rd, rest.trim()];
+        }
+    }
+    use Operand::{Imm, Offset, Reg};
+    let kinds: &[Operand] = match syntax {
+        Syntax::Reg => &[Reg, Reg, Reg],
+        Syntax::Imm | Syntax::Shift => &[Reg, Reg, Imm],
+        Syntax::Load | Syntax::Store => &[Reg, Offset],
+        Syntax::Branch => &[Reg, Reg, Imm],
+        Syntax::Upper | Syntax::Jump => &[Reg, Imm],
+    };
+    if split.len() != kinds.len() {
+        return split.join(", ");
+    }
+    let formatted: Vec<_> = split
+        .iter()
+        .zip(kinds)
+        .map(|(operand, kind)| match (kind, operand.rfind('(')) {
+            (Reg, _) => reg(operand, options),
+            (Offset, Some(open)) => {
+                let base = operand[open + 1..].trim_end_matches(')').trim();
+                format!("{}({})", expr(operand[..open].trim()), reg(base, options))
+            }
+            _ => expr(operand),
+        })
+        .collect();
+    formatted.join(", ")
+}
+
+/// A statement with its mnemonic or directive in lower case and its
+/// operands respaced, or as it is if the assembler can't parse it, like a
+/// line in a `.if` block or the `.if` itself.
+fn format_statement(statement: &str, options: &Options) -> String {
+    if !is_statement(statement, &options.instructions) {
+        return statement.to_string();
+    }
+    let end = statement.find(|c: char| c.is_ascii_whitespace()).unwrap_or(statement.len());
+    let word = statement[..end].to_lowercase();
+    let operands = statement[end..].trim();
+    let operands = match word.starts_with('.') {
+        true => split_operands(operands).into_iter().map(expr).collect::<Vec<_>>().join(", "),
+        false => instruction_operands(&word, operands, options),
+    };
+    match operands.is_empty() {
+        true => word,
+        false => format!("{:<width$} {}", word, operands, width = MNEMONIC_WIDTH - 1),
+    }
+}
+
+fn split_line(line: &str, options: &Options) -> Line {
+    let (text, comment) = match comment_start(line) {
+        Some(start) => (&line[..start], Some(line[start..].trim_end().to_string())),
+        None => (line, None),
+    };
+    let mut labels = vec![];
+    let mut rest = text;
+    while let Some((label, after)) = split_label(rest) {
+        labels.push(label.to_string());
+        rest = after;
+    }
+    let rest = rest.trim();
+    Line {
+        labels,
+        statement: (!rest.is_empty()).then(|| format_statement(rest, options)),
+        comment,
+        flush: line.starts_with('#'),
+        verbatim: None,
+    }
+}
+
+/// Whether a `/* */` comment is still open at the end of `line`, given
+/// whether one was at its start. The C preprocessor takes them out of `.S`
+/// files, so the assembler never sees them.
+fn in_block_comment(line: &str, mut open: bool) -> bool {
+    let mut quote = None;
+    let mut escape = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match quote {
+            _ if open && c == '*' && chars.peek() == Some(&'/') => {
+                chars.next();
+                open = false;
+            }
+            _ if open => {}
+            Some(_) if escape => escape = false,
+            Some(_) if c == '\\' => escape = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => break,
+            None if c == '/' && chars.peek() == Some(&'*') => {
+                chars.next();
+                open = true;
+            }
+            None => {}
+        }
+    }
+    open
+}
+
+/// Lays out an assembly file: labels in column 0, statements indented with
+/// their operands lined up, and the comments at the end of lines lined up
+/// in each block of lines. Runs of blank lines become one. A `/* */`
+/// comment on lines of its own is left as it is.
+pub fn format(source: &str, options: &Options) -> String {
+    let mut open = false;
+    let lines: Vec<_> = source
+        .lines()
+        .map(|line| {
+            let verbatim = open || line.trim_start().starts_with("/*");
+            open = in_block_comment(line, open);
+            match verbatim {
+                true => Line {
+                    labels: vec![],
+                    statement: None,
+                    comment: None,
+                    flush: false,
+                    verbatim: Some(line.to_string()),
+                },
+                false => split_line(line, options),
+            }
+        })
+        .collect();
+    let mut blocks = vec![];
+    for block in lines.split(Line::is_blank).filter(|block| !block.is_empty()) {
+        let codes: Vec<_> = block.iter().map(Line::code).collect();
+        let column = block
+            .iter()
+            .zip(&codes)
+            .filter(|(line, _)| line.comment.is_some())
+            .filter_map(|(_, code)| code.last().map(|last| last.len() + 1))
+            .max()
+            .unwrap_or(0);
+        let mut out = vec![];
+        for (line, mut code) in block.iter().zip(codes) {
+            match (&line.comment, code.pop()) {
+                (Some(comment), Some(last)) => code.push(format!("{:<width$}{}", last, comment, width = column)),
+                (Some(comment), None) if line.flush => code.push(comment.clone()),
+                (Some(comment), None) => code.push(" ".repeat(INDENT) + comment),
+                (None, last) => code.extend(last),
+            }
+            out.extend(code);
+        }
+        blocks.push(out.join("\n") + "\n");
+    }
+    blocks.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble, Options as AssemblerOptions};
+    use crate::parser::parse;
+
+    const MESSY: &str = "\
+# Adds things up
+  .TEXT
+_start: ADDI 10,0 , 0X1F   # first
+loop:BEQ a0,zero,done # branch
+   lw a1 , +8 ( 2 )
+\tlui s1 0XAB
+a_long_label: slli a0,a0, 2*( 1+1 )
+
+
+
+ # back up
+  jal zero,loop
+done: .word 007,-1, 'a' ,.-_start
+.data
+msg:.string \"hi, # there\"
+";
+
+    #[test]
+    fn format_test() {
+        let expected = "\
+# Adds things up
+        .text
+_start: addi    10, 0, 0x1f    # first
+loop:   beq     a0, zero, done # branch
+        lw      a1, 8(2)
+        lui     s1, 0xab
+a_long_label:
+        slli    a0, a0, 2 * (1 + 1)
+
+        # back up
+        jal     zero, loop
+done:   .word   7, -1, 'a', . - _start
+        .data
+msg:    .string \"hi, # there\"
+";
+        let formatted = format(MESSY, &Options::default());
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted, &Options::default()), formatted);
+        let binary = |source: &str| assemble(&parse(source).unwrap(), &AssemblerOptions::default()).unwrap();
+        assert_eq!(binary(&formatted), binary(MESSY));
+    }
+
+    #[test]
+    fn abi_names_test() {
+        let options = Options {
+            abi_names: true,
+            ..Options::default()
+        };
+        assert_eq!(format("addi 10, 2, -4\nsw 1, 0(fp)", &options), "        addi    a0, sp, -4\n        sw      ra, 0(s0)\n");
+        assert_eq!(format("ADDI A0, SP, -4\nsw RA, 0(FP)", &options), "        addi    a0, sp, -4\n        sw      ra, 0(s0)\n");
+        assert_eq!(format("ADDI A0, 10, -4", &Options::default()), "        addi    a0, 10, -4\n");
+    }
+
+    #[test]
+    fn block_comment_test() {
+        let source = "\
+/*
+ * Boots the board.
+ *
+ *   x = 1   # kept
+ */
+_start: addi a0,a0,1 /* set
+   up */
+  lw a1,0(a2)
+  /* one line */
+";
+        let expected = "\
+/*
+ * Boots the board.
+ *
+ *   x = 1   # kept
+ */
+_start: addi a0,a0,1 /* set
+   up */
+        lw      a1, 0(a2)
+  /* one line */
+";
+        assert_eq!(format(source, &Options::default()), expected);
+        assert_eq!(format(expected, &Options::default()), expected);
+        assert!(!in_block_comment("\"/*\" # /*", false));
+    }
+
+    #[test]
+    fn format_expr_test() {
+        assert_eq!(format_expr("%HI( msg )+4"), "%hi(msg) + 4");
+        assert_eq!(format_expr("-~1<<2"), "-~1 << 2");
+        assert_eq!(format_expr("a%b - 1b"), "a % b - 1b");
+        assert_eq!(format_expr("0B101|0x00FF"), "0b101 | 0x00ff");
+    }
+}
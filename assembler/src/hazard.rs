@@ -0,0 +1,480 @@
+use serde::Deserialize;
+use std::fmt;
+
+use crate::assembler::{item_label, item_offsets};
+use crate::instructions::isa::{memory_access, Access};
+use crate::instructions::types::Reg;
+use crate::instructions::{Format, InstructionData};
+use crate::lint;
+use crate::parser::{reg_name, FullFile, Item, Location, Text};
+use crate::Diagnostic;
+
+/// The pipeline a program is checked against, written in TOML. Stages are
+/// numbered from 1 for fetch. Registers are read in stage 2 and written in
+/// the last stage, early enough in the cycle to be read in the same one.
+///
+/// ```toml
+/// depth = 5
+/// execute = 3
+/// memory = 4
+/// forward = [3, 4]
+/// branch_delay = 2
+/// ```
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pipeline {
+    pub depth: u32,
+    /// Stage that computes results and takes its operands at the start.
+    pub execute: u32,
+    /// Stage at the end of which a loaded value is there.
+    pub memory: u32,
+    /// Stages whose results are forwarded to the start of `execute`.
+    pub forward: Vec<u32>,
+    /// Instructions after a branch or jump that run whether it is taken or
+    /// not, because they are already in the pipeline and not flushed.
+    pub branch_delay: u32,
+}
+
+/// The classic five stages with no forwarding, like our first CPU. Branches
+/// and jumps are done in execute, and the two instructions fetched behind
+/// them by then aren't flushed.
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline {
+            depth: 5,
+            execute: 3,
+            memory: 4,
+            forward: vec![],
+            branch_delay: 2,
+        }
+    }
+}
+
+/// The most stages, or instructions in a branch delay, a pipeline can have.
+const MAX_STAGES: u32 = 64;
+
+impl Pipeline {
+    pub fn from_toml(s: &str) -> Result<Pipeline, String> {
+        let pipeline: Pipeline = toml::from_str(s).map_err(|e| e.to_string())?;
+        for (field, value) in [("depth", pipeline.depth), ("branch_delay", pipeline.branch_delay)] {
+            if value > MAX_STAGES {
+                return Err(format!("`{}` is {}, but can be at most {}", field, value, MAX_STAGES));
+            }
+        }
+        if !(2 < pipeline.execute
+            && pipeline.execute <= pipeline.memory
+            && pipeline.memory <= pipeline.depth)
+        {
+            return Err("stages have to be 2 < execute <= memory <= depth".to_string());
+        }
+        if let Some(stage) = pipeline
+            .forward
+            .iter()
+            .find(|&&stage| stage < pipeline.execute || stage > pipeline.depth)
+        {
+            return Err(format!("can't forward from stage {}", stage));
+        }
+        Ok(pipeline)
+    }
+
+    pub fn read(path: &str) -> Result<Pipeline, String> {
+        let pipeline = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Pipeline::from_toml(&pipeline)
+    }
+
+    /// How many instructions on from one that writes a register the first
+    /// one that can read it is.
+    fn distance(&self, load: bool) -> u32 {
+        let ready = if load { self.memory } else { self.execute };
+        let written_back = self.depth - 2;
+        self.forward
+            .iter()
+            .filter(|&&stage| stage >= ready)
+            .map(|&stage| stage - self.execute + 1)
+            .chain([written_back])
+            .min()
+            .unwrap()
+            .max(1)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    /// Reads a register before the instruction that writes it is done.
+    Raw(Reg),
+    /// Reads a register right after a load into it.
+    LoadUse(Reg),
+    /// Is in the delay of a branch or jump, so it runs either way.
+    Control,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Raw(reg) => write!(f, "reads `{}` before it is written", reg_name(*reg)),
+            Kind::LoadUse(reg) => write!(f, "uses `{}` before it is loaded", reg_name(*reg)),
+            Kind::Control => write!(f, "runs even when the branch or jump before it is taken"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Hazard {
+    pub kind: Kind,
+    pub section: String,
+    /// Of the instruction that has to wait, in the file as written.
+    pub offset: u32,
+    pub mne: String,
+    /// The closest function label before the instruction, one that is
+    /// called or global, else the closest label, or the section if none.
+    pub function: String,
+    /// `nop`s it takes to wait long enough.
+    pub bubbles: u32,
+}
+
+impl fmt::Display for Hazard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}+{:#x} `{}` {}, {} bubble(s)",
+            self.section, self.offset, self.mne, self.kind, self.bubbles
+        )
+    }
+}
+
+/// What a recent instruction means for the ones after it.
+#[derive(Clone, Copy)]
+struct Slot {
+    writes: Option<(Reg, bool)>,
+    control: bool,
+}
+
+const NOP_SLOT: Slot = Slot {
+    writes: None,
+    control: false,
+};
+
+fn slot(instruction: &InstructionData) -> Slot {
+    let load = matches!(memory_access(instruction), Some((Access::Load, _)));
+    Slot {
+        writes: instruction.rd.filter(|&rd| rd != 0).map(|rd| (rd, load)),
+        control: matches!(instruction.format(), Format::B | Format::J) || instruction.mne.eq_ignore_ascii_case("jalr"),
+    }
+}
+
+/// The worst hazard of `instruction` with the ones in `recent`, the latest
+/// last, and the `nop`s that get it out of the way.
+fn worst(
+    pipeline: &Pipeline,
+    recent: &[Slot],
+    instruction: &InstructionData,
+) -> Option<(Kind, u32)> {
+    let reads = [instruction.rs1, instruction.rs2];
+    let mut worst: Option<(Kind, u32)> = None;
+    for (n, slot) in recent.iter().rev().enumerate() {
+        let distance = n as u32 + 1;
+        let mut hazards = vec![];
+        if let Some((reg, load)) = slot.writes.filter(|(reg, _)| reads.contains(&Some(*reg))) {
+            let needed = pipeline.distance(load);
+            let kind = if load {
+                Kind::LoadUse(reg)
+            } else {
+                Kind::Raw(reg)
+            };
+            hazards.push((kind, needed.saturating_sub(distance)));
+        }
+        if slot.control && distance <= pipeline.branch_delay {
+            hazards.push((Kind::Control, pipeline.branch_delay - distance + 1));
+        }
+        for (kind, bubbles) in hazards {
+            if bubbles > worst.map_or(0, |(_, b)| b) {
+                worst = Some((kind, bubbles));
+            }
+        }
+    }
+    worst
+}
+
+/// Goes through the instructions of every section, with the `nop`s that
+/// come before each one, in order. Hazards across data, or between a jump
+/// and its target, aren't seen.
+fn scan(file: &FullFile, pipeline: &Pipeline) -> Vec<(usize, usize, Kind, u32)> {
+    let lookback = pipeline.depth.max(pipeline.branch_delay + 1) as usize;
+    let mut found = vec![];
+    for (index, section) in file.sections.iter().enumerate() {
+        let mut recent: Vec<Slot> = vec![];
+        for (n, item) in section.items.iter().enumerate() {
+            let text = match item {
+                Item::Text(text) => text,
+                Item::Label(..) => continue,
+                _ => {
+                    recent.clear();
+                    continue;
+                }
+            };
+            if let Some((kind, bubbles)) = worst(pipeline, &recent, &text.instruction) {
+                found.push((index, n, kind, bubbles));
+                recent.extend(std::iter::repeat_n(NOP_SLOT, bubbles as usize));
+            }
+            recent.push(slot(&text.instruction));
+            let excess = recent.len().saturating_sub(lookback);
+            recent.drain(..excess);
+        }
+    }
+    found
+}
+
+/// Where the `nop`s for a hazard of item `n` go. A branch delay is filled
+/// right after the branch, before any label that follows it, so jumps to
+/// the label don't wait. Other hazards wait after the instruction's labels,
+/// so jumps to it wait too.
+fn bubbles_at(items: &[Item], n: usize, kind: Kind) -> usize {
+    match kind {
+        Kind::Control => n - items[..n].iter().rev().take_while(|item| matches!(item, Item::Label(..))).count(),
+        _ => n,
+    }
+}
+
+/// Finds the instructions that would read stale registers, or run when
+/// they shouldn't, on `pipeline`.
+pub fn hazards(file: &FullFile, pipeline: &Pipeline) -> Result<Vec<Hazard>, Diagnostic> {
+    let offsets = item_offsets(file)?;
+    let found = scan(file, pipeline);
+    // The entry point counts as a function here
+    let functions = lint::functions(file, "");
+    let mut hazards = vec![];
+    for (index, n, kind, bubbles) in found {
+        let section = &file.sections[index];
+        // The bubbles count against the function they are put in
+        let end = match kind {
+            Kind::Control => bubbles_at(&section.items, n, kind),
+            _ => n + 1,
+        };
+        let labels = || section.items[..end].iter().rev().filter_map(item_label);
+        let function = labels()
+            .find(|label| functions.contains(label))
+            .or_else(|| labels().next().filter(|label| !label.starts_with(".L")))
+            .unwrap_or(&section.name);
+        let mne = match &section.items[n] {
+            Item::Text(text) => text.instruction.mne.clone(),
+            _ => unreachable!(),
+        };
+        hazards.push(Hazard {
+            kind,
+            section: section.name.clone(),
+            offset: offsets[index][n],
+            mne,
+            function: function.to_string(),
+            bubbles,
+        });
+    }
+    Ok(hazards)
+}
+
+fn nop() -> Item {
+    Item::Text(Text {
+        instruction: InstructionData {
+            mne: "addi".to_string(),
+            rd: Some(0),
+            rs1: Some(0),
+            rs2: None,
+            imm: Some(0),
+            custom: None,
+        },
+        label: None,
+        label_dst: None,
+        imm_expr: None,
+        location: Location::default(),
+        pseudo: false,
+    })
+}
+
+/// Puts `nop`s in front of every instruction with a hazard, where
+/// `bubbles_at` says. Labels move along, as they are laid out afterwards.
+pub fn insert_nops(file: &mut FullFile, pipeline: &Pipeline) -> Result<Vec<Hazard>, Diagnostic> {
+    let hazards = hazards(file, pipeline)?;
+    for (index, n, kind, bubbles) in scan(file, pipeline).into_iter().rev() {
+        let items = &mut file.sections[index].items;
+        let at = bubbles_at(items, n, kind);
+        let label = match &mut items[n] {
+            Item::Text(_) if kind == Kind::Control => None,
+            Item::Text(text) => text.label.take().map(|label| Item::Label(label, text.location)),
+            _ => unreachable!(),
+        };
+        items.splice(at..at, std::iter::repeat_n(nop(), bubbles as usize));
+        items.splice(at..at, label);
+    }
+    Ok(hazards)
+}
+
+/// Bubbles by function, in the order the functions come.
+pub fn bubbles(hazards: &[Hazard]) -> Vec<(String, u32)> {
+    let mut bubbles: Vec<(String, u32)> = vec![];
+    for hazard in hazards {
+        match bubbles
+            .iter_mut()
+            .find(|(function, _)| *function == hazard.function)
+        {
+            Some((_, count)) => *count += hazard.bubbles,
+            None => bubbles.push((hazard.function.clone(), hazard.bubbles)),
+        }
+    }
+    bubbles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble, Options};
+    use crate::parser::parse;
+
+    const SOURCE: &str = "
+        main: lw a0, 0(sp)
+        addi a1, a0, 1
+        add a2, a1, a1
+        jal ra, f
+        f: addi t0, zero, 1
+        beq t0, zero, f
+        addi t1, t0, 1
+    ";
+
+    #[test]
+    fn distance_test() {
+        let mut pipeline = Pipeline::default();
+        assert_eq!((pipeline.distance(false), pipeline.distance(true)), (3, 3));
+        pipeline.forward = vec![3, 4];
+        assert_eq!((pipeline.distance(false), pipeline.distance(true)), (1, 2));
+        pipeline.forward = vec![4];
+        assert_eq!((pipeline.distance(false), pipeline.distance(true)), (2, 2));
+    }
+
+    #[test]
+    fn hazards_test() {
+        let file = parse(SOURCE).unwrap();
+        let no_delay = Pipeline {
+            branch_delay: 0,
+            ..Pipeline::default()
+        };
+        let hazards = hazards(&file, &no_delay).unwrap();
+        let found: Vec<_> = hazards
+            .iter()
+            .map(|h| (h.offset, h.kind, h.bubbles))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (4, Kind::LoadUse(10), 2),
+                (8, Kind::Raw(11), 2),
+                (20, Kind::Raw(5), 2),
+            ]
+        );
+        assert_eq!(
+            hazards[0].to_string(),
+            ".text+0x4 `addi` uses `a0` before it is loaded, 2 bubble(s)"
+        );
+        assert_eq!(
+            bubbles(&hazards),
+            vec![("main".to_string(), 4), ("f".to_string(), 2)]
+        );
+
+        let pipeline = Pipeline::from_toml("forward = [3, 4]\nbranch_delay = 1").unwrap();
+        let hazards = super::hazards(&file, &pipeline).unwrap();
+        let found: Vec<_> = hazards
+            .iter()
+            .map(|h| (h.offset, h.kind, h.bubbles))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (4, Kind::LoadUse(10), 1),
+                (16, Kind::Control, 1),
+                (24, Kind::Control, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn default_pipeline_test() {
+        let file = parse(SOURCE).unwrap();
+        let hazards = hazards(&file, &Pipeline::default()).unwrap();
+        let found: Vec<_> = hazards
+            .iter()
+            .map(|h| (h.offset, h.kind, h.bubbles))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (4, Kind::LoadUse(10), 2),
+                (8, Kind::Raw(11), 2),
+                (16, Kind::Control, 2),
+                (20, Kind::Raw(5), 2),
+                (24, Kind::Control, 2),
+            ]
+        );
+        assert_eq!(
+            hazards[2].to_string(),
+            ".text+0x10 `addi` runs even when the branch or jump before it is taken, 2 bubble(s)"
+        );
+        // The delay after `jal ra, f` is still in `main`
+        assert_eq!(
+            bubbles(&hazards),
+            vec![("main".to_string(), 6), ("f".to_string(), 4)]
+        );
+    }
+
+    #[test]
+    fn function_test() {
+        let source = "
+        main: call f
+        jal zero, main
+        f: addi a0, zero, 1
+        loop: lw a1, 0(a0)
+        addi a1, a1, 1
+        bne a1, zero, loop
+        jalr zero, 0(ra)
+        ";
+        let no_delay = Pipeline {
+            branch_delay: 0,
+            ..Pipeline::default()
+        };
+        let hazards = hazards(&parse(source).unwrap(), &no_delay).unwrap();
+        assert_eq!(bubbles(&hazards), vec![("main".to_string(), 2), ("f".to_string(), 6)]);
+    }
+
+    #[test]
+    fn insert_nops_test() {
+        let mut file = parse(SOURCE).unwrap();
+        let pipeline = Pipeline::from_toml("forward = [3, 4]\nbranch_delay = 0").unwrap();
+        insert_nops(&mut file, &pipeline).unwrap();
+        assert!(hazards(&file, &pipeline).unwrap().is_empty());
+        let (binary, _) = assemble(&file, &Options::default()).unwrap();
+        // One nop after the load, and the branch back to `f` still lands on it
+        assert_eq!(binary.len(), 32);
+        assert_eq!(&binary[4..8], &[0x00, 0x00, 0x00, 0x13]);
+        assert_eq!(&binary[24..28], &[0xFE, 0x02, 0x8E, 0xE3]);
+    }
+
+    #[test]
+    fn branch_delay_nops_test() {
+        let mut file = parse("main: beq a0, zero, skip\nskip:\naddi a0, a0, 1\nlw a1, 0(a2)").unwrap();
+        let hazards = insert_nops(&mut file, &Pipeline::default()).unwrap();
+        assert_eq!(bubbles(&hazards), vec![("main".to_string(), 2)]);
+        let (binary, _) = assemble(&file, &Options::default()).unwrap();
+        let words: Vec<_> = binary.chunks(4).map(|w| u32::from_be_bytes(w.try_into().unwrap())).collect();
+        // The branch skips the `nop`s it runs when it isn't taken
+        assert_eq!(words, vec![0x00050663, 0x00000013, 0x00000013, 0x00150513, 0x00062583]);
+    }
+
+    #[test]
+    fn from_toml_test() {
+        assert_eq!(Pipeline::from_toml("").unwrap(), Pipeline::default());
+        assert!(Pipeline::from_toml("execute = 6").is_err());
+        let err = Pipeline::from_toml("forward = [2]").unwrap_err();
+        assert_eq!(err, "can't forward from stage 2");
+        let err = Pipeline::from_toml("depth = 4294967295").unwrap_err();
+        assert_eq!(err, "`depth` is 4294967295, but can be at most 64");
+        let err = Pipeline::from_toml("branch_delay = 65").unwrap_err();
+        assert_eq!(err, "`branch_delay` is 65, but can be at most 64");
+        assert!(Pipeline::from_toml("depth = 64\nbranch_delay = 64").is_ok());
+    }
+}
@@ -1,37 +1,10 @@
 use super::types::{Imm, Reg};
 use super::instruction::Instruction;
-use std::str::FromStr;
-
-#[derive(Debug, PartialEq)]
-pub enum BTypeMne {
-    BEQ,
-    BNE,
-    BLT,
-    BGE,
-    BLTU,
-    BGEU,
-}
-
-impl FromStr for BTypeMne {
-
-    type Err = ();
-
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
-        match input.to_lowercase().as_ref() {
-            "beq" => Ok(BTypeMne::BEQ),
-            "bne" => Ok(BTypeMne::BNE),
-            "blt" => Ok(BTypeMne::BLT),
-            "bge" => Ok(BTypeMne::BGE),
-            "bltu" => Ok(BTypeMne::BLTU),
-            "bgeu" => Ok(BTypeMne::BGEU),
-            _ => Err(()),
-        }
-    }
-}
+use super::Encoding;
 
 #[derive(Debug, PartialEq)]
 pub struct BType {
-    pub mne: BTypeMne,
+    pub encoding: Encoding,
     pub rs1: Reg,
     pub rs2: Reg,
     pub imm: Imm,
@@ -40,21 +13,12 @@ pub struct BType {
 
 impl Instruction for BType {
     fn translate(&self) -> Vec<u8> {
-        let funct3 = match self.mne {
-            BTypeMne::BEQ => 0b000,
-            BTypeMne::BNE => 0b001,
-            BTypeMne::BLT => 0b100,
-            BTypeMne::BGE => 0b101,
-            BTypeMne::BLTU => 0b110,
-            BTypeMne::BGEU => 0b111,
-        };
-        let opcode = 0b1100011;
+        let Encoding { opcode, funct3, .. } = self.encoding;
         let imm12 = (self.imm >> 12) & 0x1;
         let imm10_5 = (self.imm >> 5) & 0x3F;
         let imm4_1 = (self.imm >> 1) & 0xF;
         let imm11 = (self.imm >> 11) & 0x1;
-        let result: u32 = 0
-            | (imm12 << 31)
+        let result: u32 = (imm12 << 31)
             | (imm10_5 << 25)
             | (self.rs2 << 20)
             | (self.rs1 << 15)
@@ -69,11 +33,12 @@ impl Instruction for BType {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::instructions::isa::lookup;
 
     #[test]
     fn beq_test(){
         let instruction = BType {
-            mne: BTypeMne::BEQ,
+            encoding: lookup("beq").unwrap().encoding,
             rs1: 10,
             rs2: 23,
             imm: 0b1010101010101,
@@ -86,7 +51,7 @@ mod tests {
     #[test]
     fn bne_test(){
         let instruction = BType {
-            mne: BTypeMne::BNE,
+            encoding: lookup("bne").unwrap().encoding,
             rs1: 11,
             rs2: 3,
             imm: 0b0110111010101,
@@ -0,0 +1,439 @@
+use super::types;
+use super::{Encoding, Format, InstructionData};
+
+/// How an instruction's operands are written.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Syntax {
+    /// `rd, rs1, rs2`
+    Reg,
+    /// `rd, rs1, imm`
+    Imm,
+    /// `rd, rs1, shamt`, with funct7 above the shift amount
+    Shift,
+    /// `rd, imm(rs1)`
+    Load,
+    /// `rs2, imm(rs1)`
+    Store,
+    /// `rs1, rs2, target`
+    Branch,
+    /// `rd, imm`
+    Upper,
+    /// `rd, target`
+    Jump,
+}
+
+impl Syntax {
+    /// The syntax of `.insn` and custom instructions of `format`.
+    pub fn of(format: Format) -> Syntax {
+        match format {
+            Format::R => Syntax::Reg,
+            Format::I => Syntax::Imm,
+            Format::S => Syntax::Store,
+            Format::B => Syntax::Branch,
+            Format::U => Syntax::Upper,
+            Format::J => Syntax::Jump,
+        }
+    }
+
+    pub fn operands(self) -> &'static str {
+        match self {
+            Syntax::Reg => "rd, rs1, rs2",
+            Syntax::Imm => "rd, rs1, imm",
+            Syntax::Shift => "rd, rs1, shamt",
+            Syntax::Load => "rd, imm(rs1)",
+            Syntax::Store => "rs2, imm(rs1)",
+            Syntax::Branch => "rs1, rs2, target",
+            Syntax::Upper => "rd, imm",
+            Syntax::Jump => "rd, target",
+        }
+    }
+}
+
+/// An instruction of the ISA: its mnemonic, fixed fields, how its operands
+/// are written, the extension it comes from and what it does.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Opcode {
+    pub mne: &'static str,
+    pub encoding: Encoding,
+    pub syntax: Syntax,
+    pub extension: &'static str,
+    pub description: &'static str,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn op(
+    mne: &'static str,
+    format: Format,
+    opcode: u32,
+    funct3: u32,
+    funct7: u32,
+    syntax: Syntax,
+    extension: &'static str,
+    description: &'static str,
+) -> Opcode {
+    Opcode {
+        mne,
+        encoding: Encoding {
+            format,
+            opcode,
+            funct3,
+            funct7,
+        },
+        syntax,
+        extension,
+        description,
+    }
+}
+
+use Format::{B, I, J, R, S, U};
+use Syntax::{Branch, Imm, Jump, Load, Reg, Shift, Store, Upper};
+
+/// Every instruction the assembler knows. Parsing, encoding, decoding and
+/// the list in the README all come from here.
+#[rustfmt::skip]
+pub const ISA: [Opcode; 37] = [
+    op("lui",   U, 0x37, 0, 0,    Upper,  "I", "Load upper immediate"),
+    op("auipc", U, 0x17, 0, 0,    Upper,  "I", "Add upper immediate to PC"),
+    op("jal",   J, 0x6f, 0, 0,    Jump,   "I", "Jump and link"),
+    op("jalr",  I, 0x67, 0, 0,    Load,   "I", "Jump and link register"),
+    op("beq",   B, 0x63, 0, 0,    Branch, "I", "Branch if equal"),
+    op("bne",   B, 0x63, 1, 0,    Branch, "I", "Branch if not equal"),
+    op("blt",   B, 0x63, 4, 0,    Branch, "I", "Branch if less than"),
+    op("bge",   B, 0x63, 5, 0,    Branch, "I", "Branch if greater or equal"),
+    op("bltu",  B, 0x63, 6, 0,    Branch, "I", "Branch if less than, unsigned"),
+    op("bgeu",  B, 0x63, 7, 0,    Branch, "I", "Branch if greater or equal, unsigned"),
+    op("lb",    I, 0x03, 0, 0,    Load,   "I", "Load byte"),
+    op("lh",    I, 0x03, 1, 0,    Load,   "I", "Load halfword"),
+    op("lw",    I, 0x03, 2, 0,    Load,   "I", "Load word"),
+    op("lbu",   I, 0x03, 4, 0,    Load,   "I", "Load byte, unsigned"),
+    op("lhu",   I, 0x03, 5, 0,    Load,   "I", "Load halfword, unsigned"),
+    op("sb",    S, 0x23, 0, 0,    Store,  "I", "Store byte"),
+    op("sh",    S, 0x23, 1, 0,    Store,  "I", "Store halfword"),
+    op("sw",    S, 0x23, 2, 0,    Store,  "I", "Store word"),
+    op("addi",  I, 0x13, 0, 0,    Imm,    "I", "Add immediate"),
+    op("slti",  I, 0x13, 2, 0,    Imm,    "I", "Set if less than immediate"),
+    op("sltiu", I, 0x13, 3, 0,    Imm,    "I", "Set if less than immediate, unsigned"),
+    op("xori",  I, 0x13, 4, 0,    Imm,    "I", "XOR immediate"),
+    op("ori",   I, 0x13, 6, 0,    Imm,    "I", "OR immediate"),
+    op("andi",  I, 0x13, 7, 0,    Imm,    "I", "AND immediate"),
+    op("slli",  I, 0x13, 1, 0,    Shift,  "I", "Shift left logical by immediate"),
+    op("srli",  I, 0x13, 5, 0,    Shift,  "I", "Shift right logical by immediate"),
+    op("srai",  I, 0x13, 5, 0x20, Shift,  "I", "Shift right arithmetic by immediate"),
+    op("add",   R, 0x33, 0, 0,    Reg,    "I", "Add"),
+    op("sub",   R, 0x33, 0, 0x20, Reg,    "I", "Subtract"),
+    op("sll",   R, 0x33, 1, 0,    Reg,    "I", "Shift left logical"),
+    op("slt",   R, 0x33, 2, 0,    Reg,    "I", "Set if less than"),
+    op("sltu",  R, 0x33, 3, 0,    Reg,    "I", "Set if less than, unsigned"),
+    op("xor",   R, 0x33, 4, 0,    Reg,    "I", "XOR"),
+    op("srl",   R, 0x33, 5, 0,    Reg,    "I", "Shift right logical"),
+    op("sra",   R, 0x33, 5, 0x20, Reg,    "I", "Shift right arithmetic"),
+    op("or",    R, 0x33, 6, 0,    Reg,    "I", "OR"),
+    op("and",   R, 0x33, 7, 0,    Reg,    "I", "AND"),
+];
+
+pub fn lookup(mne: &str) -> Option<&'static Opcode> {
+    ISA.iter()
+        .find(|opcode| opcode.mne.eq_ignore_ascii_case(mne))
+}
+
+/// Whether an instruction reads or writes memory.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Access {
+    Load,
+    Store,
+}
+
+/// Whether `instruction` is a load or a store, going by its opcode, and the
+/// bytes it moves, which the low two bits of its funct3 give as a power of
+/// two. Custom instructions with those opcodes count too.
+pub fn memory_access(instruction: &InstructionData) -> Option<(Access, u32)> {
+    let encoding = instruction.encoding()?;
+    let access = match encoding.opcode {
+        0x03 => Access::Load,
+        0x23 => Access::Store,
+        _ => return None,
+    };
+    Some((access, 1 << (encoding.funct3 & 3)))
+}
+
+/// A field of an instruction word, from bit `high` down to bit `low`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct BitField {
+    pub name: &'static str,
+    pub high: u32,
+    pub low: u32,
+}
+
+impl BitField {
+    pub fn width(self) -> u32 {
+        self.high - self.low + 1
+    }
+
+    pub fn value(self, word: u32) -> u32 {
+        (word >> self.low) & ((1 << self.width()) - 1)
+    }
+}
+
+const fn bits(name: &'static str, high: u32, low: u32) -> BitField {
+    BitField { name, high, low }
+}
+
+const OPCODE: BitField = bits("opcode", 6, 0);
+const RD: BitField = bits("rd", 11, 7);
+const FUNCT3: BitField = bits("funct3", 14, 12);
+const RS1: BitField = bits("rs1", 19, 15);
+const RS2: BitField = bits("rs2", 24, 20);
+const FUNCT7: BitField = bits("funct7", 31, 25);
+
+const R_FIELDS: [BitField; 6] = [FUNCT7, RS2, RS1, FUNCT3, RD, OPCODE];
+const SHIFT_FIELDS: [BitField; 6] = [FUNCT7, bits("shamt", 24, 20), RS1, FUNCT3, RD, OPCODE];
+const I_FIELDS: [BitField; 5] = [bits("imm[11:0]", 31, 20), RS1, FUNCT3, RD, OPCODE];
+const S_FIELDS: [BitField; 6] = [
+    bits("imm[11:5]", 31, 25),
+    RS2,
+    RS1,
+    FUNCT3,
+    bits("imm[4:0]", 11, 7),
+    OPCODE,
+];
+const B_FIELDS: [BitField; 8] = [
+    bits("imm[12]", 31, 31),
+    bits("imm[10:5]", 30, 25),
+    RS2,
+    RS1,
+    FUNCT3,
+    bits("imm[4:1]", 11, 8),
+    bits("imm[11]", 7, 7),
+    OPCODE,
+];
+const U_FIELDS: [BitField; 3] = [bits("imm[31:12]", 31, 12), RD, OPCODE];
+const J_FIELDS: [BitField; 6] = [
+    bits("imm[20]", 31, 31),
+    bits("imm[10:1]", 30, 21),
+    bits("imm[11]", 20, 20),
+    bits("imm[19:12]", 19, 12),
+    RD,
+    OPCODE,
+];
+
+/// The fields of a word of `format` written with `syntax`, from the top bit
+/// down. The immediate of a B or J type is split up the way
+/// `BType::translate` and `JType::translate` scramble it.
+pub fn bit_fields(format: Format, syntax: Syntax) -> &'static [BitField] {
+    match (format, syntax) {
+        (Format::R, _) => &R_FIELDS,
+        (Format::I, Syntax::Shift) => &SHIFT_FIELDS,
+        (Format::I, _) => &I_FIELDS,
+        (Format::S, _) => &S_FIELDS,
+        (Format::B, _) => &B_FIELDS,
+        (Format::U, _) => &U_FIELDS,
+        (Format::J, _) => &J_FIELDS,
+    }
+}
+
+/// Sign extends the low `bits` bits of `value`.
+fn sign_extend(value: u32, bits: u32) -> types::Imm {
+    (((value << (32 - bits)) as i32) >> (32 - bits)) as types::Imm
+}
+
+/// The immediate of `word` in `format`, sign extended, and for U the 20
+/// bits as `lui` takes them.
+pub fn immediate(format: Format, word: u32) -> types::Imm {
+    match format {
+        Format::R => 0,
+        Format::I => sign_extend(word >> 20, 12),
+        Format::S => sign_extend(((word >> 25) << 5) | ((word >> 7) & 0x1F), 12),
+        Format::B => sign_extend(
+            ((word >> 31) << 12)
+                | (((word >> 7) & 0x1) << 11)
+                | (((word >> 25) & 0x3F) << 5)
+                | (((word >> 8) & 0xF) << 1),
+            13,
+        ),
+        Format::U => word >> 12,
+        Format::J => sign_extend(
+            ((word >> 31) << 20)
+                | (((word >> 12) & 0xFF) << 12)
+                | (((word >> 20) & 0x1) << 11)
+                | (((word >> 21) & 0x3FF) << 1),
+            21,
+        ),
+    }
+}
+
+/// The instruction `word` encodes, if it is one of the table's.
+pub fn decode(word: u32) -> Option<InstructionData> {
+    let field = |shift: u32, bits: u32| (word >> shift) & ((1 << bits) - 1);
+    let opcode = ISA.iter().find(|opcode| {
+        let encoding = &opcode.encoding;
+        let funct3 =
+            matches!(encoding.format, Format::U | Format::J) || field(12, 3) == encoding.funct3;
+        let funct7 = !matches!(opcode.syntax, Syntax::Reg | Syntax::Shift)
+            || field(25, 7) == encoding.funct7;
+        field(0, 7) == encoding.opcode && funct3 && funct7
+    })?;
+    let format = opcode.encoding.format;
+    let reg = |shift: u32| Some(field(shift, 5) as types::Reg);
+    let has_rd = !matches!(format, Format::S | Format::B);
+    let has_rs1 = !matches!(format, Format::U | Format::J);
+    let has_rs2 = matches!(format, Format::R | Format::S | Format::B);
+    let imm = match opcode.syntax {
+        Syntax::Reg => None,
+        Syntax::Shift => Some(field(20, 5)),
+        _ => Some(immediate(format, word)),
+    };
+    Some(InstructionData {
+        mne: opcode.mne.to_string(),
+        rd: if has_rd { reg(7) } else { None },
+        rs1: if has_rs1 { reg(15) } else { None },
+        rs2: if has_rs2 { reg(20) } else { None },
+        imm,
+        custom: None,
+    })
+}
+
+/// The instructions as a Markdown table, as in the README.
+pub fn markdown() -> String {
+    let mut table = String::from(
+        "| Instruction | Operands | Format | Opcode | funct3 | funct7 | Extension | Description |\n\
+         |-------------|----------|--------|--------|--------|--------|-----------|-------------|\n",
+    );
+    for opcode in &ISA {
+        let encoding = &opcode.encoding;
+        let funct3 = match encoding.format {
+            Format::U | Format::J => String::new(),
+            _ => format!("{:#05b}", encoding.funct3),
+        };
+        let funct7 = match opcode.syntax {
+            Syntax::Reg | Syntax::Shift => format!("{:#04x}", encoding.funct7),
+            _ => String::new(),
+        };
+        table += &format!(
+            "| `{}` | `{}` | {:?} | `{:#04x}` | {} | {} | {} | {} |\n",
+            opcode.mne,
+            opcode.syntax.operands(),
+            encoding.format,
+            encoding.opcode,
+            funct3,
+            funct7,
+            opcode.extension,
+            opcode.description
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::generate_instruction;
+    use crate::instructions::types::Imm;
+
+    fn word(data: InstructionData) -> u32 {
+        let bytes = generate_instruction(data).unwrap().translate();
+        u32::from_be_bytes(bytes.try_into().unwrap())
+    }
+
+    #[test]
+    fn decode_test() {
+        // lhu a0, -2(sp)
+        let lhu = decode(0xFFE15503).unwrap();
+        assert_eq!(lhu.mne, "lhu");
+        assert_eq!(
+            (lhu.rd, lhu.rs1, lhu.imm),
+            (Some(10), Some(2), Some(-2i32 as Imm))
+        );
+        assert_eq!(decode(0x4020D093).unwrap().mne, "srai");
+        assert_eq!(decode(0x0020D093).unwrap().mne, "srli");
+        assert_eq!(decode(0x0000000B), None);
+    }
+
+    #[test]
+    fn memory_access_test() {
+        let access = |mne: &str| {
+            memory_access(&InstructionData {
+                mne: mne.to_string(),
+                rd: None,
+                rs1: None,
+                rs2: None,
+                imm: None,
+                custom: None,
+            })
+        };
+        assert_eq!(access("lw"), Some((Access::Load, 4)));
+        assert_eq!(access("lhu"), Some((Access::Load, 2)));
+        assert_eq!(access("sb"), Some((Access::Store, 1)));
+        assert_eq!(access("addi"), None);
+        let custom = InstructionData {
+            custom: Some(Encoding {
+                format: Format::I,
+                opcode: 0x03,
+                funct3: 3,
+                funct7: 0,
+            }),
+            ..decode(0x00000013).unwrap()
+        };
+        assert_eq!(memory_access(&custom), Some((Access::Load, 8)));
+    }
+
+    #[test]
+    fn round_trip_test() {
+        for opcode in &ISA {
+            let imm = match opcode.encoding.format {
+                Format::B => -8i32 as Imm,
+                Format::J => 0x7F4,
+                Format::U => 0xABCDE,
+                _ => 0x1F,
+            };
+            let data = InstructionData {
+                mne: opcode.mne.to_string(),
+                rd: Some(5),
+                rs1: Some(6),
+                rs2: Some(7),
+                imm: Some(imm),
+                custom: None,
+            };
+            let encoded = word(data);
+            let decoded = decode(encoded).unwrap();
+            assert_eq!(decoded.mne, opcode.mne);
+            assert_eq!(word(decoded), encoded);
+        }
+    }
+
+    #[test]
+    fn bit_fields_test() {
+        for opcode in &ISA {
+            let fields = bit_fields(opcode.encoding.format, opcode.syntax);
+            let mut next = 31;
+            for field in fields {
+                assert_eq!(field.high, next, "{}", opcode.mne);
+                next = field.low.wrapping_sub(1);
+            }
+            assert_eq!(next, u32::MAX, "{}", opcode.mne);
+        }
+
+        // The immediate's bits go back together where their names say
+        let jal = word(InstructionData {
+            mne: "jal".to_string(),
+            rd: Some(1),
+            rs1: None,
+            rs2: None,
+            imm: Some(0x8A5F6),
+            custom: None,
+        });
+        let imm: u32 = bit_fields(Format::J, Syntax::Jump)
+            .iter()
+            .filter_map(|field| {
+                let range = field.name.strip_prefix("imm[")?.strip_suffix(']')?;
+                let low: u32 = range.rsplit(':').next()?.parse().ok()?;
+                Some(field.value(jal) << low)
+            })
+            .sum();
+        assert_eq!(imm, 0x8A5F6);
+    }
+
+    #[test]
+    fn readme_test() {
+        assert!(include_str!("../../README.md").contains(&markdown()));
+    }
+}
@@ -1,5 +1,6 @@
 mod btype;
 pub mod instruction;
+pub mod isa;
 mod itype;
 mod jtype;
 mod rtype;
@@ -7,7 +8,7 @@ mod stype;
 pub mod types;
 mod utype;
 
-use std::str::FromStr;
+use serde::Deserialize;
 
 use self::btype::*;
 use self::instruction::Instruction;
@@ -18,6 +19,28 @@ use self::stype::*;
 use self::types::{Imm, Reg};
 use self::utype::*;
 
+/// How an instruction's operands are laid out in its 32 bits.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    R,
+    I,
+    S,
+    B,
+    U,
+    J,
+}
+
+/// The fixed fields of an instruction. Fields its format doesn't have are
+/// 0, and an I type's funct7 goes above its immediate, as for `srai`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Encoding {
+    pub format: Format,
+    pub opcode: u32,
+    pub funct3: u32,
+    pub funct7: u32,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct InstructionData {
     pub mne: String,
@@ -25,91 +48,71 @@ pub struct InstructionData {
     pub rs1: Option<Reg>,
     pub rs2: Option<Reg>,
     pub imm: Option<Imm>,
+    /// The encoding of an instruction from `.insn` or a custom mnemonic,
+    /// which isn't in the ISA table.
+    pub custom: Option<Encoding>,
+}
+
+impl InstructionData {
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.custom.or_else(|| isa::lookup(&self.mne).map(|opcode| opcode.encoding))
+    }
+
+    pub fn format(&self) -> Format {
+        self.encoding().map_or(Format::I, |encoding| encoding.format)
+    }
 }
 
-/// Could crash easily. Returns the corresponding instruction object.
-/// If this crashes though, the parser is either wrong, or the assembly
-/// syntax is incorrect.
-pub fn generate_instruction(data: InstructionData) -> Box<dyn Instruction> {
-    match data.mne.to_lowercase().as_ref() {
-        // B type
-        "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu" => {
-            let rs1 = data.rs1.unwrap();
-            let rs2 = data.rs2.unwrap();
-            let imm = data.imm.unwrap();
-
-            Box::new(BType {
-                mne: BTypeMne::from_str(data.mne.as_ref()).unwrap(),
-                rs1,
-                rs2,
-                imm,
-            })
-        }
-        // I type
-        "jalr" | "lb" | "lh" | "lw" | "lbu" | "lhu" | "addi" | "slti" | "sltiu" | "xori"
-        | "ori" | "andi" | "slli" | "srli" | "srai" => {
-            let rd = data.rd.unwrap();
-            let rs1 = data.rs1.unwrap();
-            let imm = data.imm.unwrap();
-
-            Box::new(IType {
-                mne: ITypeMne::from_str(data.mne.as_ref()).unwrap(),
-                rd,
-                rs1,
-                imm,
-            })
-        }
-        // J type
-        "jal" => {
-            let rd = data.rd.unwrap();
-            let imm = data.imm.unwrap();
-
-            Box::new(JType {
-                mne: JTypeMne::from_str(data.mne.as_ref()).unwrap(),
-                rd,
-                imm,
-            })
-        }
-        // R type
-        "add" | "sub" | "sll" | "slt" | "sltu" | "xor" | "srl" | "sra" | "or" | "and" => {
-            let rd = data.rd.unwrap();
-            let rs1 = data.rs1.unwrap();
-            let rs2 = data.rs2.unwrap();
-
-            Box::new(RType {
-                mne: RTypeMne::from_str(data.mne.as_ref()).unwrap(),
-                rd,
-                rs1,
-                rs2,
-            })
-        }
-        // S type
-        "sb" | "sh" | "sw" => {
-            let rs1 = data.rs1.unwrap();
-            let rs2 = data.rs2.unwrap();
-            let imm = data.imm.unwrap();
-
-            Box::new(SType {
-                mne: STypeMne::from_str(data.mne.as_ref()).unwrap(),
-                rs1,
-                rs2,
-                imm,
-            })
-        }
-        // U type
-        "lui" | "auipc" => {
-            let rd = data.rd.unwrap();
-            let imm = data.imm.unwrap();
-            Box::new(UType {
-                mne: UTypeMne::from_str(data.mne.as_ref()).unwrap(),
-                rd,
-                imm,
-            })
-        }
-        _ => {
-            panic!("Invalid mnemonic")
-        }
+/// Returns the corresponding instruction object. Operands it leaves out
+/// are 0.
+pub fn generate_instruction(data: InstructionData) -> Result<Box<dyn Instruction>, String> {
+    let encoding = data
+        .encoding()
+        .ok_or_else(|| format!("`{}` isn't an instruction", data.mne))?;
+    let rd = data.rd.unwrap_or(0);
+    let rs1 = data.rs1.unwrap_or(0);
+    let rs2 = data.rs2.unwrap_or(0);
+    let imm = data.imm.unwrap_or(0);
+    Ok(match encoding.format {
+        Format::R => Box::new(RType {
+            encoding,
+            rd,
+            rs1,
+            rs2,
+        }),
+        Format::I => Box::new(IType {
+            encoding,
+            rd,
+            rs1,
+            imm,
+        }),
+        Format::S => Box::new(SType {
+            encoding,
+            rs2,
+            imm,
+            rs1,
+        }),
+        Format::B => Box::new(BType {
+            encoding,
+            rs1,
+            rs2,
+            imm,
+        }),
+        Format::U => Box::new(UType { encoding, rd, imm }),
+        Format::J => Box::new(JType { encoding, rd, imm }),
+    })
+}
+
+/// The 32 bits of one instruction. Operands it leaves out are 0.
+pub fn encode(data: &InstructionData) -> Result<u32, String> {
+    if let Some(reg) = [data.rd, data.rs1, data.rs2].into_iter().flatten().find(|&reg| reg >= 32) {
+        return Err(format!("x{} isn't a register", reg));
+    }
+    if let Some(custom) = &data.custom {
+        crate::custom::check_fields(custom)?;
     }
+    let bytes = generate_instruction(data.clone())?.translate();
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
 }
 
 #[cfg(test)]
@@ -124,10 +127,12 @@ mod tests {
             rs1: Some(21),
             rs2: Some(12),
             imm: Some(1234),
-        });
+            custom: None,
+        })
+        .unwrap();
 
         let expected = BType {
-            mne: BTypeMne::BEQ,
+            encoding: isa::lookup("beq").unwrap().encoding,
             rs1: 21,
             rs2: 12,
             imm: 1234,
@@ -143,10 +148,12 @@ mod tests {
             rs1: Some(23),
             rs2: None,
             imm: Some(1234),
-        });
+            custom: None,
+        })
+        .unwrap();
 
         let expected = IType {
-            mne: ITypeMne::LB,
+            encoding: isa::lookup("lb").unwrap().encoding,
             rd: 12,
             rs1: 23,
             imm: 1234,
@@ -162,10 +169,12 @@ mod tests {
             rs1: None,
             rs2: None,
             imm: Some(1234),
-        });
+            custom: None,
+        })
+        .unwrap();
 
         let expected = JType {
-            mne: JTypeMne::JAL,
+            encoding: isa::lookup("jal").unwrap().encoding,
             rd: 12,
             imm: 1234,
         };
@@ -180,10 +189,12 @@ mod tests {
             rs1: Some(13),
             rs2: Some(14),
             imm: None,
-        });
+            custom: None,
+        })
+        .unwrap();
 
         let expected = RType {
-            mne: RTypeMne::ADD,
+            encoding: isa::lookup("add").unwrap().encoding,
             rd: 12,
             rs1: 13,
             rs2: 14,
@@ -199,10 +210,12 @@ mod tests {
             rs1: Some(13),
             rs2: Some(14),
             imm: Some(1234),
-        });
+            custom: None,
+        })
+        .unwrap();
 
         let expected = SType {
-            mne: STypeMne::SW,
+            encoding: isa::lookup("sw").unwrap().encoding,
             rs1: 13,
             rs2: 14,
             imm: 1234,
@@ -218,10 +231,12 @@ mod tests {
             rs1: None,
             rs2: None,
             imm: Some(1234),
-        });
+            custom: None,
+        })
+        .unwrap();
 
         let expected = UType {
-            mne: UTypeMne::LUI,
+            encoding: isa::lookup("lui").unwrap().encoding,
             rd: 12,
             imm: 1234,
         };
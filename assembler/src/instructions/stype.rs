@@ -1,30 +1,10 @@
 use super::instruction::Instruction;
 use super::types::{Imm, Reg};
-use std::str::FromStr;
-
-#[derive(PartialEq, Debug)] 
-pub enum STypeMne {
-    SB,
-    SH,
-    SW,
-}
-
-impl FromStr for STypeMne {
-    type Err = ();
-
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
-        match input.to_lowercase().as_ref() {
-            "sb" => Ok(STypeMne::SB),
-            "sh" => Ok(STypeMne::SH),
-            "sw" => Ok(STypeMne::SW),
-            _ => Err(()),
-        }
-    }
-}
+use super::Encoding;
 
 #[derive(PartialEq, Debug)] 
 pub struct SType {
-    pub mne: STypeMne,
+    pub encoding: Encoding,
     pub rs2: Reg,
     pub imm: Imm,
     pub rs1: Reg,
@@ -32,16 +12,10 @@ pub struct SType {
 
 impl Instruction for SType {
     fn translate(&self) -> Vec<u8> {
-        let opcode = 0b0100011;
-        let funct3 = match self.mne {
-            STypeMne::SB => 0b000,
-            STypeMne::SH => 0b001,
-            STypeMne::SW => 0b010,
-        };
+        let Encoding { opcode, funct3, .. } = self.encoding;
         let imm11_5 = (self.imm >> 5) & 0x7F;
         let imm4_0 = self.imm & 0x1F;
-        let result = 0
-            | (imm11_5 << 25)
+        let result = (imm11_5 << 25)
             | (self.rs2 << 20)
             | (self.rs1 << 15)
             | (funct3 << 12)
@@ -55,11 +29,12 @@ impl Instruction for SType {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::instructions::isa::lookup;
 
     #[test]
     fn sw_test() {
         let instruction = SType {
-            mne: STypeMne::SW,
+            encoding: lookup("sw").unwrap().encoding,
             rs2: 10,
             imm: 0b100101101010,
             rs1: 3,
@@ -72,7 +47,7 @@ mod tests {
     #[test]
     fn sb_test() {
         let instruction = SType {
-            mne: STypeMne::SB,
+            encoding: lookup("sb").unwrap().encoding,
             rs2: 2,
             imm: 0b001010011100,
             rs1: 24,
@@ -1,40 +1,19 @@
 use super::types::{Imm, Reg};
 use super::instruction::Instruction;
-use std::str::FromStr;
-
-#[derive(PartialEq, Debug)] 
-pub enum UTypeMne {
-    LUI,
-    AUIPC,
-}
-
-impl FromStr for UTypeMne {
-    type Err = ();
-
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
-        match input.to_lowercase().as_ref() {
-            "lui" => Ok(UTypeMne::LUI),
-            "auipc" => Ok(UTypeMne::AUIPC),
-            _ => Err(()),
-        }
-    }
-}
+use super::Encoding;
 
 #[derive(PartialEq, Debug)] 
 pub struct UType {
-    pub mne: UTypeMne,
+    pub encoding: Encoding,
     pub rd: Reg,
     pub imm: Imm,
 }
 
 impl Instruction for UType {
     fn translate(&self) -> Vec<u8> {
-        let opcode = match self.mne {
-            UTypeMne::LUI => 0b0110111,
-            UTypeMne::AUIPC => 0b0010111,
-        };
+        let opcode = self.encoding.opcode;
 
-        let result: u32 = 0 | (self.imm << 12) | (self.rd << 7) | opcode;
+        let result: u32 = (self.imm << 12) | (self.rd << 7) | opcode;
 
         result.to_be_bytes().to_vec()
     }
@@ -43,11 +22,12 @@ impl Instruction for UType {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::instructions::isa::lookup;
 
     #[test]
     fn lui_test() {
         let instruction = UType {
-            mne: UTypeMne::LUI,
+            encoding: lookup("lui").unwrap().encoding,
             rd: 12,
             imm: 0xDEAD,
         };
@@ -59,7 +39,7 @@ mod tests {
     #[test]
     fn auipc_test() {
         let instruction = UType {
-            mne: UTypeMne::AUIPC,
+            encoding: lookup("auipc").unwrap().encoding,
             rd: 1,
             imm: 0xD1DF2,
         };
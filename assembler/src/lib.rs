@@ -0,0 +1,191 @@
+//! A RISC-V assembler for my CPU, as a library. [`assemble`] turns source
+//! into the binary the simulator runs, [`parse`] stops at the parsed
+//! [`FullFile`], and [`encode`] and [`decode`] work on one instruction at a
+//! time. The modules underneath are public too, for the tools built on
+//! them, but these are the parts that are meant to stay put.
+
+// Mnemonics are spelled the way the ISA manual does
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod assembler;
+pub mod custom;
+pub mod executable;
+pub mod explain;
+pub mod expr;
+pub mod formatter;
+pub mod hazard;
+pub mod instructions;
+pub mod line_table;
+pub mod link;
+pub mod linker_script;
+pub mod lsp;
+pub mod lint;
+pub mod object;
+pub mod parser;
+pub mod preprocessor;
+pub mod relax;
+pub mod stats;
+
+use std::fmt;
+
+pub use instructions::isa::decode;
+pub use instructions::{encode, InstructionData};
+pub use parser::FullFile;
+pub use relax::Relaxation;
+
+/// How to parse and assemble a source file.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub parser: parser::Options,
+    pub assembler: assembler::Options,
+}
+
+/// An assembled program.
+#[derive(Debug, PartialEq)]
+pub struct Program {
+    /// The bytes to load, from the lowest address of the stored sections.
+    pub binary: Vec<u8>,
+    /// The branches and jumps that were rewritten to reach their targets.
+    pub relaxed: Vec<Relaxation>,
+    /// The file it was assembled from.
+    pub file: FullFile,
+}
+
+/// Something wrong with the source, on a line of it if it's known which.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// One on `line`, where 0 is for code that isn't in the source.
+    pub fn at(line: usize, message: String) -> Diagnostic {
+        Diagnostic {
+            line: (line > 0).then_some(line),
+            message,
+        }
+    }
+}
+
+impl From<String> for Diagnostic {
+    fn from(message: String) -> Diagnostic {
+        Diagnostic {
+            line: None,
+            message,
+        }
+    }
+}
+
+impl From<Diagnostic> for String {
+    fn from(diagnostic: Diagnostic) -> String {
+        diagnostic.to_string()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Why a program couldn't be assembled.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl From<String> for Diagnostics {
+    fn from(message: String) -> Diagnostics {
+        Diagnostics(vec![Diagnostic::from(message)])
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Diagnostics {
+        Diagnostics(vec![diagnostic])
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<_> = self.0.iter().map(Diagnostic::to_string).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+pub fn parse(source: &str, options: &Options) -> Result<FullFile, Diagnostics> {
+    Ok(parser::parse_with(source, &options.parser)?)
+}
+
+/// Assembles a file that's already parsed, which may have been changed
+/// since, like by `hazard::insert_nops`.
+pub fn assemble_file(file: FullFile, options: &Options) -> Result<Program, Diagnostics> {
+    let (binary, relaxed) = assembler::assemble(&file, &options.assembler)?;
+    Ok(Program {
+        binary,
+        relaxed,
+        file,
+    })
+}
+
+pub fn assemble(source: &str, options: &Options) -> Result<Program, Diagnostics> {
+    assemble_file(parse(source, options)?, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_test() {
+        let program = assemble("loop: addi a0, a0, 1\njal zero, loop", &Options::default()).unwrap();
+        assert_eq!(program.binary, vec![0x00, 0x15, 0x05, 0x13, 0xFF, 0xDF, 0xF0, 0x6F]);
+        assert_eq!(program.file.sections[0].items.len(), 2);
+        assert!(program.relaxed.is_empty());
+
+        let err = assemble("addi a0, a0, 1\nbogus a0", &Options::default()).unwrap_err();
+        assert_eq!(
+            err,
+            Diagnostics(vec![Diagnostic {
+                line: Some(2),
+                message: "can't parse `bogus a0`".to_string(),
+            }])
+        );
+        let err = assemble("jal ra, missing", &Options::default()).unwrap_err();
+        assert_eq!(err.0[0].line, Some(1));
+        assert!(err.to_string().contains("missing"));
+    }
+
+    #[test]
+    fn encode_decode_test() {
+        let data = decode(0x00150513).unwrap();
+        assert_eq!(data.mne, "addi");
+        assert_eq!(encode(&data), Ok(0x00150513));
+        let bad = InstructionData {
+            rd: Some(32),
+            ..data
+        };
+        assert_eq!(encode(&bad), Err("x32 isn't a register".to_string()));
+        let custom = InstructionData {
+            rd: Some(1),
+            custom: Some(instructions::Encoding {
+                format: instructions::Format::U,
+                opcode: 0x0b,
+                funct3: 0,
+                funct7: 0x80,
+            }),
+            ..bad.clone()
+        };
+        assert_eq!(encode(&custom), Err("funct7 128 doesn't fit in 7 bits".to_string()));
+        let unknown = InstructionData {
+            mne: "fmadd".to_string(),
+            rd: Some(1),
+            ..bad
+        };
+        assert_eq!(encode(&unknown), Err("`fmadd` isn't an instruction".to_string()));
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+
+use crate::assembler::{self, item_addresses, item_label};
+use crate::parser::{FullFile, Item};
+use crate::Diagnostic;
+
+/// Where each instruction of a program came from, for showing the source
+/// line of the current PC. Stored as JSON next to the binary:
+///
+/// ```json
+/// {
+///   "file": "blink.s",
+///   "rows": [
+///     { "address": 4096, "size": 4, "line": 3, "column": 5, "function": "_start" }
+///   ]
+/// }
+/// ```
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct LineTable {
+    pub file: String,
+    /// By address.
+    pub rows: Vec<Row>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Row {
+    pub address: u32,
+    /// More than 4 for a branch or jump that was relaxed into two or three
+    /// instructions.
+    pub size: u32,
+    /// The file the instruction is in when it isn't `file`, like one that
+    /// `file` includes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    /// The last label before the instruction that isn't an `.L` local one.
+    pub function: Option<String>,
+}
+
+impl LineTable {
+    /// The row of the instruction `address` is in.
+    pub fn find(&self, address: u32) -> Option<&Row> {
+        let n = self.rows.partition_point(|row| row.address <= address);
+        self.rows[..n]
+            .last()
+            .filter(|row| address - row.address < row.size)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// The line table of `file`, linked on its own, which was read from `name`.
+/// Instructions that aren't in the source are left out, and ones from
+/// files it includes point at those.
+pub fn line_table(file: &FullFile, name: &str, options: &assembler::Options) -> Result<LineTable, Diagnostic> {
+    let addresses = item_addresses(file, options)?;
+    let mut rows = vec![];
+    for (section, addresses) in file.sections.iter().zip(&addresses) {
+        let mut function = None;
+        for (n, item) in section.items.iter().enumerate() {
+            if let Some(label) = item_label(item).filter(|label| !label.starts_with(".L")) {
+                function = Some(label.to_string());
+            }
+            let text = match item {
+                Item::Text(text) if text.location.line > 0 => text,
+                _ => continue,
+            };
+            let (origin, line) = file.origin(text.location.line);
+            rows.push(Row {
+                address: addresses[n],
+                size: addresses[n + 1] - addresses[n],
+                file: origin.filter(|&origin| origin != name).map(String::from),
+                line,
+                column: text.location.column,
+                function: function.clone(),
+            });
+        }
+    }
+    rows.sort_by_key(|row| row.address);
+    Ok(LineTable {
+        file: name.to_string(),
+        rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link;
+    use crate::parser::parse;
+    use crate::preprocessor::{self, preprocess_with};
+    use std::path::Path;
+
+    #[test]
+    fn line_table_test() {
+        let source = "\
+_start:
+    addi a0, zero, 1
+.Lloop: beq a0, zero, far
+        .data
+msg:    .word 1
+        .text
+far:
+  jal ra, _start
+";
+        let options = assembler::Options {
+            link: link::Options {
+                text_base: Some(0x1000),
+                ..link::Options::default()
+            },
+            ..assembler::Options::default()
+        };
+        let table = line_table(&parse(source).unwrap(), "test.s", &options).unwrap();
+        let rows: Vec<_> = table
+            .rows
+            .iter()
+            .map(|row| (row.address, row.size, row.line, row.column, row.function.as_deref()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (0x1000, 4, 2, 5, Some("_start")),
+                (0x1004, 4, 3, 9, Some("_start")),
+                (0x1008, 4, 8, 3, Some("far")),
+            ]
+        );
+        assert_eq!(table.find(0x1006).unwrap().line, 3);
+        assert_eq!(table.find(0x100C), None);
+        assert_eq!(table.find(0xFFC), None);
+    }
+
+    #[test]
+    fn included_test() {
+        let read = |path: &Path| match path.to_str() {
+            Some("inc.h") => Ok("addi a1, zero, 2\n".to_string()),
+            _ => Err("missing".to_string()),
+        };
+        let source = "addi a0, zero, 1\n#include \"inc.h\"\naddi a2, zero, 3\n";
+        let preprocessed = preprocess_with(source, "main.S", &preprocessor::Options::default(), &read).unwrap();
+        let mut file = parse(&preprocessed.source).unwrap();
+        preprocessed.add_origins(&mut file);
+        let table = line_table(&file, "main.S", &assembler::Options::default()).unwrap();
+        let rows: Vec<_> = table.rows.iter().map(|row| (row.file.as_deref(), row.line)).collect();
+        assert_eq!(rows, vec![(None, 1), (Some("inc.h"), 1), (None, 3)]);
+        let json = table.to_json();
+        assert!(json.contains("\"file\": \"inc.h\",\n      \"line\": 1"), "{}", json);
+        assert_eq!(json.matches("\"file\"").count(), 2);
+    }
+}
@@ -0,0 +1,692 @@
+use std::collections::HashMap;
+
+use crate::assembler::nop;
+use crate::expr::{hi, lo, Reloc};
+use crate::linker_script::LinkerScript;
+use crate::object::{Definition, Field, Object, Relocation, Target};
+use crate::relax;
+
+/// Where the first section goes when nothing says otherwise.
+const START_ADDRESS: u32 = 0x00000000;
+
+/// A larger hole between two stored sections is almost certainly a mistake,
+/// like a section that needs a `load` region to sit next to the others.
+/// Sections the options place that far away are stored next to the others
+/// instead.
+const MAX_IMAGE_GAP: u32 = 16 << 20;
+
+/// Where the sections go in memory.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Address of .text.
+    pub text_base: Option<u32>,
+    /// Address of .data.
+    pub data_base: Option<u32>,
+    /// Places sections in memory regions, and takes precedence over the
+    /// bases for the sections it mentions.
+    pub script: Option<LinkerScript>,
+}
+
+impl Options {
+    /// The symbol the program starts at: the linker script's entry, or
+    /// `_start`.
+    pub fn entry(&self) -> &str {
+        self.script.as_ref().and_then(|s| s.entry.as_deref()).unwrap_or("_start")
+    }
+}
+
+/// Parses an address given on the command line, in hex with `0x` or in
+/// decimal.
+pub fn parse_address(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("`{}` is not a 32-bit address", s))
+}
+
+/// The sections of the same name from every object, one after the other.
+struct OutputSection {
+    name: String,
+    executable: bool,
+    nobits: bool,
+    align: u32,
+    data: Vec<u8>,
+    size: u32,
+    start: u32,
+    /// Where the section is stored in the image, which is `start` unless it
+    /// is copied elsewhere before it runs.
+    load: u32,
+}
+
+/// The output section each section of each object went to, and its offset
+/// in there.
+type Pieces = Vec<Vec<(usize, u32)>>;
+
+type Symbols = HashMap<String, i64>;
+
+/// Bytes needed to take `address` up to a multiple of `align`.
+pub fn padding(address: u32, align: u32) -> u32 {
+    (align - address % align) % align
+}
+
+fn merge(objects: &[Object]) -> Result<(Vec<OutputSection>, Pieces), String> {
+    let mut outputs: Vec<OutputSection> = vec![];
+    let mut pieces = vec![];
+    for object in objects {
+        let mut placed = vec![];
+        for section in &object.sections {
+            let index = match outputs.iter().position(|o| o.name == section.name) {
+                Some(index) => index,
+                None => {
+                    outputs.push(OutputSection {
+                        name: section.name.clone(),
+                        executable: section.executable,
+                        nobits: section.nobits,
+                        align: 1,
+                        data: vec![],
+                        size: 0,
+                        start: 0,
+                        load: 0,
+                    });
+                    outputs.len() - 1
+                }
+            };
+            let output = &mut outputs[index];
+            if output.nobits != section.nobits {
+                return Err(format!(
+                    "`{}` only reserves space in some of the objects",
+                    section.name
+                ));
+            }
+            let offset = output
+                .size
+                .checked_add(padding(output.size, section.align))
+                .and_then(|offset| Some((offset, offset.checked_add(section.size)?)))
+                .ok_or_else(|| format!("`{}` doesn't fit in the address space", section.name))?;
+            output.align = output.align.max(section.align);
+            output.size = offset.1;
+            if !section.nobits {
+                let gap = offset.0 as usize - output.data.len();
+                if output.executable {
+                    output.data.resize(output.data.len() + gap % 4, 0);
+                    output.data.extend(nop().repeat(gap / 4));
+                } else {
+                    output.data.resize(offset.0 as usize, 0);
+                }
+                output.data.extend(&section.data);
+            }
+            placed.push((index, offset.0));
+        }
+        pieces.push(placed);
+    }
+    Ok((outputs, pieces))
+}
+
+/// The order sections are placed in: as they first appear, which puts .text
+/// first, with the ones that only reserve space at the end.
+fn section_order(sections: &[OutputSection]) -> Vec<usize> {
+    let (nobits, stored): (Vec<_>, Vec<_>) = (0..sections.len()).partition(|&i| sections[i].nobits);
+    stored.into_iter().chain(nobits).collect()
+}
+
+/// Moves a region's cursor past `size` bytes at `address`, or says by how
+/// much they don't fit.
+fn claim(
+    cursors: &mut HashMap<String, u64>,
+    script: &LinkerScript,
+    region: &str,
+    section: &OutputSection,
+    address: u32,
+) -> Result<(), String> {
+    let end = address as u64 + section.size as u64;
+    let limit = script.memory[region].end();
+    if end > limit {
+        return Err(format!(
+            "`{}` overflows region `{}` by {} bytes",
+            section.name,
+            region,
+            end - limit
+        ));
+    }
+    cursors.insert(region.to_string(), end);
+    Ok(())
+}
+
+/// Places each section where the linker script or the options put it, or
+/// right after the previous one.
+fn place(sections: &mut [OutputSection], options: &Options) -> Result<(), String> {
+    let mut cursors = HashMap::new();
+    let mut address = START_ADDRESS;
+    for index in section_order(sections) {
+        let section = &mut sections[index];
+        let placement = options
+            .script
+            .as_ref()
+            .and_then(|script| Some((script, script.placement(&section.name)?)));
+        let base = match (placement, section.name.as_str()) {
+            (Some((script, placement)), _) => {
+                let region = &placement.region;
+                let origin = script.memory[region].origin as u64;
+                *cursors.get(region).unwrap_or(&origin) as u32
+            }
+            (None, ".text") => options.text_base.unwrap_or(address),
+            (None, ".data") => options.data_base.unwrap_or(address),
+            (None, _) => address,
+        };
+        let overflow = || format!("`{}` doesn't fit in the address space", section.name);
+        section.start = base
+            .checked_add(padding(base, section.align))
+            .ok_or_else(overflow)?;
+        section.load = section.start;
+        address = section.start.checked_add(section.size).ok_or_else(overflow)?;
+        if let Some((script, placement)) = placement {
+            claim(&mut cursors, script, &placement.region, section, section.start)?;
+            match &placement.load {
+                Some(load) if !section.nobits => {
+                    let origin = script.memory[load].origin as u64;
+                    let cursor = *cursors.get(load).unwrap_or(&origin) as u32;
+                    section.load = cursor + padding(cursor, 4);
+                    claim(&mut cursors, script, load, section, section.load)?;
+                }
+                _ => {}
+            }
+        }
+    }
+    check_overlaps(sections)
+}
+
+/// For a flat binary, a section the options put more than `MAX_IMAGE_GAP`
+/// from the ones stored before it, like `.data` in RAM after `.text` in
+/// ROM, is stored right after them and copied into place, as if it had a
+/// `load` region.
+fn store_together(sections: &mut [OutputSection], options: &Options) -> Result<(), String> {
+    // Where the sections stored so far start and end
+    let mut stored: Option<(u64, u64)> = None;
+    for index in section_order(sections) {
+        let section = &mut sections[index];
+        if section.nobits || section.size == 0 {
+            continue;
+        }
+        let scripted = options.script.as_ref().is_some_and(|script| script.placement(&section.name).is_some());
+        let (start, end) = (section.load as u64, section.load as u64 + section.size as u64);
+        let far = |(first, last): (u64, u64)| start > last + MAX_IMAGE_GAP as u64 || end + (MAX_IMAGE_GAP as u64) < first;
+        if let Some((_, last)) = stored.filter(|&stored| !scripted && far(stored)) {
+            section.load = u32::try_from(last + padding(last as u32, 4) as u64)
+                .map_err(|_| format!("`{}` doesn't fit in the address space", section.name))?;
+        }
+        let (load, size) = (section.load as u64, section.size as u64);
+        stored = Some(stored.map_or((load, load + size), |(first, last)| (first.min(load), last.max(load + size))));
+    }
+    check_overlaps(sections)
+}
+
+/// Errors if two sections share any addresses, either where they run or
+/// where they are stored.
+fn check_overlaps(sections: &[OutputSection]) -> Result<(), String> {
+    for load in [false, true] {
+        let mut ranges: Vec<_> = sections
+            .iter()
+            .filter(|section| section.size > 0 && !(load && section.nobits))
+            .map(|section| {
+                let start = if load { section.load } else { section.start };
+                (start, start as u64 + section.size as u64, &section.name)
+            })
+            .collect();
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            if (pair[1].0 as u64) < pair[0].1 {
+                return Err(format!("sections `{}` and `{}` overlap", pair[0].2, pair[1].2));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `__data_start` and friends for `.data`, so startup code can find a
+/// section, and copy it from where it's stored to where it runs.
+fn section_symbols(section: &OutputSection) -> [(String, u32); 3] {
+    let name = section.name.trim_start_matches('.').replace('.', "_");
+    [
+        (format!("__{}_start", name), section.start),
+        (format!("__{}_end", name), section.start + section.size),
+        (format!("__{}_load", name), section.load),
+    ]
+}
+
+/// Address of an object's section.
+fn section_address(outputs: &[OutputSection], pieces: &[(usize, u32)], index: usize) -> u32 {
+    let (output, offset) = pieces[index];
+    outputs[output].start + offset
+}
+
+fn definition_value(
+    definition: &Definition,
+    outputs: &[OutputSection],
+    pieces: &[(usize, u32)],
+) -> i64 {
+    match definition {
+        Definition::Absolute(value) => *value,
+        Definition::Section { index, offset } => {
+            section_address(outputs, pieces, *index) as i64 + offset
+        }
+    }
+}
+
+/// Every global symbol, and the ones the linker provides unless an object
+/// defines them.
+fn global_symbols(
+    objects: &[Object],
+    outputs: &[OutputSection],
+    pieces: &Pieces,
+) -> Result<Symbols, String> {
+    let mut symbols = Symbols::new();
+    for (object, pieces) in objects.iter().zip(pieces) {
+        for symbol in object.symbols.iter().filter(|s| s.global) {
+            let value = match &symbol.definition {
+                Some(definition) => definition_value(definition, outputs, pieces),
+                None => continue,
+            };
+            if symbols.insert(symbol.name.clone(), value).is_some() {
+                return Err(format!("symbol `{}` is defined more than once", symbol.name));
+            }
+        }
+    }
+    for section in outputs {
+        for (name, value) in section_symbols(section) {
+            symbols.entry(name).or_insert(value as i64);
+        }
+    }
+    Ok(symbols)
+}
+
+/// The immediate for `field`, from the address it refers to and the address
+/// of the instruction.
+fn relocated(relocation: &Relocation, value: i64, pc: u32) -> i64 {
+    match (relocation.reloc, relocation.field) {
+        (Some(Reloc::Hi), _) => hi(value),
+        (Some(Reloc::Lo), _) => lo(value),
+        (Some(Reloc::PcrelHi), _) => hi(value - pc as i64),
+        (Some(Reloc::PcrelLo), _) => lo(value),
+        (None, Field::B | Field::J) => value - pc as i64,
+        (None, _) => value,
+    }
+}
+
+/// Writes `value` into the bits of `field` of the big endian word at the
+/// start of `bytes`, keeping the others.
+fn patch(bytes: &mut [u8], field: Field, value: i64) {
+    let v = value as u32;
+    let (mask, bits) = match field {
+        Field::B => (
+            0xFE000F80,
+            ((v >> 12) & 1) << 31 | ((v >> 5) & 0x3F) << 25 | ((v >> 1) & 0xF) << 8 | ((v >> 11) & 1) << 7,
+        ),
+        Field::J => (
+            0xFFFFF000,
+            ((v >> 20) & 1) << 31 | ((v >> 1) & 0x3FF) << 21 | ((v >> 11) & 1) << 20 | ((v >> 12) & 0xFF) << 12,
+        ),
+        Field::U => (0xFFFFF000, v << 12),
+        Field::I => (0xFFF00000, v << 20),
+        Field::S => (0xFE000F80, ((v >> 5) & 0x7F) << 25 | (v & 0x1F) << 7),
+        Field::Word => (0xFFFFFFFF, v),
+    };
+    let word = u32::from_be_bytes(bytes[..4].try_into().unwrap());
+    bytes[..4].copy_from_slice(&((word & !mask) | (bits & mask)).to_be_bytes());
+}
+
+/// Applies the relocations of one object.
+fn relocate(
+    object: &Object,
+    pieces: &[(usize, u32)],
+    outputs: &mut [OutputSection],
+    symbols: &Symbols,
+) -> Result<(), String> {
+    let target = |outputs: &[OutputSection], target: &Target| match target {
+        Target::Section(index) => Ok(section_address(outputs, pieces, *index) as i64),
+        Target::Symbol(name) => symbols
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("undefined symbol `{}`", name)),
+        Target::Absolute => Ok(0),
+    };
+    for (index, section) in object.sections.iter().enumerate() {
+        for relocation in &section.relocations {
+            let pc = section_address(outputs, pieces, index) + relocation.offset;
+            let context = |e| format!("{:#010x} in `{}`: {}", pc, section.name, e);
+            let mut value = target(outputs, &relocation.target).map_err(context)? + relocation.addend;
+            if relocation.reloc == Some(Reloc::PcrelLo) {
+                // The target is the auipc, which has the real one
+                let auipc = value;
+                let hi = match &relocation.target {
+                    Target::Section(index) => object.sections[*index]
+                        .relocations
+                        .iter()
+                        .find(|r| r.reloc == Some(Reloc::PcrelHi) && r.offset as i64 == relocation.addend),
+                    Target::Symbol(_) | Target::Absolute => None,
+                };
+                let hi = hi.ok_or_else(|| context(format!("no %pcrel_hi at {:#010x} for %pcrel_lo", auipc)))?;
+                value = target(outputs, &hi.target).map_err(context)? + hi.addend - auipc;
+            }
+            let value = relocated(relocation, value, pc);
+            if let (None, Field::B | Field::J) = (relocation.reloc, relocation.field) {
+                // Only the assembler can relax, and only within a section
+                if !relax::reaches(relocation.field == Field::B, value) {
+                    return Err(context(format!("offset {} is out of range", value)));
+                }
+            }
+            let (output, offset) = pieces[index];
+            let at = (offset + relocation.offset) as usize;
+            patch(&mut outputs[output].data[at..], relocation.field, value);
+        }
+    }
+    Ok(())
+}
+
+/// The value of a global symbol, or of a local one from the first object
+/// that defines it.
+fn find_symbol(
+    name: &str,
+    objects: &[Object],
+    (outputs, pieces): (&[OutputSection], &Pieces),
+    symbols: &Symbols,
+) -> Option<i64> {
+    let local = || {
+        objects.iter().zip(pieces).find_map(|(object, pieces)| {
+            let symbol = object.symbols.iter().find(|s| s.name == name)?;
+            Some(definition_value(symbol.definition.as_ref()?, outputs, pieces))
+        })
+    };
+    symbols.get(name).copied().or_else(local)
+}
+
+/// Every label but the `.L` local ones, by address.
+fn labels(objects: &[Object], outputs: &[OutputSection], pieces: &Pieces) -> Vec<(String, u32)> {
+    let mut labels: Vec<_> = objects
+        .iter()
+        .zip(pieces)
+        .flat_map(|(object, pieces)| {
+            object.symbols.iter().filter_map(move |symbol| match &symbol.definition {
+                Some(definition @ Definition::Section { .. }) if !symbol.name.starts_with(".L") => {
+                    Some((symbol.name.clone(), definition_value(definition, outputs, pieces) as u32))
+                }
+                _ => None,
+            })
+        })
+        .collect();
+    labels.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    labels
+}
+
+/// The address of the entry symbol, if there is one. It doesn't need to be
+/// global when only one object defines it, but the linker script's entry
+/// has to be defined.
+fn entry_address(
+    options: &Options,
+    objects: &[Object],
+    (outputs, pieces): (&[OutputSection], &Pieces),
+    symbols: &Symbols,
+) -> Result<Option<u32>, String> {
+    let address = find_symbol(options.entry(), objects, (outputs, pieces), symbols);
+    match options.script.as_ref().and_then(|s| s.entry.as_ref()) {
+        Some(entry) if address.is_none() => Err(format!("entry: undefined symbol `{}`", entry)),
+        _ => Ok(address.map(|address| address as u32)),
+    }
+}
+
+/// A flat binary starts running at its first byte, so that's where the
+/// linker script's entry symbol has to be.
+fn check_entry(options: &Options, entry: Option<u32>, image_start: u32) -> Result<(), String> {
+    match (options.script.as_ref().and_then(|s| s.entry.as_ref()), entry) {
+        (Some(name), Some(address)) if address != image_start => Err(format!(
+            "entry `{}` is at {:#010x}, but the binary starts at {:#010x}",
+            name, address, image_start
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Every stored section at its load address, starting from the lowest one.
+fn image(outputs: &[OutputSection]) -> Result<(u32, Vec<u8>), String> {
+    let mut stored: Vec<_> = outputs
+        .iter()
+        .filter(|section| !section.nobits && section.size > 0)
+        .collect();
+    stored.sort_by_key(|section| section.load);
+    let image_start = stored.first().map_or(START_ADDRESS, |section| section.load);
+
+    let mut binary = vec![];
+    for (n, section) in stored.iter().enumerate() {
+        let offset = (section.load - image_start) as usize;
+        if offset - binary.len() > MAX_IMAGE_GAP as usize {
+            return Err(format!(
+                "`{}` is stored {:#x} bytes past `{}`, give one of them a `load` region \
+                 in the linker script to store it next to the other",
+                section.name,
+                offset - binary.len(),
+                stored[n - 1].name
+            ));
+        }
+        binary.resize(offset, 0);
+        binary.extend(&section.data);
+        binary.resize(offset + section.size as usize, 0);
+    }
+    Ok((image_start, binary))
+}
+
+/// Where each section of each object is placed, as `link` would place it.
+pub fn section_addresses(objects: &[Object], options: &Options) -> Result<Vec<Vec<u32>>, String> {
+    let (mut outputs, pieces) = merge(objects)?;
+    place(&mut outputs, options)?;
+    Ok(pieces
+        .iter()
+        .map(|pieces| (0..pieces.len()).map(|index| section_address(&outputs, pieces, index)).collect())
+        .collect())
+}
+
+/// Where an output section ended up.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Placement {
+    pub name: String,
+    pub executable: bool,
+    pub nobits: bool,
+    pub start: u32,
+    pub load: u32,
+    pub size: u32,
+}
+
+/// A linked program.
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    /// The lowest load address of the stored sections, where `binary`
+    /// starts.
+    pub start: u32,
+    pub binary: Vec<u8>,
+    /// In the order they were placed.
+    pub sections: Vec<Placement>,
+    /// The linker script's entry symbol, or `_start`, or where the image
+    /// starts if there's neither.
+    pub entry: u32,
+    /// Every label but the `.L` local ones, by address.
+    pub labels: Vec<(String, u32)>,
+}
+
+impl Image {
+    /// The bytes at run time `address`, if a stored section is there.
+    pub fn bytes_at(&self, address: u32, len: u32) -> Option<&[u8]> {
+        let section = self.sections.iter().find(|section| {
+            !section.nobits
+                && address >= section.start
+                && u64::from(address) + u64::from(len) <= u64::from(section.start) + u64::from(section.size)
+        })?;
+        let offset = (section.load - self.start + (address - section.start)) as usize;
+        self.binary.get(offset..offset + len as usize)
+    }
+}
+
+/// Combines objects into a binary: sections of the same name are joined in
+/// the order of the objects, placed according to `options`, and every
+/// relocation is patched now that its target has an address.
+pub fn link(objects: &[Object], options: &Options) -> Result<Vec<u8>, String> {
+    Ok(link_image(objects, options)?.binary)
+}
+
+/// Every global symbol of the objects once linked, with the ones the linker
+/// provides, like `__data_load`.
+pub fn linked_symbols(objects: &[Object], options: &Options) -> Result<HashMap<String, i64>, String> {
+    let (mut outputs, pieces) = merge(objects)?;
+    place(&mut outputs, options)?;
+    global_symbols(objects, &outputs, &pieces)
+}
+
+/// Merges the objects' sections, places them and patches the relocations.
+/// A flat binary also has its stored sections kept together.
+fn link_outputs(objects: &[Object], options: &Options, flat: bool) -> Result<(Vec<OutputSection>, Pieces, Symbols), String> {
+    let (mut outputs, pieces) = merge(objects)?;
+    place(&mut outputs, options)?;
+    if flat {
+        store_together(&mut outputs, options)?;
+    }
+    let symbols = global_symbols(objects, &outputs, &pieces)?;
+    for (object, pieces) in objects.iter().zip(&pieces) {
+        relocate(object, pieces, &mut outputs, &symbols)?;
+    }
+    Ok((outputs, pieces, symbols))
+}
+
+fn placement(section: &OutputSection) -> Placement {
+    Placement {
+        name: section.name.clone(),
+        executable: section.executable,
+        nobits: section.nobits,
+        start: section.start,
+        load: section.load,
+        size: section.size,
+    }
+}
+
+/// Links like `link`, also returning where each section went.
+pub fn link_image(objects: &[Object], options: &Options) -> Result<Image, String> {
+    let (outputs, pieces, symbols) = link_outputs(objects, options, true)?;
+    let (image_start, binary) = image(&outputs)?;
+    let entry = entry_address(options, objects, (&outputs, &pieces), &symbols)?;
+    check_entry(options, entry, image_start)?;
+    Ok(Image {
+        start: image_start,
+        binary,
+        sections: outputs.iter().map(placement).collect(),
+        entry: entry.unwrap_or(image_start),
+        labels: labels(objects, &outputs, &pieces),
+    })
+}
+
+/// A linked program whose sections are kept apart, each stored at its own
+/// load address, for a container that has a place for each.
+#[derive(Debug, PartialEq)]
+pub struct Sections {
+    /// In the order they were placed, with the bytes of the stored ones.
+    pub sections: Vec<(Placement, Vec<u8>)>,
+    /// The linker script's entry symbol, or `_start`, or the lowest load
+    /// address if there's neither.
+    pub entry: u32,
+    /// Every label but the `.L` local ones, by address.
+    pub labels: Vec<(String, u32)>,
+}
+
+/// Links like `link_image`, but without putting the sections in one binary,
+/// so they can be any distance apart and the entry can be anywhere.
+pub fn link_sections(objects: &[Object], options: &Options) -> Result<Sections, String> {
+    let (outputs, pieces, symbols) = link_outputs(objects, options, false)?;
+    let entry = entry_address(options, objects, (&outputs, &pieces), &symbols)?;
+    let lowest = outputs
+        .iter()
+        .filter(|section| !section.nobits && section.size > 0)
+        .map(|section| section.load)
+        .min()
+        .unwrap_or(START_ADDRESS);
+    Ok(Sections {
+        entry: entry.unwrap_or(lowest),
+        labels: labels(objects, &outputs, &pieces),
+        sections: outputs.into_iter().map(|section| (placement(&section), section.data)).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{self, object};
+    use crate::parser::parse;
+
+    fn objects(sources: &[&str]) -> Vec<Object> {
+        sources
+            .iter()
+            .map(|source| object(&parse(source).unwrap(), &assembler::Options::default()).unwrap().0)
+            .collect()
+    }
+
+    fn words(binary: &[u8]) -> Vec<u32> {
+        binary
+            .chunks(4)
+            .map(|w| u32::from_be_bytes(w.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn link_test() {
+        let objects = objects(&[
+            "
+            .globl main
+            main: jal ra, square
+            lui a0, %hi(result)
+            sw a0, %lo(result)(a0)
+            beq a0, zero, main
+            ",
+            "
+            .globl square, result
+            square: jalr zero, 0(ra)
+            .data
+            result: .word square
+            ",
+        ]);
+        let binary = link(&objects, &Options::default()).unwrap();
+        // square is at 0x10, result at 0x14
+        assert_eq!(
+            words(&binary),
+            vec![0x010000EF, 0x00000537, 0x00A52A23, 0xFE050AE3, 0x00008067, 0x00000010]
+        );
+    }
+
+    #[test]
+    fn link_errors_test() {
+        let err = link(&objects(&["jal ra, missing"]), &Options::default()).unwrap_err();
+        assert!(err.contains("undefined symbol `missing`"));
+        // Labels aren't global unless they say so
+        let objects = objects(&["jal ra, f", "f: jalr zero, 0(ra)"]);
+        let err = link(&objects, &Options::default()).unwrap_err();
+        assert!(err.contains("undefined symbol `f`"));
+        let objects = super::tests::objects(&[".globl f\nf:", ".globl f\nf:"]);
+        let err = link(&objects, &Options::default()).unwrap_err();
+        assert!(err.contains("`f` is defined more than once"));
+        // Branches to other objects can't be relaxed
+        let objects = super::tests::objects(&["beq a0, zero, f", ".space 0x1000\n.globl f\nf:"]);
+        let err = link(&objects, &Options::default()).unwrap_err();
+        assert!(err.contains("0x00000000 in `.text`: offset 4100 is out of range"));
+    }
+
+    #[test]
+    fn patch_test() {
+        let mut bytes = 0x00000063u32.to_be_bytes();
+        patch(&mut bytes, Field::B, -4);
+        assert_eq!(u32::from_be_bytes(bytes), 0xFE000EE3);
+        let mut bytes = 0x0000006Fu32.to_be_bytes();
+        patch(&mut bytes, Field::J, 0x800);
+        assert_eq!(u32::from_be_bytes(bytes), 0x0010006F);
+    }
+
+    #[test]
+    fn parse_address_test() {
+        assert_eq!(parse_address("0x8000_0000"), Ok(0x80000000));
+        assert_eq!(parse_address("4096"), Ok(4096));
+        assert!(parse_address("0x1_0000_0000").is_err());
+    }
+}
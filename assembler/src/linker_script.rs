@@ -0,0 +1,156 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// A small linker script, written in TOML:
+///
+/// ```toml
+/// entry = "_start"
+///
+/// [memory]
+/// ROM = { origin = 0x80000000, length = 0x10000 }
+/// RAM = { origin = 0x10000000, length = 0x4000 }
+///
+/// [[sections]]
+/// name = ".text*"
+/// region = "ROM"
+///
+/// [[sections]]
+/// name = ".data"
+/// region = "RAM"
+/// load = "ROM"
+/// ```
+#[derive(Debug, Deserialize, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct LinkerScript {
+    /// Symbol execution starts at.
+    pub entry: Option<String>,
+    #[serde(default)]
+    pub memory: HashMap<String, Region>,
+    /// Checked in order, the first one that matches a section places it.
+    #[serde(default)]
+    pub sections: Vec<Placement>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    pub origin: u32,
+    pub length: u32,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Placement {
+    /// A section name, or a prefix of one followed by `*`.
+    pub name: String,
+    /// Region the section runs from.
+    pub region: String,
+    /// Region the section is stored in, when it is copied to `region` at
+    /// startup, like initialised data kept in ROM.
+    pub load: Option<String>,
+}
+
+impl Region {
+    /// First address past the end of the region.
+    pub fn end(&self) -> u64 {
+        self.origin as u64 + self.length as u64
+    }
+}
+
+impl Placement {
+    pub fn matches(&self, section: &str) -> bool {
+        match self.name.strip_suffix('*') {
+            Some(prefix) => section.starts_with(prefix),
+            None => section == self.name,
+        }
+    }
+}
+
+impl LinkerScript {
+    pub fn from_toml(s: &str) -> Result<LinkerScript, String> {
+        let script: LinkerScript = toml::from_str(s).map_err(|e| e.to_string())?;
+        for placement in &script.sections {
+            for region in [Some(&placement.region), placement.load.as_ref()]
+                .into_iter()
+                .flatten()
+            {
+                if !script.memory.contains_key(region) {
+                    return Err(format!(
+                        "`{}` is placed in region `{}`, which isn't in [memory]",
+                        placement.name, region
+                    ));
+                }
+            }
+        }
+        Ok(script)
+    }
+
+    pub fn read(path: &str) -> Result<LinkerScript, String> {
+        let script = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        LinkerScript::from_toml(&script)
+    }
+
+    pub fn placement(&self, section: &str) -> Option<&Placement> {
+        self.sections.iter().find(|p| p.matches(section))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"
+        entry = "_start"
+
+        [memory]
+        ROM = { origin = 0x80000000, length = 0x10000 }
+        RAM = { origin = 0x10000000, length = 0x4000 }
+
+        [[sections]]
+        name = ".text*"
+        region = "ROM"
+
+        [[sections]]
+        name = ".data"
+        region = "RAM"
+        load = "ROM"
+    "#;
+
+    #[test]
+    fn from_toml_test() {
+        let script = LinkerScript::from_toml(SCRIPT).unwrap();
+        assert_eq!(script.entry, Some("_start".to_string()));
+        assert_eq!(
+            script.memory["ROM"],
+            Region {
+                origin: 0x80000000,
+                length: 0x10000
+            }
+        );
+        assert_eq!(script.memory["ROM"].end(), 0x80010000);
+        assert_eq!(script.sections[1].load, Some("ROM".to_string()));
+    }
+
+    #[test]
+    fn placement_test() {
+        let script = LinkerScript::from_toml(SCRIPT).unwrap();
+        assert_eq!(script.placement(".text").unwrap().region, "ROM");
+        assert_eq!(script.placement(".text.init").unwrap().region, "ROM");
+        assert_eq!(script.placement(".data").unwrap().region, "RAM");
+        assert_eq!(script.placement(".data1"), None);
+        assert_eq!(script.placement(".bss"), None);
+    }
+
+    #[test]
+    fn unknown_region_test() {
+        let err = LinkerScript::from_toml(
+            r#"
+            [[sections]]
+            name = ".text"
+            region = "FLASH"
+            "#,
+        )
+        .unwrap_err();
+        assert!(err.contains("region `FLASH`"));
+    }
+}
@@ -0,0 +1,467 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::assembler::{item_label, item_offsets};
+use crate::expr::{Expr, Reloc};
+use crate::instructions::isa::{memory_access, Access};
+use crate::instructions::InstructionData;
+use crate::parser::{reg_name, Binding, FullFile, Item, Text};
+use crate::Diagnostic;
+
+/// What a warning is about, so it can be turned off with `--allow`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Code {
+    /// An instruction writes `zero`, so the result is lost.
+    WriteZero,
+    /// A label nothing refers to.
+    UnusedLabel,
+    /// Code straight after a `jal zero` or `jalr zero` with no label to get
+    /// to it.
+    Unreachable,
+    /// A load or store from an address that isn't a multiple of its size.
+    Misaligned,
+    /// A shift by 32 or more.
+    Shamt,
+    /// A function changes an `s` register without saving it on the stack.
+    CalleeSaved,
+}
+
+const CODES: [Code; 6] = [
+    Code::WriteZero,
+    Code::UnusedLabel,
+    Code::Unreachable,
+    Code::Misaligned,
+    Code::Shamt,
+    Code::CalleeSaved,
+];
+
+impl Code {
+    pub fn name(self) -> &'static str {
+        match self {
+            Code::WriteZero => "write-zero",
+            Code::UnusedLabel => "unused-label",
+            Code::Unreachable => "unreachable",
+            Code::Misaligned => "misaligned",
+            Code::Shamt => "shamt",
+            Code::CalleeSaved => "callee-saved",
+        }
+    }
+}
+
+/// Parses a warning code given to `--allow`.
+pub fn parse_code(s: &str) -> Result<Code, String> {
+    CODES
+        .into_iter()
+        .find(|code| code.name() == s)
+        .ok_or_else(|| {
+            let names: Vec<_> = CODES.iter().map(|code| code.name()).collect();
+            format!(
+                "unknown warning `{}`, expected one of {}",
+                s,
+                names.join(", ")
+            )
+        })
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warning {
+    pub code: Code,
+    /// Of the statement it is about, like the line of an error.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: warning: {} [{}]", self.line, self.message, self.code.name())
+    }
+}
+
+/// Registers a function has to give back the way it found them.
+const SAVED: [u32; 12] = [8, 9, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27];
+const RA: u32 = 1;
+const SP: u32 = 2;
+
+fn mne(text: &Text) -> String {
+    text.instruction.mne.to_lowercase()
+}
+
+fn is_load(instruction: &InstructionData) -> bool {
+    matches!(memory_access(instruction), Some((Access::Load, _)))
+}
+
+fn is_store(instruction: &InstructionData) -> bool {
+    matches!(memory_access(instruction), Some((Access::Store, _)))
+}
+
+fn is_jump(mne: &str) -> bool {
+    mne == "jal" || mne == "jalr"
+}
+
+fn item_refs(item: &Item) -> Vec<&str> {
+    let exprs: Vec<&Expr> = match item {
+        Item::Text(text) => {
+            let mut refs: Vec<&str> = text.label_dst.iter().map(String::as_str).collect();
+            refs.extend(text.imm_expr.iter().flat_map(Expr::symbols));
+            return refs;
+        }
+        Item::Data(data) => data.exprs.iter().collect(),
+        Item::Label(..) => vec![],
+        Item::Align { bytes: size, fill, .. } | Item::Space { size, fill, .. } => {
+            std::iter::once(size).chain(fill).collect()
+        }
+        Item::Org(offset, _) => vec![offset],
+        Item::Fill {
+            repeat,
+            size,
+            value,
+            ..
+        } => vec![repeat, size, value],
+    };
+    exprs.into_iter().flat_map(Expr::symbols).collect()
+}
+
+/// Collects warnings for one section, walking its items in order.
+struct Linter {
+    warnings: Vec<Warning>,
+    /// Register values known from `lui` and `addi` since the last label.
+    known: [Option<i64>; 32],
+    /// Nothing falls through to here, and no label has been seen since.
+    unreachable: bool,
+}
+
+impl Linter {
+    fn warn(&mut self, code: Code, line: usize, message: String) {
+        self.warnings.push(Warning { code, line, message });
+    }
+
+    fn forget(&mut self) {
+        self.known = [None; 32];
+        self.known[0] = Some(0);
+    }
+
+    fn instruction(&mut self, text: &Text) {
+        let line = text.location.line;
+        let mne = mne(text);
+        let i = &text.instruction;
+        if self.unreachable {
+            self.warn(
+                Code::Unreachable,
+                line,
+                format!("`{}` can never run", mne),
+            );
+            self.unreachable = false;
+        }
+        if i.rd == Some(0)
+            && !is_jump(&mne)
+            && !(mne == "addi" && i.rs1 == Some(0) && i.imm == Some(0))
+        {
+            self.warn(
+                Code::WriteZero,
+                line,
+                format!("`{}` writes `zero`, so its result is lost", mne),
+            );
+        }
+        if matches!(mne.as_ref(), "slli" | "srli" | "srai") {
+            if let Some(shamt) = i.imm.filter(|&shamt| shamt >= 32) {
+                self.warn(
+                    Code::Shamt,
+                    line,
+                    format!("`{}` by {} is more than the 32 bits there are", mne, shamt),
+                );
+            }
+        }
+        if let Some((_, size)) = memory_access(i) {
+            let base = i.rs1.and_then(|rs1| self.known[rs1 as usize]);
+            if let (Some(base), Some(imm)) = (base, i.imm) {
+                let address = base + imm as i32 as i64;
+                if address % size as i64 != 0 {
+                    self.warn(
+                        Code::Misaligned,
+                        line,
+                        format!("`{}` at {:#x} isn't {} byte aligned", mne, address, size),
+                    );
+                }
+            }
+        }
+
+        let value = match (mne.as_ref(), i.imm) {
+            ("lui", Some(imm)) => Some(((imm as i64) << 12) as i32 as i64),
+            ("addi", Some(imm)) => i
+                .rs1
+                .and_then(|rs1| self.known[rs1 as usize])
+                .map(|base| base + imm as i64),
+            _ => None,
+        };
+        if let Some(rd) = i.rd.filter(|&rd| rd != 0) {
+            self.known[rd as usize] = value;
+        }
+        if is_jump(&mne) || i.rd.is_none() && !is_store(i) {
+            // Calls change registers and branches join paths
+            self.forget();
+        }
+        self.unreachable = is_jump(&mne) && i.rd == Some(0);
+    }
+}
+
+/// Where a call goes: the target of a `jal ra`, or of the `lui ra` or
+/// `auipc ra` before a `jalr ra`, which is what `call` expands to.
+fn call_target<'a>(previous: Option<&'a Item>, item: &'a Item) -> Option<&'a str> {
+    let text = match item {
+        Item::Text(text) if text.instruction.rd == Some(RA) => text,
+        _ => return None,
+    };
+    match (mne(text).as_str(), previous) {
+        ("jal", _) => text.label_dst.as_deref(),
+        ("jalr", Some(Item::Text(upper)))
+            if text.instruction.rs1 == Some(RA)
+                && upper.instruction.rd == Some(RA)
+                && matches!(mne(upper).as_str(), "lui" | "auipc") =>
+        {
+            match &upper.imm_expr {
+                Some(Expr::Reloc(Reloc::Hi | Reloc::PcrelHi, target)) => match target.as_ref() {
+                    Expr::Sym(name) => Some(name),
+                    _ => None,
+                },
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// The labels that start a function: ones called with `jal ra` or `call`
+/// and global ones, except the entry point, which nothing returns from.
+pub(crate) fn functions<'a>(file: &'a FullFile, entry: &str) -> HashSet<&'a str> {
+    let called = file.sections.iter().flat_map(|s| {
+        let previous = std::iter::once(None).chain(s.items.iter().map(Some));
+        previous.zip(&s.items).filter_map(|(previous, item)| call_target(previous, item))
+    });
+    called
+        .chain(globals(file))
+        .filter(|&name| name != entry)
+        .collect()
+}
+
+/// Warns about `s` registers a function writes but never stores to the
+/// stack.
+fn callee_saved(file: &FullFile, entry: &str, warnings: &mut Vec<Warning>) {
+    let functions = functions(file, entry);
+    for section in &file.sections {
+        let mut function: Option<&str> = None;
+        let mut saved = HashSet::new();
+        let mut written: Vec<(u32, usize, String)> = vec![];
+        let mut finish = |function: Option<&str>,
+                          saved: &HashSet<u32>,
+                          written: &mut Vec<(u32, usize, String)>| {
+            if let Some(name) = function {
+                for (reg, line, mne) in written.drain(..) {
+                    if !saved.contains(&reg) {
+                        warnings.push(Warning {
+                            code: Code::CalleeSaved,
+                            line,
+                            message: format!(
+                                "`{}` changes `{}`, but `{}` doesn't save it",
+                                mne,
+                                reg_name(reg),
+                                name
+                            ),
+                        });
+                    }
+                }
+            }
+            written.clear();
+        };
+        for item in &section.items {
+            if let Some(label) = item_label(item).filter(|label| functions.contains(label)) {
+                finish(function, &saved, &mut written);
+                function = Some(label);
+                saved.clear();
+            }
+            let text = match item {
+                Item::Text(text) => text,
+                _ => continue,
+            };
+            let mne = mne(text);
+            let i = &text.instruction;
+            if is_store(i) && i.rs1 == Some(SP) {
+                saved.extend(i.rs2);
+            }
+            let restores = is_load(i) && i.rs1 == Some(SP);
+            if let Some(rd) = i.rd.filter(|rd| SAVED.contains(rd) && !restores) {
+                if !written.iter().any(|(reg, ..)| *reg == rd) {
+                    written.push((rd, text.location.line, mne));
+                }
+            }
+        }
+        finish(function, &saved, &mut written);
+    }
+}
+
+/// The labels made visible to other objects with `.globl`.
+fn globals(file: &FullFile) -> impl Iterator<Item = &str> {
+    file.bindings
+        .iter()
+        .filter(|(_, binding)| *binding == Binding::Global)
+        .map(|(name, _)| name.as_str())
+}
+
+/// Looks for likely mistakes, leaving out the warnings in `allowed`.
+/// `entry` is the symbol the program starts at, which is used even though
+/// nothing refers to it.
+pub fn lint(file: &FullFile, allowed: &[Code], entry: &str) -> Result<Vec<Warning>, Diagnostic> {
+    // A file that doesn't lay out fails here, as `--lint` alone checks it
+    item_offsets(file)?;
+    let mut used: HashSet<&str> = file
+        .sections
+        .iter()
+        .flat_map(|s| &s.items)
+        .flat_map(item_refs)
+        .collect();
+    used.extend(file.consts.iter().flat_map(|c| c.expr.symbols()));
+    // Other objects can use global labels
+    used.extend(globals(file));
+    used.insert(entry);
+
+    let mut warnings = vec![];
+    for section in &file.sections {
+        let mut linter = Linter {
+            warnings: vec![],
+            known: [None; 32],
+            unreachable: false,
+        };
+        linter.forget();
+        for item in &section.items {
+            if let Some(label) = item_label(item) {
+                // Numeric labels are renamed with a `^`, and are meant to be throwaway
+                if !used.contains(label) && !label.contains('^') {
+                    linter.warn(
+                        Code::UnusedLabel,
+                        item.location().line,
+                        format!("`{}` is never used", label),
+                    );
+                }
+                linter.forget();
+                linter.unreachable = false;
+            }
+            match item {
+                Item::Text(text) => linter.instruction(text),
+                Item::Data(_) => linter.unreachable = false,
+                _ => {}
+            }
+        }
+        warnings.extend(linter.warnings);
+    }
+    callee_saved(file, entry, &mut warnings);
+
+    warnings.retain(|warning| !allowed.contains(&warning.code));
+    warnings.sort_by_key(|warning| warning.line);
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, parse_with};
+
+    fn codes(source: &str) -> Vec<(Code, usize)> {
+        lint(&parse(source).unwrap(), &[], "_start")
+            .unwrap()
+            .into_iter()
+            .map(|warning| (warning.code, warning.line))
+            .collect()
+    }
+
+    #[test]
+    fn lint_test() {
+        let source = "
+            _start: addi zero, zero, 0
+            add zero, a0, a1
+            slli a0, a0, 32
+            lui a0, 0x10000
+            addi a0, a0, 2
+            lw a1, 0(a0)
+            sh a1, 2(a0)
+            jal zero, _start
+            addi a0, a0, 1
+            unused: jalr zero, 0(ra)
+        ";
+        assert_eq!(
+            codes(source),
+            vec![
+                (Code::WriteZero, 3),
+                (Code::Shamt, 4),
+                (Code::Misaligned, 7),
+                (Code::Unreachable, 10),
+                (Code::UnusedLabel, 11),
+            ]
+        );
+        let file = parse(source).unwrap();
+        let warnings = lint(&file, &[Code::UnusedLabel, Code::Shamt], "_start").unwrap();
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(
+            warnings[1].to_string(),
+            "line 7: warning: `lw` at 0x10000002 isn't 4 byte aligned [misaligned]"
+        );
+    }
+
+    #[test]
+    fn callee_saved_test() {
+        let source = "
+            _start: jal ra, f
+            jal ra, g
+            f: addi sp, sp, -4
+            sw s0, 0(sp)
+            addi s0, zero, 1
+            lw s0, 0(sp)
+            addi sp, sp, 4
+            jalr zero, 0(ra)
+            g: addi s1, zero, 1
+            jalr zero, 0(ra)
+        ";
+        let warnings = lint(&parse(source).unwrap(), &[], "_start").unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].to_string(),
+            "line 10: warning: `addi` changes `s1`, but `g` doesn't save it [callee-saved]"
+        );
+
+        let source = source.replace("jal ra,", "call");
+        let pic = crate::parser::Options {
+            pic: true,
+            ..Default::default()
+        };
+        for file in [parse(&source).unwrap(), parse_with(&source, &pic).unwrap()] {
+            let warnings = lint(&file, &[], "_start").unwrap();
+            assert_eq!(
+                warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+                vec!["line 10: warning: `addi` changes `s1`, but `g` doesn't save it [callee-saved]"]
+            );
+        }
+    }
+
+    #[test]
+    fn unused_label_test() {
+        let source = "
+            .globl exported
+            .local helper
+            main: addi a0, zero, 1
+            exported: addi a0, zero, 2
+            helper: jalr zero, 0(ra)
+        ";
+        let unused = |entry| {
+            let warnings = lint(&parse(source).unwrap(), &[], entry).unwrap();
+            warnings.into_iter().filter(|w| w.code == Code::UnusedLabel).map(|w| w.message).collect::<Vec<_>>()
+        };
+        assert_eq!(unused("main"), vec!["`helper` is never used"]);
+        assert_eq!(unused("_start"), vec!["`main` is never used", "`helper` is never used"]);
+    }
+
+    #[test]
+    fn parse_code_test() {
+        assert_eq!(parse_code("write-zero"), Ok(Code::WriteZero));
+        assert!(parse_code("everything")
+            .unwrap_err()
+            .contains("callee-saved"));
+    }
+}
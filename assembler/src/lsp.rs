@@ -0,0 +1,653 @@
+//! A language server for editors, over stdio. It keeps the open files in
+//! memory and answers from them: diagnostics from assembling the file,
+//! definitions and references of labels and constants, hover for
+//! instructions and completion for mnemonics, directives, registers, CSRs
+//! and symbols.
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+use crate::instructions::encode;
+use crate::instructions::isa::{lookup, ISA};
+use crate::instructions::{Encoding, Format};
+use crate::parser::{self, comment_start, split_label, str_to_reg, Item, DIRECTIVES, PSEUDO_INSTRUCTIONS, REG_NAMES};
+use crate::{assemble, Diagnostics, Options};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Completion item kinds, as the protocol numbers them.
+const KEYWORD: u32 = 14;
+const VARIABLE: u32 = 6;
+const REFERENCE: u32 = 18;
+const CONSTANT: u32 = 21;
+
+/// The standard machine, supervisor and counter CSRs, with their addresses.
+const CSR_NAMES: [(&str, u32); 35] = [
+    ("mstatus", 0x300),
+    ("misa", 0x301),
+    ("medeleg", 0x302),
+    ("mideleg", 0x303),
+    ("mie", 0x304),
+    ("mtvec", 0x305),
+    ("mcounteren", 0x306),
+    ("mscratch", 0x340),
+    ("mepc", 0x341),
+    ("mcause", 0x342),
+    ("mtval", 0x343),
+    ("mip", 0x344),
+    ("mcycle", 0xB00),
+    ("minstret", 0xB02),
+    ("mcycleh", 0xB80),
+    ("minstreth", 0xB82),
+    ("mvendorid", 0xF11),
+    ("marchid", 0xF12),
+    ("mimpid", 0xF13),
+    ("mhartid", 0xF14),
+    ("sstatus", 0x100),
+    ("sie", 0x104),
+    ("stvec", 0x105),
+    ("sscratch", 0x140),
+    ("sepc", 0x141),
+    ("scause", 0x142),
+    ("stval", 0x143),
+    ("sip", 0x144),
+    ("satp", 0x180),
+    ("cycle", 0xC00),
+    ("time", 0xC01),
+    ("instret", 0xC02),
+    ("cycleh", 0xC80),
+    ("timeh", 0xC81),
+    ("instreth", 0xC82),
+];
+
+/// Reads one message, or `None` at the end of the input.
+pub fn read_message(reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Serves one client until it says `exit`. Returns the exit code, which is
+/// 1 if it didn't ask to shut down first.
+pub fn run(mut reader: impl BufRead, mut writer: impl Write) -> io::Result<i32> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(&mut reader)? {
+        let message = match serde_json::from_slice(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                write_message(&mut writer, &error(Value::Null, PARSE_ERROR, &e.to_string()))?;
+                continue;
+            }
+        };
+        if let Some(code) = server.exit_code(&message) {
+            return Ok(code);
+        }
+        for out in server.handle(&message) {
+            write_message(&mut writer, &out)?;
+        }
+    }
+    Ok(1)
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+/// A name in a file, by line and the bytes it takes.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Span {
+    line: usize,
+    start: usize,
+    end: usize,
+}
+
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$')
+}
+
+/// The words on a line outside its comment, strings and characters, with
+/// the byte each starts at.
+fn words(line: &str) -> Vec<(usize, &str)> {
+    let code = &line[..comment_start(line).unwrap_or(line.len())];
+    let mut words = vec![];
+    let mut quote = None;
+    let mut escape = false;
+    let mut start = None;
+    for (idx, c) in code.char_indices().chain([(code.len(), ' ')]) {
+        match quote {
+            Some(_) if escape => escape = false,
+            Some(_) if c == '\\' => escape = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if is_word(c) => {
+                start.get_or_insert(idx);
+            }
+            None => {
+                if let Some(start) = start.take() {
+                    words.push((start, &code[start..idx]));
+                }
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+            }
+        }
+    }
+    words
+}
+
+/// Where each label and `.equ`/`.set` constant is defined and used.
+/// Numeric local labels are left out, since `1b` depends on where it is.
+struct Symbols<'a> {
+    definitions: HashMap<&'a str, Span>,
+    constants: HashSet<&'a str>,
+    words: Vec<(Span, &'a str)>,
+}
+
+impl<'a> Symbols<'a> {
+    fn new(source: &'a str) -> Symbols<'a> {
+        let mut definitions = HashMap::new();
+        let mut constants = HashSet::new();
+        let mut all = vec![];
+        for (n, line) in source.lines().enumerate() {
+            let words = words(line);
+            for (i, &(start, word)) in words.iter().enumerate() {
+                let span = Span {
+                    line: n,
+                    start,
+                    end: start + word.len(),
+                };
+                let label = line[span.end..].starts_with(':');
+                let constant = i == 1 && [".equ", ".set"].iter().any(|d| words[0].1.eq_ignore_ascii_case(d));
+                let named = !word.starts_with(|c: char| c.is_ascii_digit());
+                if (label || constant) && named {
+                    definitions.entry(word).or_insert(span);
+                }
+                if constant && named {
+                    constants.insert(word);
+                }
+                all.push((span, word));
+            }
+        }
+        Symbols {
+            definitions,
+            constants,
+            words: all,
+        }
+    }
+
+    /// The word at byte `column` of `line`.
+    fn at(&self, line: usize, column: usize) -> Option<(Span, &'a str)> {
+        self.words
+            .iter()
+            .find(|(span, _)| span.line == line && span.start <= column && column <= span.end)
+            .copied()
+    }
+
+    fn references(&self, name: &str) -> impl Iterator<Item = Span> + '_ {
+        let name = name.to_string();
+        self.words
+            .iter()
+            .filter(move |(_, word)| *word == name)
+            .map(|(span, _)| *span)
+    }
+}
+
+/// The protocol counts columns in UTF-16 code units.
+fn utf16_column(line: &str, byte: usize) -> usize {
+    line[..byte.min(line.len())].encode_utf16().count()
+}
+
+fn byte_column(line: &str, utf16: usize) -> usize {
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= utf16 {
+            return idx;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn range(source: &str, span: Span) -> Value {
+    let line = source.lines().nth(span.line).unwrap_or("");
+    json!({
+        "start": { "line": span.line, "character": utf16_column(line, span.start) },
+        "end": { "line": span.line, "character": utf16_column(line, span.end) },
+    })
+}
+
+fn location(uri: &str, source: &str, span: Span) -> Value {
+    json!({ "uri": uri, "range": range(source, span) })
+}
+
+/// What assembling the file says is wrong with it, each error on the
+/// statement of its line. Errors without a line are about the file as a
+/// whole, like the entry point, so they aren't put on any line but come
+/// back as messages of their own.
+fn diagnostics(source: &str) -> (Vec<Value>, Vec<String>) {
+    match assemble(source, &Options::default()) {
+        Ok(_) => (vec![], vec![]),
+        Err(errors) => report(source, errors),
+    }
+}
+
+fn report(source: &str, errors: Diagnostics) -> (Vec<Value>, Vec<String>) {
+    let mut diagnostics = vec![];
+    let mut messages = vec![];
+    for diagnostic in errors.0 {
+        let line = match diagnostic.line {
+            Some(line) => line,
+            None => {
+                messages.push(diagnostic.message);
+                continue;
+            }
+        };
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let code = text[..comment_start(text).unwrap_or(text.len())].trim_end();
+        let span = Span {
+            line: line - 1,
+            start: code.len() - code.trim_start().len(),
+            end: code.len(),
+        };
+        diagnostics.push(json!({
+            "range": range(source, span),
+            "severity": 1,
+            "source": "riscv-assembler",
+            "message": diagnostic.message,
+        }));
+    }
+    (diagnostics, messages)
+}
+
+fn fields(encoding: &Encoding) -> String {
+    let mut fields = vec![format!("{:?} type", encoding.format), format!("opcode `{:#04x}`", encoding.opcode)];
+    if !matches!(encoding.format, Format::U | Format::J) {
+        fields.push(format!("funct3 `{:#05b}`", encoding.funct3));
+    }
+    if encoding.funct7 != 0 || encoding.format == Format::R {
+        fields.push(format!("funct7 `{:#04x}`", encoding.funct7));
+    }
+    fields.join(", ")
+}
+
+/// Markdown for the word under the cursor: what an instruction is and how
+/// it's encoded, with the word it assembles to when its operands are all
+/// known, where a symbol is defined, or which register a name is.
+fn hover(source: &str, symbols: &Symbols, span: Span, word: &str) -> Option<String> {
+    let line = source.lines().nth(span.line)?;
+    if let Some(definition) = symbols.definitions.get(word) {
+        let text = source.lines().nth(definition.line)?.trim();
+        return Some(format!("```\n{}\n```\nline {}", text, definition.line + 1));
+    }
+    let mut rest = line;
+    while let Some((_, after)) = split_label(rest) {
+        rest = after;
+    }
+    let mnemonic = rest.trim_start().starts_with(word) && line.len() - rest.trim_start().len() == span.start;
+    if let Some(opcode) = lookup(word).filter(|_| mnemonic) {
+        let mut text = format!(
+            "**{}** `{}`\n\n{}\n\n{}, extension {}",
+            opcode.mne,
+            opcode.syntax.operands(),
+            opcode.description,
+            fields(&opcode.encoding),
+            opcode.extension
+        );
+        let file = parser::parse(&line[..comment_start(line).unwrap_or(line.len())]).ok();
+        let instruction = file.as_ref().and_then(|file| {
+            file.sections[0].items.iter().find_map(|item| match item {
+                Item::Text(text) if text.imm_expr.is_none() && text.label_dst.is_none() => Some(&text.instruction),
+                _ => None,
+            })
+        });
+        if let Some(word) = instruction.and_then(|instruction| encode(instruction).ok()) {
+            text += &format!("\n\n`{:#010x}`", word);
+        }
+        return Some(text);
+    }
+    let reg = str_to_reg(word)?;
+    Some(format!("`x{}`, `{}`", reg, REG_NAMES[reg as usize]))
+}
+
+fn completion(label: &str, kind: u32, detail: &str) -> Value {
+    json!({ "label": label, "kind": kind, "detail": detail })
+}
+
+/// Mnemonics and directives for the first word of a statement, and
+/// registers, CSRs and symbols after it.
+fn completions(line: &str, symbols: &Symbols) -> Vec<Value> {
+    let mut rest = line;
+    while let Some((_, after)) = split_label(rest) {
+        rest = after;
+    }
+    if !rest.trim_start().contains(char::is_whitespace) {
+        let mnemonics = ISA
+            .iter()
+            .map(|opcode| (opcode.mne, opcode.syntax.operands()))
+            .chain(PSEUDO_INSTRUCTIONS)
+            .map(|(mne, operands)| completion(mne, KEYWORD, operands));
+        let directives = DIRECTIVES.iter().map(|directive| completion(directive, KEYWORD, "directive"));
+        return mnemonics.chain(directives).collect();
+    }
+    let registers = REG_NAMES
+        .iter()
+        .enumerate()
+        .map(|(n, name)| completion(name, VARIABLE, &format!("x{}", n)));
+    let csrs = CSR_NAMES
+        .iter()
+        .map(|(name, address)| completion(name, CONSTANT, &format!("CSR {:#05x}", address)));
+    let mut names: Vec<_> = symbols.definitions.iter().collect();
+    names.sort_by_key(|(_, span)| (span.line, span.start));
+    let names = names.into_iter().map(|(name, _)| match symbols.constants.contains(name) {
+        true => completion(name, CONSTANT, "constant"),
+        false => completion(name, REFERENCE, "label"),
+    });
+    registers.chain(csrs).chain(names).collect()
+}
+
+/// The open files, and whether the client asked to shut down.
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    shut_down: bool,
+}
+
+impl Server {
+    /// The exit code once the client says `exit`.
+    fn exit_code(&self, message: &Value) -> Option<i32> {
+        (message["method"] == "exit").then_some(if self.shut_down { 0 } else { 1 })
+    }
+
+    /// The responses and notifications to send for a message.
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        match message.get("id") {
+            // A response from the client, which nothing here asks for
+            Some(_) if message.get("method").is_none() => vec![],
+            Some(id) => vec![match self.request(method, params) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, e)) => error(id.clone(), code, &e),
+            }],
+            None => self.notification(method, params),
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if self.shut_down {
+            return Err((INVALID_REQUEST, "shutting down".to_string()));
+        }
+        if method == "initialize" {
+            return Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "completionProvider": {},
+                },
+                "serverInfo": { "name": "riscv-lsp", "version": env!("CARGO_PKG_VERSION") },
+            }));
+        }
+        if method == "shutdown" {
+            self.shut_down = true;
+            return Ok(Value::Null);
+        }
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let source = match self.documents.get(uri) {
+            Some(source) => source.as_str(),
+            None if method.starts_with("textDocument/") => return Ok(Value::Null),
+            None => return Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        };
+        let symbols = Symbols::new(source);
+        let line = params["position"]["line"].as_u64().unwrap_or(0) as usize;
+        let text = source.lines().nth(line).unwrap_or("");
+        let column = byte_column(text, params["position"]["character"].as_u64().unwrap_or(0) as usize);
+        let at = symbols.at(line, column);
+        match method {
+            "textDocument/definition" => Ok(at
+                .and_then(|(_, word)| symbols.definitions.get(word))
+                .map_or(Value::Null, |&span| location(uri, source, span))),
+            "textDocument/references" => {
+                let (word, declaration) = match at {
+                    Some((_, word)) if symbols.definitions.contains_key(word) => {
+                        (word, symbols.definitions[word])
+                    }
+                    _ => return Ok(Value::Null),
+                };
+                let include = params["context"]["includeDeclaration"].as_bool().unwrap_or(true);
+                let references: Vec<_> = symbols
+                    .references(word)
+                    .filter(|&span| include || span != declaration)
+                    .map(|span| location(uri, source, span))
+                    .collect();
+                Ok(json!(references))
+            }
+            "textDocument/hover" => Ok(at
+                .and_then(|(span, word)| Some((span, hover(source, &symbols, span, word)?)))
+                .map_or(Value::Null, |(span, value)| {
+                    json!({
+                        "contents": { "kind": "markdown", "value": value },
+                        "range": range(source, span),
+                    })
+                })),
+            "textDocument/completion" => Ok(json!(completions(&text[..column], &symbols))),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method `{}`", method))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // Changes are always the whole file
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish(&uri, vec![])];
+            }
+            _ => None,
+        };
+        match text {
+            Some(text) => {
+                let (diagnostics, messages) = diagnostics(text);
+                self.documents.insert(uri.clone(), text.to_string());
+                let messages = messages.into_iter().map(|message| show_message(&message));
+                std::iter::once(publish(&uri, diagnostics)).chain(messages).collect()
+            }
+            None => vec![],
+        }
+    }
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/// An error for the user that isn't on any line of the file.
+fn show_message(message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "window/showMessage",
+        "params": { "type": 1, "message": message },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Diagnostic;
+
+    const URI: &str = "file:///main.s";
+    const SOURCE: &str = "\
+.equ COUNT, 3
+_start: addi a0, zero, COUNT
+loop:   addi a0, a0, -1
+        bne a0, zero, loop
+        jal ra, missing
+";
+
+    /// Runs a session of requests and notifications, and returns what the
+    /// server sent back.
+    fn session(messages: &[Value]) -> (i32, Vec<Value>) {
+        let mut input = vec![];
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = vec![];
+        let code = run(&input[..], &mut output).unwrap();
+        let mut reader = &output[..];
+        let mut sent = vec![];
+        while let Some(body) = read_message(&mut reader).unwrap() {
+            sent.push(serde_json::from_slice(&body).unwrap());
+        }
+        (code, sent)
+    }
+
+    fn request(id: u32, method: &str, line: u32, character: u32) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            },
+        })
+    }
+
+    #[test]
+    fn diagnostics_test() {
+        let (found, messages) = diagnostics("addi a0, a0, 1\n  x: .word missing # later\n");
+        assert_eq!(
+            found[0]["range"],
+            json!({ "start": { "line": 1, "character": 2 }, "end": { "line": 1, "character": 18 } })
+        );
+        assert!(messages.is_empty());
+        let (found, _) = diagnostics("addi a0, a0, 1\n.equ A, B\n");
+        assert_eq!(found[0]["range"]["start"], json!({ "line": 1, "character": 0 }));
+        // One without a line isn't put on the first line
+        let errors = Diagnostics(vec![Diagnostic::from("entry: undefined symbol `main`".to_string())]);
+        let (found, messages) = report("addi a0, a0, 1\n", errors);
+        assert!(found.is_empty());
+        assert_eq!(messages, vec!["entry: undefined symbol `main`".to_string()]);
+    }
+
+    #[test]
+    fn session_test() {
+        let (code, sent) = session(&[
+            json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": URI, "languageId": "riscv", "version": 1, "text": SOURCE } },
+            }),
+            request(1, "textDocument/definition", 3, 23),
+            request(2, "textDocument/references", 1, 26),
+            request(3, "textDocument/hover", 2, 9),
+            request(4, "textDocument/completion", 4, 8),
+            request(5, "textDocument/completion", 4, 16),
+            request(6, "textDocument/formatting", 0, 0),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": 2 },
+                    "contentChanges": [{ "text": SOURCE.replace("missing", "loop") }],
+                },
+            }),
+            json!({ "jsonrpc": "2.0", "id": 7, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+        assert_eq!(code, 0);
+        assert_eq!(sent[0]["result"]["capabilities"]["hoverProvider"], true);
+
+        assert_eq!(sent[1]["method"], "textDocument/publishDiagnostics");
+        let diagnostics = &sent[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert!(diagnostics[0]["message"].as_str().unwrap().contains("missing"));
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({ "start": { "line": 4, "character": 8 }, "end": { "line": 4, "character": 23 } })
+        );
+
+        assert_eq!(sent[2]["result"]["range"]["start"], json!({ "line": 2, "character": 0 }));
+        let references: Vec<_> = sent[3]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| location["range"]["start"]["line"].clone())
+            .collect();
+        assert_eq!(references, vec![json!(0), json!(1)]);
+
+        let hover = sent[4]["result"]["contents"]["value"].as_str().unwrap();
+        assert!(hover.contains("**addi** `rd, rs1, imm`"));
+        assert!(hover.contains("Add immediate"));
+        assert!(hover.contains("opcode `0x13`"));
+        assert!(hover.contains("`0xfff50513`"));
+
+        let labels = |sent: &Value| -> Vec<String> {
+            let items = sent["result"].as_array().unwrap();
+            items.iter().map(|item| item["label"].as_str().unwrap().to_string()).collect()
+        };
+        let first = labels(&sent[5]);
+        assert!(first.contains(&"jalr".to_string()) && first.contains(&".word".to_string()));
+        let operands = labels(&sent[6]);
+        assert!(["ra", "mstatus", "cycle", "COUNT", "_start", "loop"].iter().all(|name| operands.contains(&name.to_string())));
+        assert!(!operands.contains(&"addi".to_string()));
+
+        assert_eq!(sent[7]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(sent[8]["params"]["diagnostics"], json!([]));
+        assert_eq!(sent[9]["result"], Value::Null);
+    }
+
+    #[test]
+    fn exit_without_shutdown_test() {
+        let (code, sent) = session(&[json!({ "jsonrpc": "2.0", "method": "exit" })]);
+        assert_eq!((code, sent.len()), (1, 0));
+    }
+
+    #[test]
+    fn words_test() {
+        assert_eq!(
+            words("msg: .string \"a b\" # c d"),
+            vec![(0, "msg"), (5, ".string")]
+        );
+        assert_eq!(words("lw a0, 8(sp)"), vec![(0, "lw"), (3, "a0"), (7, "8"), (9, "sp")]);
+    }
+}
@@ -1,26 +1,295 @@
-mod assembler;
-mod parser;
-mod instructions;
-
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use riscv_assembler::custom::CustomInstructions;
+use riscv_assembler::executable::executable;
+use riscv_assembler::explain::explain;
+use riscv_assembler::formatter;
+use riscv_assembler::hazard::{self, Pipeline};
+use riscv_assembler::line_table::line_table;
+use riscv_assembler::link::{self, parse_address};
+use riscv_assembler::lint::{self, Code};
+use riscv_assembler::linker_script::LinkerScript;
+use riscv_assembler::preprocessor::{self, preprocess, Preprocessed};
+use riscv_assembler::stats::stats;
+use riscv_assembler::{assembler, parser, Diagnostics, Options};
+use std::fmt::Display;
 use std::fs;
+use std::path::PathBuf;
+use std::process;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    #[clap(value_parser=file_exists, help="Input assembly file" )]
-    input_file: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(value_parser=file_exists, required=true, help="Input assembly file, run through the C preprocessor first if it ends in .S" )]
+    input_file: Option<String>,
     
-    #[clap(value_parser, help="Output binary executable")]
-    output_file: String,
+    #[clap(value_parser, required_unless_present_any=&["lint", "hazards", "explain", "stats", "stats-json", "preprocess-only"], help="Output binary executable")]
+    output_file: Option<String>,
+
+    #[clap(short='c', long, help="Write a relocatable object for riscv-link instead")]
+    object: bool,
+
+    #[clap(short='x', long, conflicts_with="object", help="Write an executable with a header, section table and CRC-32 instead of a flat binary")]
+    executable: bool,
+
+    #[clap(long, requires="executable", help="Leave the symbol table out of the executable")]
+    strip: bool,
+
+    #[clap(long, value_parser=parse_address, help="Address of .text")]
+    text_base: Option<u32>,
+
+    #[clap(long, value_parser=parse_address, help="Address of .data")]
+    data_base: Option<u32>,
+
+    #[clap(short='T', long, value_parser=file_exists, help="TOML linker script")]
+    script: Option<String>,
+
+    #[clap(short='D', value_parser=parser::parse_define, help="Define NAME as a constant, 1 unless given")]
+    define: Vec<(String, i64)>,
+
+    #[clap(long, help="Run the C preprocessor first, whatever the extension")]
+    cpp: bool,
+
+    #[clap(short='E', long="preprocess", help="Only run the C preprocessor, and print its output with line markers")]
+    preprocess_only: bool,
+
+    #[clap(short='I', value_name="DIR", help="Look for #include files in DIR too")]
+    include: Vec<PathBuf>,
+
+    #[clap(long, value_parser=file_exists, help="TOML file of custom instructions")]
+    instructions: Option<String>,
+
+    #[clap(long, help="Load addresses relative to the PC, and fail on absolute ones, so the program can run anywhere")]
+    pic: bool,
+
+    #[clap(long, help="Fail on out of range branches and jumps instead of rewriting them")]
+    no_relax: bool,
+
+    #[clap(long, help="List the branches and jumps that were rewritten")]
+    report_relax: bool,
+
+    #[clap(long, value_name="FILE", conflicts_with="object", help="Write the source line of each instruction address as JSON")]
+    line_table: Option<String>,
+
+    #[clap(long, help="Print the bit fields of each instruction")]
+    explain: bool,
+
+    #[clap(long, help="Print the instruction counts, section sizes, labels and longest basic block")]
+    stats: bool,
+
+    #[clap(long, value_name="FILE", help="Write the --stats as JSON")]
+    stats_json: Option<String>,
+
+    #[clap(long, help="Warn about pipeline hazards and list the bubbles they need by function")]
+    hazards: bool,
+
+    #[clap(long, help="Insert nops to get around pipeline hazards, listing them by function")]
+    fix_hazards: bool,
+
+    #[clap(long, value_parser=file_exists, help="TOML description of the pipeline for --hazards")]
+    pipeline: Option<String>,
+
+    #[clap(long, help="Warn about likely mistakes, and only assemble if given an output file")]
+    lint: bool,
+
+    #[clap(long, value_parser=lint::parse_code, help="Leave out a kind of --lint warning")]
+    allow: Vec<Code>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lay out assembly files the same way, in place
+    Fmt(FmtArgs),
+}
+
+#[derive(Args)]
+struct FmtArgs {
+    #[clap(value_parser=file_exists, required=true, help="Assembly files to format")]
+    files: Vec<String>,
+
+    #[clap(long, help="Only list the files that aren't formatted, and fail if there are any")]
+    check: bool,
+
+    #[clap(long, help="Write numbered registers by their ABI names")]
+    abi_names: bool,
+
+    #[clap(long, value_parser=file_exists, help="TOML file of custom instructions")]
+    instructions: Option<String>,
 }
 
 fn main() {
     let cli = Cli::parse();
-    
+    if let Some(Command::Fmt(args)) = &cli.command {
+        return fmt(args);
+    }
+    let input_file = cli.input_file.as_deref().unwrap();
+
     // Read file
-    let contents: String = fs::read_to_string(cli.input_file).unwrap();
-    print!("{}", contents);
+    let contents: String = fs::read_to_string(input_file).unwrap_or_else(|e| fail(input_file, e));
+    let preprocessed = (cli.cpp || cli.preprocess_only || input_file.ends_with(".S")).then(|| {
+        let options = preprocessor::Options {
+            defines: cli.define.clone(),
+            include_dirs: cli.include.clone(),
+        };
+        preprocess(&contents, input_file, &options).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    });
+    if cli.preprocess_only {
+        print!("{}", preprocessed.unwrap().with_line_markers());
+        return;
+    }
+
+    let script = cli.script.as_ref().map(|path| {
+        LinkerScript::read(path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        })
+    });
+    let instructions = cli.instructions.as_ref().map(|path| {
+        CustomInstructions::read(path).unwrap_or_else(|e| fail(path, e))
+    });
+    let options = Options {
+        parser: parser::Options {
+            defines: cli.define.clone(),
+            instructions: instructions.unwrap_or_default(),
+            pic: cli.pic,
+        },
+        assembler: assembler::Options {
+            relax: !cli.no_relax,
+            pic: cli.pic,
+            link: link::Options {
+                text_base: cli.text_base,
+                data_base: cli.data_base,
+                script,
+            },
+        },
+    };
+
+    let source = preprocessed.as_ref().map_or(&contents, |preprocessed| &preprocessed.source);
+    let mut file = riscv_assembler::parse(source, &options).unwrap_or_else(|e| fail_source(input_file, preprocessed.as_ref(), e));
+    if let Some(preprocessed) = &preprocessed {
+        preprocessed.add_origins(&mut file);
+    }
+    if cli.lint {
+        let warnings = lint::lint(&file, &cli.allow, options.assembler.link.entry()).unwrap_or_else(|e| fail_source(input_file, preprocessed.as_ref(), e.into()));
+        for mut warning in warnings {
+            let (name, line) = file.origin(warning.line);
+            warning.line = line;
+            eprintln!("{}: {}", name.unwrap_or(input_file), warning);
+        }
+    }
+    if cli.hazards || cli.fix_hazards {
+        let pipeline = match &cli.pipeline {
+            Some(path) => Pipeline::read(path).unwrap_or_else(|e| fail(path, e)),
+            None => Pipeline::default(),
+        };
+        let hazards = match cli.fix_hazards {
+            true => hazard::insert_nops(&mut file, &pipeline),
+            false => hazard::hazards(&file, &pipeline),
+        };
+        let hazards = hazards.unwrap_or_else(|e| fail_source(input_file, preprocessed.as_ref(), e.into()));
+        if !cli.fix_hazards {
+            for hazard in &hazards {
+                eprintln!("{}: warning: {}", input_file, hazard);
+            }
+        }
+        for (function, bubbles) in hazard::bubbles(&hazards) {
+            eprintln!("{}: `{}`: {} bubble(s)", input_file, function, bubbles);
+        }
+    }
+    if cli.explain {
+        let explained = explain(&file, source, &options.assembler)
+            .unwrap_or_else(|e| fail_source(input_file, preprocessed.as_ref(), e.into()));
+        print!("{}", explained);
+    }
+    if cli.stats || cli.stats_json.is_some() {
+        let stats = stats(&file, &options.assembler).unwrap_or_else(|e| fail_source(input_file, preprocessed.as_ref(), e.into()));
+        if cli.stats {
+            print!("{}", stats);
+        }
+        if let Some(path) = &cli.stats_json {
+            fs::write(path, stats.to_json()).unwrap_or_else(|e| fail(path, e));
+        }
+    }
+    let output_file = match &cli.output_file {
+        Some(output_file) => output_file,
+        None => return,
+    };
+
+    if let Some(path) = &cli.line_table {
+        let table = line_table(&file, input_file, &options.assembler)
+            .unwrap_or_else(|e| fail_source(input_file, preprocessed.as_ref(), e.into()));
+        fs::write(path, table.to_json()).unwrap_or_else(|e| fail(path, e));
+    }
+    let binary = match (cli.object, cli.executable) {
+        (true, _) => assembler::object(&file, &options.assembler)
+            .map(|(object, relaxed)| (object.to_json().into_bytes(), relaxed))
+            .map_err(Diagnostics::from),
+        (_, true) => executable(&file, &options.assembler, cli.strip)
+            .map(|(executable, relaxed)| (executable.to_bytes(), relaxed))
+            .map_err(Diagnostics::from),
+        (false, false) => riscv_assembler::assemble_file(file, &options)
+            .map(|program| (program.binary, program.relaxed)),
+    };
+    let (binary, relaxed) = binary.unwrap_or_else(|e| fail_source(input_file, preprocessed.as_ref(), e));
+    if cli.report_relax {
+        for relaxation in relaxed {
+            eprintln!("{}: relaxed {}", input_file, relaxation);
+        }
+    }
+    fs::write(output_file, binary).unwrap_or_else(|e| fail(output_file, e))
+}
+
+fn fmt(args: &FmtArgs) {
+    let instructions = args.instructions.as_ref().map(|path| {
+        CustomInstructions::read(path).unwrap_or_else(|e| fail(path, e))
+    });
+    let options = formatter::Options {
+        abi_names: args.abi_names,
+        instructions: instructions.unwrap_or_default(),
+    };
+    let mut unformatted = false;
+    for path in &args.files {
+        let source = fs::read_to_string(path).unwrap_or_else(|e| fail(path, e));
+        let formatted = formatter::format(&source, &options);
+        if formatted == source {
+            continue;
+        }
+        match args.check {
+            true => {
+                eprintln!("{}: not formatted", path);
+                unformatted = true;
+            }
+            false => fs::write(path, formatted).unwrap_or_else(|e| fail(path, e)),
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+fn fail(path: &str, e: impl Display) -> ! {
+    eprintln!("{}: {}", path, e);
+    process::exit(1);
+}
+
+/// Like `fail`, but with each diagnostic at the file and line it came from
+/// before preprocessing, if `input_file` was preprocessed.
+fn fail_source(input_file: &str, preprocessed: Option<&Preprocessed>, e: Diagnostics) -> ! {
+    match preprocessed {
+        Some(preprocessed) => {
+            for diagnostic in &e.0 {
+                eprintln!("{}", preprocessed.locate(diagnostic));
+            }
+            process::exit(1);
+        }
+        None => fail(input_file, e),
+    }
 }
 
 fn file_exists(s: &str) -> Result<String, String> {
@@ -32,3 +301,4 @@ fn file_exists(s: &str) -> Result<String, String> {
     }
 
 }
+
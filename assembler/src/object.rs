@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+
+use crate::expr::Reloc;
+
+/// Bumped whenever the layout below changes, so the linker can refuse
+/// objects written by another version instead of misreading them.
+const VERSION: u32 = 1;
+
+/// A relocatable object: sections assembled as if each started at address
+/// zero, the symbols they define or need, and the places that have to be
+/// patched once the linker knows where everything goes. Stored as JSON.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Object {
+    version: u32,
+    pub sections: Vec<ObjectSection>,
+    pub symbols: Vec<Symbol>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ObjectSection {
+    pub name: String,
+    pub executable: bool,
+    pub nobits: bool,
+    /// The section must start at a multiple of this.
+    pub align: u32,
+    pub size: u32,
+    /// Empty for sections that only reserve space.
+    #[serde(with = "hex")]
+    pub data: Vec<u8>,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    /// Only global symbols are seen by other objects.
+    pub global: bool,
+    /// `None` for a symbol this object uses but another one defines.
+    pub definition: Option<Definition>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum Definition {
+    /// A constant from `.equ` or `.set`.
+    Absolute(i64),
+    /// A label, at `offset` bytes into a section of this object.
+    Section { index: usize, offset: i64 },
+}
+
+/// What a relocation's value is measured from.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub enum Target {
+    /// The start of a section of this object.
+    Section(usize),
+    /// A symbol another object defines.
+    Symbol(String),
+    /// Address zero, for a constant that's relative to the instruction.
+    Absolute,
+}
+
+/// The bits a relocation patches.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Field {
+    /// A branch offset.
+    B,
+    /// A `jal` offset.
+    J,
+    /// The upper 20 bits of `lui` or `auipc`.
+    U,
+    /// The 12 bit immediate of an I type instruction.
+    I,
+    /// The 12 bit immediate of a store.
+    S,
+    /// A `.word`.
+    Word,
+}
+
+/// Patch `field` at `offset` with the address of `target` plus `addend`,
+/// put through `reloc` if there is one. B and J fields hold the distance
+/// from the instruction. For `%pcrel_lo`, the target and addend are the
+/// `auipc` with the matching `%pcrel_hi`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Relocation {
+    pub offset: u32,
+    pub field: Field,
+    pub reloc: Option<Reloc>,
+    pub target: Target,
+    pub addend: i64,
+}
+
+impl Object {
+    pub fn new(sections: Vec<ObjectSection>, symbols: Vec<Symbol>) -> Object {
+        Object {
+            version: VERSION,
+            sections,
+            symbols,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(s: &str) -> Result<Object, String> {
+        let object: Object =
+            serde_json::from_str(s).map_err(|e| format!("not an object file: {}", e))?;
+        if object.version != VERSION {
+            return Err(format!(
+                "object file version {} isn't supported, expected {}",
+                object.version, VERSION
+            ));
+        }
+        object.check()?;
+        Ok(object)
+    }
+
+    /// Checks what the linker indexes by, so a damaged object is an error
+    /// rather than a crash.
+    fn check(&self) -> Result<(), String> {
+        let section = |index: usize| {
+            self.sections
+                .get(index)
+                .map(|_| ())
+                .ok_or_else(|| format!("there's no section {}", index))
+        };
+        for symbol in &self.symbols {
+            if let Some(Definition::Section { index, .. }) = symbol.definition {
+                section(index)?;
+            }
+        }
+        for s in &self.sections {
+            if !s.align.is_power_of_two() {
+                return Err(format!("`{}`: alignment {} isn't a power of two", s.name, s.align));
+            }
+            if !s.data.is_empty() && s.data.len() != s.size as usize {
+                return Err(format!("`{}`: {} bytes of data for size {}", s.name, s.data.len(), s.size));
+            }
+            for relocation in &s.relocations {
+                if relocation.offset as u64 + 4 > s.data.len() as u64 {
+                    return Err(format!("`{}`: relocation at {:#x} is outside the data", s.name, relocation.offset));
+                }
+                if let Target::Section(index) = relocation.target {
+                    section(index)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Section contents as a hex string, which is a lot shorter than a list of
+/// numbers.
+mod hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        serializer.serialize_str(&hex)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        if hex.len() % 2 != 0 {
+            return Err(D::Error::custom("odd number of hex digits"));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                hex.get(i..i + 2)
+                    .ok_or_else(|| D::Error::custom("not a hex digit"))
+                    .and_then(|digits| u8::from_str_radix(digits, 16).map_err(D::Error::custom))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_test() {
+        let object = Object::new(
+            vec![ObjectSection {
+                name: ".text".to_string(),
+                executable: true,
+                nobits: false,
+                align: 4,
+                size: 4,
+                data: vec![0x00, 0x00, 0x00, 0xef],
+                relocations: vec![Relocation {
+                    offset: 0,
+                    field: Field::J,
+                    reloc: None,
+                    target: Target::Symbol("printf".to_string()),
+                    addend: 0,
+                }],
+            }],
+            vec![Symbol {
+                name: "printf".to_string(),
+                global: true,
+                definition: None,
+            }],
+        );
+        let json = object.to_json();
+        assert!(json.contains("\"000000ef\""));
+        assert_eq!(Object::from_json(&json).unwrap(), object);
+    }
+
+    #[test]
+    fn version_test() {
+        let json = Object::new(vec![], vec![]).to_json().replace("1", "2");
+        assert!(Object::from_json(&json).unwrap_err().contains("version 2"));
+        assert!(Object::from_json("MZ").unwrap_err().contains("not an object file"));
+    }
+
+    #[test]
+    fn check_test() {
+        let mut object = Object::new(
+            vec![ObjectSection {
+                name: ".text".to_string(),
+                executable: true,
+                nobits: false,
+                align: 4,
+                size: 4,
+                data: vec![0; 4],
+                relocations: vec![Relocation {
+                    offset: 2,
+                    field: Field::Word,
+                    reloc: None,
+                    target: Target::Section(1),
+                    addend: 0,
+                }],
+            }],
+            vec![],
+        );
+        let err = Object::from_json(&object.to_json()).unwrap_err();
+        assert_eq!(err, "`.text`: relocation at 0x2 is outside the data");
+        object.sections[0].relocations[0].offset = 0;
+        let err = Object::from_json(&object.to_json()).unwrap_err();
+        assert_eq!(err, "there's no section 1");
+        object.sections[0].align = 0;
+        let err = Object::from_json(&object.to_json()).unwrap_err();
+        assert_eq!(err, "`.text`: alignment 0 isn't a power of two");
+        let json = object.to_json().replace("\"00000000\"", "\"0é000\"");
+        assert!(Object::from_json(&json).unwrap_err().contains("not a hex digit"));
+    }
+}
@@ -1,30 +1,64 @@
+use crate::expr::{parse_escape, parse_expr, parse_symbol, BinOp, Expr, Reloc};
 use crate::instructions::types::{Imm, Reg};
 
 use nom::branch::alt;
-use nom::bytes::complete::{escaped, is_not, tag, tag_no_case};
+use nom::bytes::complete::{is_not, tag, tag_no_case, take_while};
 use nom::character::complete::{
-    alphanumeric0, alphanumeric1, char, digit0, multispace0, one_of, space0, space1, u32,
+    alphanumeric1, char, multispace0, one_of, satisfy, space0, space1,
 };
-use nom::combinator::{cut, map, opt};
-use nom::error::{context, VerboseError};
-use nom::multi::{many0, many1, separated_list1};
-use nom::sequence::{pair, preceded, terminated, tuple};
+use nom::combinator::{cut, map, map_opt, not, opt, peek, verify};
+use nom::error::{Error, ErrorKind, ParseError};
+use nom::multi::separated_list1;
+use nom::number::complete::{double, float};
+use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
 use nom::IResult;
 
-use crate::instructions::InstructionData;
+use crate::custom::{check_fields, CustomInstructions};
+use crate::instructions::isa::{lookup, Syntax};
+use crate::instructions::{Encoding, Format, InstructionData};
+use crate::preprocessor::Origin;
+use crate::Diagnostic;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Text {
     pub instruction: InstructionData,
     pub label: Option<String>,
     pub label_dst: Option<String>,
+    /// Immediate that refers to symbols, so it can only be evaluated once
+    /// labels are laid out. `instruction.imm` is filled in from it then.
+    pub imm_expr: Option<Expr>,
+    pub location: Location,
+    /// Part of the expansion of a pseudo-instruction, like `la` or `call`.
+    pub pseudo: bool,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Where a statement starts in the source, counting from 1. Both are 0 for
+/// instructions that aren't in the source, like the nops `--fix-hazards`
+/// inserts.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum DataSize {
     Byte,
     Half,
     Word,
+    Dword,
+}
+
+impl DataSize {
+    pub fn bytes(self) -> usize {
+        match self {
+            DataSize::Byte => 1,
+            DataSize::Half => 2,
+            DataSize::Word => 4,
+            DataSize::Dword => 8,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -32,36 +66,168 @@ pub struct Data {
     pub label: Option<String>,
     pub data: Vec<u8>,
     pub size: DataSize,
+    /// Values of a `.byte`/`.half`/`.word`/`.dword` list, emitted after
+    /// `data` once symbols are known.
+    pub exprs: Vec<Expr>,
+    pub location: Location,
 }
 
+/// A symbol defined with `.equ` or `.set`.
 #[derive(Debug, PartialEq, Clone)]
-pub struct FullFile {
-    pub text: Vec<Text>,
-    pub data: Vec<Data>,
+pub struct Constant {
+    pub name: String,
+    pub expr: Expr,
+    pub location: Location,
+}
+
+/// Anything that takes up space in a section.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Item {
+    Text(Text),
+    Data(Data),
+    /// A label that isn't on the same statement as an instruction or data.
+    Label(String, Location),
+    /// `.align`, `.p2align` or `.balign`, with the alignment in bytes.
+    Align { bytes: Expr, fill: Option<Expr>, location: Location },
+    /// `.space`, `.skip` or `.zero`.
+    Space { size: Expr, fill: Option<Expr>, location: Location },
+    /// `.org`, relative to the start of the section.
+    Org(Expr, Location),
+    /// `.fill`, `repeat` copies of `value` in `size` bytes.
+    Fill { repeat: Expr, size: Expr, value: Expr, location: Location },
+}
+
+impl Item {
+    /// Where the statement the item came from starts.
+    pub fn location(&self) -> Location {
+        match self {
+            Item::Text(Text { location, .. })
+            | Item::Data(Data { location, .. })
+            | Item::Label(_, location)
+            | Item::Align { location, .. }
+            | Item::Space { location, .. }
+            | Item::Org(_, location)
+            | Item::Fill { location, .. } => *location,
+        }
+    }
+
+    fn location_mut(&mut self) -> &mut Location {
+        match self {
+            Item::Text(Text { location, .. })
+            | Item::Data(Data { location, .. })
+            | Item::Label(_, location)
+            | Item::Align { location, .. }
+            | Item::Space { location, .. }
+            | Item::Org(_, location)
+            | Item::Fill { location, .. } => location,
+        }
+    }
 }
 
-fn num_to_bytes(data: Vec<u32>, datasize: DataSize) -> Vec<u8>{
-    match datasize {
-        DataSize::Byte => {
-            data.iter().map(|b| {
-                b.to_be_bytes()[0] 
-            }).collect()
+#[derive(Debug, PartialEq, Clone)]
+pub struct Section {
+    pub name: String,
+    /// Holds code, so alignment is padded with `nop`s.
+    pub executable: bool,
+    /// Only reserves space, like `.bss`, and isn't stored in the output.
+    pub nobits: bool,
+    pub items: Vec<Item>,
+}
+
+impl Section {
+    /// Sections get their defaults from their name, like `.text.init` or
+    /// `.bss`, and `.section` flags ("ax") and type (@nobits) add to them.
+    fn new(name: &str, flags: Option<&str>, kind: Option<&str>) -> Section {
+        let is = |prefix: &str| name == prefix || name.starts_with(&format!("{}.", prefix));
+        Section {
+            name: name.to_string(),
+            executable: is(".text") || flags.is_some_and(|f| f.contains('x')),
+            nobits: is(".bss") || is(".sbss") || kind == Some("nobits"),
+            items: vec![],
         }
-        DataSize::Half => {
-            data.iter().flat_map(|h| {
-                h.to_be_bytes()[0..1].to_vec()
-            }).collect()
+    }
+}
+
+/// Whether other objects can see a symbol, from `.globl` or `.local`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Binding {
+    Global,
+    Local,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FullFile {
+    /// In the order they first appear, .text is always the first.
+    pub sections: Vec<Section>,
+    pub consts: Vec<Constant>,
+    /// In order, so a later `.globl` or `.local` for a symbol wins.
+    pub bindings: Vec<(String, Binding)>,
+    /// Where each line of the parsed text came from, if it was preprocessed.
+    /// Locations are lines of the parsed text either way.
+    pub origins: Vec<Origin>,
+}
+
+impl FullFile {
+    /// The file and line that line `line` of the parsed text came from. The
+    /// file is `None` if it wasn't preprocessed.
+    pub fn origin(&self, line: usize) -> (Option<&str>, usize) {
+        match line.checked_sub(1).and_then(|n| self.origins.get(n)) {
+            Some(origin) => (Some(&origin.file), origin.line),
+            None => (None, line),
         }
-        DataSize::Word => {
-            data.iter().flat_map(|h| {
-                h.to_be_bytes().to_vec()
-            }).collect()
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Statement {
+    Section(Section),
+    Item(Item),
+    Binding(Vec<(String, Binding)>),
+    Constant(Constant),
+    Pseudo(Pseudo),
+}
+
+/// `la rd, symbol`, `call symbol`, a load like `lw rd, symbol` or a store
+/// like `sw rs2, symbol, rt`, before it's expanded into the two instructions
+/// that load the address and use it. `base` is the register the address
+/// goes in, `rt` for a store, and `rd` is the value's.
+#[derive(Debug, PartialEq, Clone)]
+struct Pseudo {
+    mne: String,
+    rd: Reg,
+    base: Reg,
+    target: Expr,
+}
+
+/// `value` as `bytes` big endian bytes. It has to fit, either as a signed
+/// or as an unsigned number.
+pub fn num_to_bytes(value: i64, bytes: usize) -> Result<Vec<u8>, String> {
+    if bytes < 8 {
+        let bits = 8 * bytes as u32;
+        let min = -(1i64 << (bits - 1));
+        let max = (1i64 << bits) - 1;
+        if value < min || value > max {
+            return Err(format!("{} doesn't fit in {} bytes", value, bytes));
         }
     }
+    Ok(value.to_be_bytes()[8 - bytes..].to_vec())
+}
+
+/// ABI names of the registers, by number.
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// The ABI name of register `reg`, for messages.
+pub fn reg_name(reg: Reg) -> &'static str {
+    REG_NAMES[reg as usize]
 }
 
-fn str_to_reg(s: &str) -> Option<Reg> {
-    match s {
+/// A register by its number or ABI name, in either case.
+pub fn str_to_reg(s: &str) -> Option<Reg> {
+    match s.to_ascii_lowercase().as_str() {
         "0" | "zero" => Some(0),
         "1" | "ra" => Some(1),
         "2" | "sp" => Some(2),
@@ -98,14 +264,14 @@ fn str_to_reg(s: &str) -> Option<Reg> {
     }
 }
 
-fn parse_label(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
+fn parse_label(i: &str) -> IResult<&str, &str, Error<&str>> {
     preceded(
         multispace0,
         terminated(is_not(" \t\r\n:"), terminated(tag(":"), multispace0)),
     )(i)
 }
 
-fn inside_par(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
+fn inside_par(i: &str) -> IResult<&str, &str, Error<&str>> {
     preceded(
         space0,
         preceded(
@@ -118,370 +284,1071 @@ fn inside_par(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
     )(i)
 }
 
-fn reg(i: &str) -> IResult<&str, &str, VerboseError<&str>> {
+fn reg(i: &str) -> IResult<&str, &str, Error<&str>> {
     terminated(preceded(space0, alphanumeric1), space0)(i)
 }
 
-fn parse_imm(i: &str) -> IResult<&str, (Option<&str>, &str), VerboseError<&str>> {
-    pair(opt(alt((tag("0x"), tag("0b")))), alphanumeric1)(i)
+fn parse_imm(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    parse_expr(i)
 }
 
-fn parse_load_instr(i: &str) -> IResult<&str, Text, VerboseError<&str>> {
-    let label_p = opt(parse_label);
-    let mne_p = terminated(
-        preceded(
-            space0,
-            alt((
-                tag_no_case("lbu"),
-                tag_no_case("lhu"),
-                tag_no_case("lb"),
-                tag_no_case("lw"),
-                tag_no_case("lh"),
-            )),
-        ),
-        space1,
-    );
-    let rd_p = reg;
-    let imm_p = preceded(
-        // Remove beginning whitespace and comma
-        preceded(preceded(space0, tag(",")), space0),
-        // Gets numbers
-        parse_imm,
-    );
-    let rs1_p = terminated(inside_par, multispace0);
-    map(
-        tuple((label_p, mne_p, rd_p, imm_p, rs1_p)),
-        |(label, mne, rd, (imm1, imm2), rs1)| Text {
-            instruction: InstructionData {
-                mne: mne.to_string(),
-                rs1: str_to_reg(rs1),
-                rs2: None,
-                rd: str_to_reg(rd),
-                imm: match imm1 {
-                    Some("0x") => Some(u32::from_str_radix(imm2, 16).unwrap()),
-                    Some("0b") => Some(u32::from_str_radix(imm2, 2).unwrap()),
-                    None => Some(imm2.parse::<Imm>().unwrap()),
-                    _ => unreachable!(),
-                },
-            },
-            label: label.map(|s| String::from(s)),
-            label_dst: None,
-        },
-    )(i)
+/// Splits an immediate into the value, if it is already known and fits in
+/// 32 bits, or the expression to evaluate after label layout, which is also
+/// where one that doesn't fit is reported.
+fn split_imm(expr: Expr) -> (Option<Imm>, Option<Expr>) {
+    match expr.constant() {
+        Some(n) if i32::try_from(n).is_ok() || u32::try_from(n).is_ok() => (Some(n as Imm), None),
+        _ => (None, Some(expr)),
+    }
 }
 
-fn parse_store_instr(i: &str) -> IResult<&str, Text, VerboseError<&str>> {
-    let label_p = opt(parse_label);
-    let mne_p = terminated(
-        preceded(
-            space0,
-            alt((tag_no_case("sw"), tag_no_case("sb"), tag_no_case("sh"))),
-        ),
-        space1,
-    );
-    let rs2_p = reg;
-    let imm_p = preceded(
-        // Remove beginning whitespace
-        preceded(preceded(space0, tag(",")), space0),
-        parse_imm,
-    );
-    let rs1_p = terminated(inside_par, multispace0);
-    map(
-        tuple((label_p, mne_p, rs2_p, imm_p, rs1_p)),
-        |(label, mne, rs2, (imm1, imm2), rs1)| Text {
-            instruction: InstructionData {
-                mne: mne.to_string(),
-                rs1: str_to_reg(rs1),
-                rs2: str_to_reg(rs2),
-                rd: None,
-                imm: match imm1 {
-                    Some("0x") => Some(u32::from_str_radix(imm2, 16).unwrap()),
-                    Some("0b") => Some(u32::from_str_radix(imm2, 2).unwrap()),
-                    None => Some(imm2.parse::<Imm>().unwrap()),
-                    _ => unreachable!(),
-                },
+/// Bare labels as branch and jump targets are handled by the pseudo
+/// instruction parsers.
+fn is_not_label(expr: &Expr) -> bool {
+    !matches!(expr, Expr::Sym(_))
+}
+
+/// The operands of an instruction, as its syntax writes them.
+#[derive(Default)]
+struct Operands {
+    rd: Option<Reg>,
+    rs1: Option<Reg>,
+    rs2: Option<Reg>,
+    imm: Option<Imm>,
+    imm_expr: Option<Expr>,
+    label_dst: Option<String>,
+}
+
+type Immediate = (Option<Imm>, Option<Expr>);
+
+fn first_reg(i: &str) -> IResult<&str, Reg, Error<&str>> {
+    map_opt(reg, str_to_reg)(i)
+}
+
+fn next_reg(i: &str) -> IResult<&str, Reg, Error<&str>> {
+    map_opt(preceded(tag(","), reg), str_to_reg)(i)
+}
+
+fn comma(i: &str) -> IResult<&str, (), Error<&str>> {
+    map(preceded(preceded(space0, tag(",")), space0), |_| ())(i)
+}
+
+/// The comma before the last operand of `lui` and `jal` is optional, as in
+/// `lui s1 0x12`.
+fn optional_comma(i: &str) -> IResult<&str, (), Error<&str>> {
+    map(preceded(space0, opt(terminated(tag(","), space0))), |_| ())(i)
+}
+
+fn imm(i: &str) -> IResult<&str, Immediate, Error<&str>> {
+    map(parse_imm, split_imm)(i)
+}
+
+/// `, imm(rs1)`
+fn offset(i: &str) -> IResult<&str, (Immediate, Reg), Error<&str>> {
+    pair(preceded(comma, imm), map_opt(inside_par, str_to_reg))(i)
+}
+
+/// A branch or jump target. A bare label is kept as the label it goes to,
+/// anything else is an immediate.
+fn target(i: &str) -> IResult<&str, (Immediate, Option<String>), Error<&str>> {
+    alt((
+        map(verify(parse_imm, is_not_label), |expr| (split_imm(expr), None)),
+        map(is_not(" \t\r\n:"), |label: &str| ((None, None), Some(label.to_string()))),
+    ))(i)
+}
+
+fn parse_operands(syntax: Syntax, i: &str) -> IResult<&str, Operands, Error<&str>> {
+    let (i, operands) = match syntax {
+        Syntax::Reg => map(tuple((first_reg, next_reg, next_reg)), |(rd, rs1, rs2)| Operands {
+            rd: Some(rd),
+            rs1: Some(rs1),
+            rs2: Some(rs2),
+            ..Operands::default()
+        })(i),
+        Syntax::Imm | Syntax::Shift => map(
+            tuple((first_reg, next_reg, preceded(comma, imm))),
+            |(rd, rs1, (imm, imm_expr))| Operands {
+                rd: Some(rd),
+                rs1: Some(rs1),
+                imm,
+                imm_expr,
+                ..Operands::default()
+            },
+        )(i),
+        Syntax::Load => map(pair(first_reg, offset), |(rd, ((imm, imm_expr), rs1))| Operands {
+            rd: Some(rd),
+            rs1: Some(rs1),
+            imm,
+            imm_expr,
+            ..Operands::default()
+        })(i),
+        Syntax::Store => map(pair(first_reg, offset), |(rs2, ((imm, imm_expr), rs1))| Operands {
+            rs1: Some(rs1),
+            rs2: Some(rs2),
+            imm,
+            imm_expr,
+            ..Operands::default()
+        })(i),
+        Syntax::Branch => map(
+            tuple((first_reg, next_reg, preceded(comma, target))),
+            |(rs1, rs2, ((imm, imm_expr), label_dst))| Operands {
+                rs1: Some(rs1),
+                rs2: Some(rs2),
+                imm,
+                imm_expr,
+                label_dst,
+                ..Operands::default()
+            },
+        )(i),
+        Syntax::Upper => map(pair(first_reg, preceded(optional_comma, imm)), |(rd, (imm, imm_expr))| {
+            Operands {
+                rd: Some(rd),
+                imm,
+                imm_expr,
+                ..Operands::default()
+            }
+        })(i),
+        Syntax::Jump => map(
+            pair(first_reg, preceded(optional_comma, target)),
+            |(rd, ((imm, imm_expr), label_dst))| Operands {
+                rd: Some(rd),
+                imm,
+                imm_expr,
+                label_dst,
+                ..Operands::default()
             },
-            label: label.map(|s| String::from(s)),
-            label_dst: None,
+        )(i),
+    }?;
+    let (i, _) = multispace0(i)?;
+    Ok((i, operands))
+}
+
+/// `.insn` and custom instructions of type I take `rd, rs1, imm` or, like
+/// loads, `rd, imm(rs1)`.
+fn parse_custom_operands(format: Format, i: &str) -> IResult<&str, Operands, Error<&str>> {
+    match format {
+        Format::I => alt((
+            |i| parse_operands(Syntax::Imm, i),
+            |i| parse_operands(Syntax::Load, i),
+        ))(i),
+        _ => parse_operands(Syntax::of(format), i),
+    }
+}
+
+fn instruction_text(
+    label: Option<&str>,
+    mne: &str,
+    custom: Option<Encoding>,
+    operands: Operands,
+) -> Text {
+    Text {
+        instruction: InstructionData {
+            mne: mne.to_string(),
+            rd: operands.rd,
+            rs1: operands.rs1,
+            rs2: operands.rs2,
+            imm: operands.imm,
+            custom,
         },
-    )(i)
+        label: label.map(String::from),
+        label_dst: operands.label_dst,
+        imm_expr: operands.imm_expr,
+        location: Location::default(),
+        pseudo: false,
+    }
 }
 
-fn parse_branch_instr(i: &str) -> IResult<&str, Text, VerboseError<&str>> {
-    let label_p = opt(parse_label);
-    let mne_p = terminated(
-        preceded(
-            space0,
-            alt((
-                tag_no_case("bltu"),
-                tag_no_case("bgeu"),
-                tag_no_case("beq"),
-                tag_no_case("bne"),
-                tag_no_case("blt"),
-                tag_no_case("bge"),
-            )),
-        ),
+/// An instruction of the ISA table or the custom instructions, with its
+/// operands written the way the table says. The lexer has already split
+/// `mne` off the front of `operands`.
+fn parse_instruction<'a>(
+    mne: &str,
+    operands: &'a str,
+    instructions: &CustomInstructions,
+) -> IResult<&'a str, Text, Error<&'a str>> {
+    if let Some(opcode) = lookup(mne) {
+        let (rest, parsed) = parse_operands(opcode.syntax, operands)?;
+        return Ok((rest, instruction_text(None, mne, None, parsed)));
+    }
+    match instructions.get(mne) {
+        Some(definition) => {
+            let (rest, parsed) = parse_custom_operands(definition.format, operands)?;
+            let encoding = Some(definition.encoding());
+            Ok((rest, instruction_text(None, &definition.name, encoding, parsed)))
+        }
+        None => Err(nom::Err::Error(Error::from_error_kind(operands, ErrorKind::Tag))),
+    }
+}
+
+fn parse_pseudo<'a>(mne: &str, i: &'a str) -> IResult<&'a str, Pseudo, Error<&'a str>> {
+    let mne = mne.to_ascii_lowercase();
+    let (i, rd) = match mne.as_str() {
+        "call" => (i, 1),
+        _ => terminated(first_reg, comma)(i)?,
+    };
+    let (i, target) = preceded(space0, parse_expr)(i)?;
+    let (i, base) = match STORES.contains(&mne.as_str()) {
+        true => preceded(comma, first_reg)(i)?,
+        false => (i, rd),
+    };
+    let (i, _) = multispace0(i)?;
+    Ok((i, Pseudo { mne, rd, base, target }))
+}
+
+/// The instructions a pseudo-instruction on `line` stands for. They load
+/// the address with `lui` and `%hi`/`%lo`, or with `auipc` and
+/// `%pcrel_hi`/`%pcrel_lo` for position-independent code, which needs a
+/// label on the `auipc`.
+fn expand_pseudo(pseudo: Pseudo, label: Option<&str>, location: Location, pic: bool) -> Vec<Item> {
+    let Pseudo { mne, rd, base, target } = pseudo;
+    let store = STORES.contains(&mne.as_str());
+    let reloc = |reloc, expr| Some(Expr::Reloc(reloc, Box::new(expr)));
+    let auipc = format!(".Lpcrel^{}", location.line);
+    let (upper, hi, lo) = match pic {
+        true => ("auipc", reloc(Reloc::PcrelHi, target), reloc(Reloc::PcrelLo, Expr::Sym(auipc.clone()))),
+        false => ("lui", reloc(Reloc::Hi, target.clone()), reloc(Reloc::Lo, target)),
+    };
+    let first = Operands {
+        rd: Some(base),
+        imm_expr: hi,
+        ..Operands::default()
+    };
+    let second = Operands {
+        rd: (!store).then_some(rd),
+        rs1: Some(base),
+        rs2: store.then_some(rd),
+        imm_expr: lo,
+        ..Operands::default()
+    };
+    let second_mne = match mne.as_str() {
+        "la" => "addi",
+        "call" => "jalr",
+        load_or_store => load_or_store,
+    };
+    let mut items = vec![];
+    let first_label = match pic {
+        true => {
+            items.extend(label.map(|label| Item::Label(label.to_string(), location)));
+            Some(auipc.as_str())
+        }
+        false => label,
+    };
+    for (label, mne, operands) in [(first_label, upper, first), (None, second_mne, second)] {
+        let mut text = instruction_text(label, mne, None, operands);
+        text.location = location;
+        text.pseudo = true;
+        items.push(Item::Text(text));
+    }
+    items
+}
+
+fn parse_constant(i: &str) -> IResult<&str, Constant, Error<&str>> {
+    let dir = terminated(
+        preceded(space0, alt((tag_no_case(".equ"), tag_no_case(".set")))),
         space1,
     );
-    let rs1_p = reg;
-    let rs2_p = preceded(tag(","), reg);
-    let imm_p = terminated(
-        preceded(
-            // Remove beginning whitespace
-            preceded(preceded(space0, tag(",")), space0),
-            parse_imm,
-        ),
+    let expr_p = terminated(
+        preceded(preceded(preceded(space0, tag(",")), space0), parse_expr),
         multispace0,
     );
+    map(tuple((dir, parse_symbol, expr_p)), |(_, name, expr)| {
+        Constant {
+            name: name.to_string(),
+            expr,
+            location: Location::default(),
+        }
+    })(i)
+}
 
-    map(
-        tuple((label_p, mne_p, rs1_p, rs2_p, imm_p)),
-        |(label, mne, rs1, rs2, (imm1, imm2))| Text {
-            instruction: InstructionData {
-                mne: mne.to_string(),
-                rs1: str_to_reg(rs1),
-                rs2: str_to_reg(rs2),
-                rd: None,
-                imm: match imm1 {
-                    Some("0x") => Some(u32::from_str_radix(imm2, 16).unwrap()),
-                    Some("0b") => Some(u32::from_str_radix(imm2, 2).unwrap()),
-                    None => Some(imm2.parse::<Imm>().unwrap()),
-                    _ => unreachable!(),
-                },
-            },
-            label: label.map(|s| String::from(s)),
-            label_dst: None,
-        },
-    )(i)
+/// A fixed field of `.insn`, which has to be a constant.
+fn parse_field(i: &str) -> IResult<&str, u32, Error<&str>> {
+    let named = alt((
+        map(tag_no_case("custom_0"), |_| 0x0b),
+        map(tag_no_case("custom_1"), |_| 0x2b),
+        map(tag_no_case("custom_2"), |_| 0x5b),
+        map(tag_no_case("custom_3"), |_| 0x7b),
+    ));
+    let constant = map_opt(parse_expr, |expr| {
+        expr.constant().and_then(|n| u32::try_from(n).ok())
+    });
+    preceded(space0, alt((named, constant)))(i)
 }
 
-fn parse_branch_pseudo_instr(i: &str) -> IResult<&str, Text, VerboseError<&str>> {
-    let label_p = opt(parse_label);
-    let mne_p = terminated(
-        preceded(
-            space0,
-            alt((
-                tag_no_case("bltu"),
-                tag_no_case("bgeu"),
-                tag_no_case("beq"),
-                tag_no_case("bne"),
-                tag_no_case("blt"),
-                tag_no_case("bge"),
-            )),
-        ),
-        space1,
+/// `.insn format opcode, funct3, funct7, operands...` for an instruction
+/// the assembler doesn't know, with as many fixed fields as the format has
+/// (`funct7` only for R, no `funct3` for U and J), like GNU as.
+fn parse_insn(i: &str) -> IResult<&str, Text, Error<&str>> {
+    let format_p = preceded(
+        terminated(directive(".insn"), space1),
+        alt((
+            map(tag_no_case("sb"), |_| Format::B),
+            map(tag_no_case("uj"), |_| Format::J),
+            map(tag_no_case("r"), |_| Format::R),
+            map(tag_no_case("i"), |_| Format::I),
+            map(tag_no_case("s"), |_| Format::S),
+            map(tag_no_case("b"), |_| Format::B),
+            map(tag_no_case("u"), |_| Format::U),
+            map(tag_no_case("j"), |_| Format::J),
+        )),
     );
-    let rs1_p = reg;
-    let rs2_p = preceded(tag(","), reg);
-    let label_dst_p = terminated(
-        preceded(
-            // Remove beginning whitespace
-            preceded(preceded(space0, tag(",")), space0),
-            is_not(" \t\r\n:"),
+    let (i, (label, format)) = pair(opt(parse_label), format_p)(i)?;
+    let field = || preceded(preceded(space0, tag(",")), parse_field);
+    let (i, opcode) = preceded(space1, parse_field)(i)?;
+    let (i, (funct3, funct7)) = match format {
+        Format::R => pair(field(), field())(i)?,
+        Format::U | Format::J => (i, (0, 0)),
+        _ => map(field(), |funct3| (funct3, 0))(i)?,
+    };
+    let encoding = Encoding {
+        format,
+        opcode,
+        funct3,
+        funct7,
+    };
+    let (i, operands) = verify(
+        preceded(preceded(space0, tag(",")), |i| parse_custom_operands(format, i)),
+        |_| check_fields(&encoding).is_ok(),
+    )(i)?;
+    Ok((i, instruction_text(label, ".insn", Some(encoding), operands)))
+}
+
+/// Matches the directive `name`, but not a longer name that starts with it.
+fn directive<'a>(
+    name: &'static str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, Error<&'a str>> {
+    preceded(
+        space0,
+        terminated(
+            tag_no_case(name),
+            not(peek(satisfy(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.'))),
         ),
-        multispace0,
+    )
+}
+
+/// A comma followed by an expression.
+fn next_arg(i: &str) -> IResult<&str, Expr, Error<&str>> {
+    preceded(preceded(preceded(space0, tag(",")), space0), parse_expr)(i)
+}
+
+fn parse_section(i: &str) -> IResult<&str, Section, Error<&str>> {
+    let short = alt((
+        map(directive(".text"), |_| Section::new(".text", None, None)),
+        map(directive(".data"), |_| Section::new(".data", None, None)),
+        map(directive(".bss"), |_| Section::new(".bss", None, None)),
+    ));
+    let flags = preceded(
+        preceded(preceded(space0, tag(",")), space0),
+        delimited(char('"'), take_while(|c: char| c.is_ascii_alphabetic()), char('"')),
+    );
+    let kind = preceded(
+        preceded(preceded(space0, tag(",")), space0),
+        preceded(one_of("@%"), alphanumeric1),
+    );
+    let long = map(
+        tuple((
+            terminated(directive(".section"), space1),
+            parse_symbol,
+            opt(flags),
+            opt(kind),
+        )),
+        |(_, name, flags, kind)| Section::new(name, flags, kind),
     );
+    terminated(alt((short, long)), multispace0)(i)
+}
 
+fn parse_binding(i: &str) -> IResult<&str, Vec<(String, Binding)>, Error<&str>> {
+    let binding = alt((
+        map(alt((directive(".globl"), directive(".global"))), |_| Binding::Global),
+        map(directive(".local"), |_| Binding::Local),
+    ));
+    let names = separated_list1(
+        delimited(space0, tag(","), space0),
+        map(parse_symbol, String::from),
+    );
     map(
-        tuple((label_p, mne_p, rs1_p, rs2_p, label_dst_p)),
-        |(label, mne, rs1, rs2, label_dst)| Text {
-            instruction: InstructionData {
-                mne: mne.to_string(),
-                rs1: str_to_reg(rs1),
-                rs2: str_to_reg(rs2),
-                rd: None,
-                imm: None,
-            },
-
-            label: label.map(|s| String::from(s)),
-            label_dst: Some(label_dst.to_string()),
-        },
+        terminated(separated_pair(binding, space1, names), multispace0),
+        |(binding, names)| names.into_iter().map(|name| (name, binding)).collect(),
     )(i)
 }
 
-fn parse_imm_instr(i: &str) -> IResult<&str, Text, VerboseError<&str>> {
-    let label_p = opt(parse_label);
-    let mne_p = terminated(
-        preceded(
-            space0,
-            alt((
-                tag_no_case("addi"),
-                tag_no_case("xori"),
-                tag_no_case("ori"),
-                tag_no_case("andi"),
-                tag_no_case("slli"),
-                tag_no_case("srli"),
-                tag_no_case("srai"),
-                tag_no_case("slti"),
-                tag_no_case("sltiu"),
-            )),
+fn parse_layout(i: &str) -> IResult<&str, Item, Error<&str>> {
+    let align = alt((
+        map(
+            preceded(alt((directive(".align"), directive(".p2align"))), space1),
+            |_| true,
         ),
-        space1,
+        map(preceded(directive(".balign"), space1), |_| false),
+    ));
+    let align = map(
+        tuple((align, parse_expr, opt(next_arg))),
+        |(pow2, n, fill)| Item::Align {
+            bytes: match pow2 {
+                true => Expr::Bin(BinOp::Shl, Box::new(Expr::Num(1)), Box::new(n)),
+                false => n,
+            },
+            fill,
+            location: Location::default(),
+        },
     );
-    let rd_p = reg;
-    let rs1_p = preceded(tag(","), reg);
-    let imm_p = terminated(
-        preceded(
-            // Remove beginning whitespace
-            preceded(preceded(space0, tag(",")), space0),
-            parse_imm,
-        ),
-        multispace0,
+    let space = map(
+        tuple((
+            preceded(
+                alt((directive(".space"), directive(".skip"), directive(".zero"))),
+                space1,
+            ),
+            parse_expr,
+            opt(next_arg),
+        )),
+        |(_, size, fill)| Item::Space {
+            size,
+            fill,
+            location: Location::default(),
+        },
     );
+    let org = map(preceded(preceded(directive(".org"), space1), parse_expr), |offset| {
+        Item::Org(offset, Location::default())
+    });
+    let fill = map(
+        tuple((
+            preceded(directive(".fill"), space1),
+            parse_expr,
+            opt(next_arg),
+            opt(next_arg),
+        )),
+        |(_, repeat, size, value)| Item::Fill {
+            repeat,
+            size: size.unwrap_or(Expr::Num(1)),
+            value: value.unwrap_or(Expr::Num(0)),
+            location: Location::default(),
+        },
+    );
+    terminated(alt((align, space, org, fill)), multispace0)(i)
+}
+
+/// The label at the start of `line`, if there is one, and what follows it.
+pub fn split_label(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    let end = line.find(|c: char| c == ':' || c.is_ascii_whitespace())?;
+    match line[end..].starts_with(':') && end > 0 {
+        true => Some((&line[..end], &line[end + 1..])),
+        false => None,
+    }
+}
+
+/// The pseudo-instructions and their operands.
+pub const PSEUDO_INSTRUCTIONS: [(&str, &str); 2] = [("la", "rd, symbol"), ("call", "symbol")];
+
+/// Loads, which also take `rd, symbol` to load from a symbol.
+const LOADS: [&str; 5] = ["lb", "lh", "lw", "lbu", "lhu"];
+
+/// Stores, which also take `rs2, symbol, rt` to store at a symbol, with its
+/// address in `rt`.
+const STORES: [&str; 3] = ["sb", "sh", "sw"];
 
+/// Every directive, for editors to offer.
+pub const DIRECTIVES: [&str; 36] = [
+    ".text", ".data", ".bss", ".section", ".globl", ".global", ".local", ".equ", ".set", ".byte",
+    ".half", ".2byte", ".word", ".4byte", ".dword", ".8byte", ".ascii", ".string", ".asciz",
+    ".float", ".double", ".align", ".p2align", ".balign", ".space", ".skip", ".zero", ".org",
+    ".fill", ".insn", ".if", ".ifdef", ".ifndef", ".elseif", ".else", ".endif",
+];
+
+/// A statement without labels, which the first word says the grammar of.
+fn parse_statement<'a>(
+    line: &'a str,
+    instructions: &CustomInstructions,
+) -> IResult<&'a str, Statement, Error<&'a str>> {
+    let (word, operands) = line.split_at(line.find(|c: char| c.is_ascii_whitespace()).unwrap_or(line.len()));
+    let is = |names: &[&str]| names.iter().any(|name| word.eq_ignore_ascii_case(name));
+    let item = |item| Statement::Item(item);
+    if PSEUDO_INSTRUCTIONS.iter().any(|(mne, _)| is(&[mne])) {
+        map(|i| parse_pseudo(word, i), Statement::Pseudo)(operands)
+    } else if !word.starts_with('.') {
+        let instruction = map(|i| parse_instruction(word, i, instructions), |text| item(Item::Text(text)))(operands);
+        match instruction {
+            Ok(("", _)) => instruction,
+            _ if is(&LOADS) || is(&STORES) => map(|i| parse_pseudo(word, i), Statement::Pseudo)(operands).or(instruction),
+            _ => instruction,
+        }
+    } else if is(&[".insn"]) {
+        map(parse_insn, |text| item(Item::Text(text)))(line)
+    } else if is(&[".ascii", ".string", ".asciz"]) {
+        map(parse_string, |data| item(Item::Data(data)))(line)
+    } else if is(&[".float", ".double"]) {
+        map(parse_floatline, |data| item(Item::Data(data)))(line)
+    } else if is(&[".byte", ".half", ".2byte", ".word", ".4byte", ".dword", ".8byte"]) {
+        map(parse_dataline, |data| item(Item::Data(data)))(line)
+    } else if is(&[".text", ".data", ".bss", ".section"]) {
+        map(parse_section, Statement::Section)(line)
+    } else if is(&[".equ", ".set"]) {
+        map(parse_constant, Statement::Constant)(line)
+    } else if is(&[".globl", ".global", ".local"]) {
+        map(parse_binding, Statement::Binding)(line)
+    } else {
+        map(parse_layout, item)(line)
+    }
+}
+
+/// Whether `statement`, a line without its labels and comment, is one the
+/// assembler can parse.
+pub fn is_statement(statement: &str, instructions: &CustomInstructions) -> bool {
+    matches!(parse_statement(statement, instructions), Ok(("", _)))
+}
+
+/// Splits the source into statements a line at a time. The first word of a
+/// line picks the one grammar the rest of it is parsed with, so nothing is
+/// parsed twice. A label goes with the instruction or data after it, even
+/// on a later line, and is an item of its own otherwise.
+fn parse_lines(source: &str, options: &Options) -> Result<Vec<Statement>, Diagnostic> {
+    let mut statements = vec![];
+    let mut pending: Option<(&str, Location)> = None;
+    let label_item = |(label, location): (&str, Location)| Statement::Item(Item::Label(label.to_string(), location));
+    for (n, line) in source.lines().enumerate() {
+        let at = |rest: &str| {
+            let indent = line.len() - rest.trim_start().len();
+            Location {
+                line: n + 1,
+                column: line[..indent].chars().count() + 1,
+            }
+        };
+        let mut rest = line;
+        while let Some((label, after)) = split_label(rest) {
+            if let Some(previous) = pending.replace((label, at(rest))) {
+                statements.push(label_item(previous));
+            }
+            rest = after;
+        }
+        let location = at(rest);
+        let rest = rest.trim();
+        if rest.is_empty() {
+            continue;
+        }
+        let mut statement = match parse_statement(rest, &options.instructions) {
+            Ok(("", statement)) => statement,
+            _ => return Err(Diagnostic::at(n + 1, format!("can't parse `{}`", rest))),
+        };
+        match &mut statement {
+            Statement::Pseudo(pseudo) => {
+                let label = pending.take().map(|(label, _)| label);
+                let items = expand_pseudo(pseudo.clone(), label, location, options.pic);
+                statements.extend(items.into_iter().map(Statement::Item));
+                continue;
+            }
+            Statement::Item(Item::Text(text)) => {
+                text.label = pending.take().map(|(label, _)| label.to_string());
+                text.location = location;
+            }
+            Statement::Item(Item::Data(data)) => {
+                data.label = pending.take().map(|(label, _)| label.to_string());
+                data.location = location;
+            }
+            Statement::Item(item) => {
+                statements.extend(pending.take().map(label_item));
+                *item.location_mut() = location;
+            }
+            Statement::Constant(constant) => {
+                statements.extend(pending.take().map(label_item));
+                constant.location = location;
+            }
+            Statement::Section(_) | Statement::Binding(_) => statements.extend(pending.take().map(label_item)),
+        }
+        statements.push(statement);
+    }
+    statements.extend(pending.map(label_item));
+    Ok(statements)
+}
+
+/// A string literal in double quotes, as bytes. Runs of plain characters
+/// are copied into the one buffer whole, with escapes in between them.
+fn parse_quoted(i: &str) -> IResult<&str, Vec<u8>, Error<&str>> {
+    let (mut i, _) = char('"')(i)?;
+    let mut bytes = vec![];
+    loop {
+        if let Ok((rest, text)) = is_not::<_, _, Error<&str>>("\\\"\n")(i) {
+            bytes.extend_from_slice(text.as_bytes());
+            i = rest;
+        }
+        match parse_escape(i) {
+            Ok((rest, byte)) => {
+                bytes.push(byte);
+                i = rest;
+            }
+            Err(_) => break,
+        }
+    }
+    let (i, _) = cut(char('"'))(i)?;
+    Ok((i, bytes))
+}
+
+/// `.ascii`, or `.string`/`.asciz` which end each string with a NUL.
+fn parse_string(i: &str) -> IResult<&str, Data, Error<&str>> {
+    let dir = alt((
+        map(directive(".ascii"), |_| false),
+        map(alt((directive(".string"), directive(".asciz"))), |_| true),
+    ));
+    let strings = separated_list1(delimited(space0, char(','), space0), parse_quoted);
     map(
-        tuple((label_p, mne_p, rd_p, rs1_p, imm_p)),
-        |(label, mne, rd, rs1, (imm1, imm2))| Text {
-            instruction: InstructionData {
-                mne: mne.to_string(),
-                rs1: str_to_reg(rs1),
-                rs2: None,
-                rd: str_to_reg(rd),
-                imm: match imm1 {
-                    Some("0x") => Some(u32::from_str_radix(imm2, 16).unwrap()),
-                    Some("0b") => Some(u32::from_str_radix(imm2, 2).unwrap()),
-                    None => Some(imm2.parse::<Imm>().unwrap()),
-                    _ => unreachable!(),
-                },
-            },
-            label: label.map(|s| String::from(s)),
-            label_dst: None,
+        terminated(
+            tuple((opt(parse_label), dir, preceded(space1, strings))),
+            multispace0,
+        ),
+        |(label, nul, strings)| Data {
+            label: label.map(String::from),
+            size: DataSize::Byte,
+            data: strings
+                .into_iter()
+                .flat_map(|mut s| {
+                    if nul {
+                        s.push(0);
+                    }
+                    s
+                })
+                .collect(),
+            exprs: vec![],
+            location: Location::default(),
         },
     )(i)
 }
 
-fn parse_reg_instr(i: &str) -> IResult<&str, Text, VerboseError<&str>> {
-    let label_p = opt(parse_label);
-    let mne_p = terminated(
-        preceded(
-            space0,
-            alt((
-                tag_no_case("add"),
-                tag_no_case("sub"),
-                tag_no_case("xor"),
-                tag_no_case("or"),
-                tag_no_case("and"),
-                tag_no_case("sll"),
-                tag_no_case("srl"),
-                tag_no_case("sra"),
-                tag_no_case("slt"),
-                tag_no_case("sltu"),
-            )),
-        ),
-        space1,
-    );
-    let rd_p = reg;
-    let rs1_p = preceded(tag(","), reg);
-    let rs2_p = terminated(preceded(tag(","), reg), multispace0);
+fn parse_datasize(i: &str) -> IResult<&str, DataSize, Error<&str>> {
+    alt((
+        map(directive(".byte"), |_| DataSize::Byte),
+        map(alt((directive(".half"), directive(".2byte"))), |_| DataSize::Half),
+        map(alt((directive(".word"), directive(".4byte"))), |_| DataSize::Word),
+        map(alt((directive(".dword"), directive(".8byte"))), |_| DataSize::Dword),
+    ))(i)
+}
 
+/// `.float` and `.double`, which take IEEE 754 literals rather than
+/// expressions.
+fn parse_floatline(i: &str) -> IResult<&str, Data, Error<&str>> {
+    let comma = || delimited(space0, char(','), space0);
+    let floats = map(
+        preceded(terminated(directive(".float"), space1), separated_list1(comma(), float)),
+        |floats| floats.iter().flat_map(|f| f.to_be_bytes()).collect(),
+    );
+    let doubles = map(
+        preceded(terminated(directive(".double"), space1), separated_list1(comma(), double)),
+        |doubles| doubles.iter().flat_map(|d| d.to_be_bytes()).collect(),
+    );
     map(
-        tuple((label_p, mne_p, rd_p, rs1_p, rs2_p)),
-        |(label, mne, rd, rs1, rs2)| Text {
-            instruction: InstructionData {
-                mne: mne.to_string(),
-                rs1: str_to_reg(rs1),
-                rs2: str_to_reg(rs2),
-                rd: str_to_reg(rd),
-                imm: None,
-            },
-            label: label.map(|s| String::from(s)),
-            label_dst: None,
+        terminated(pair(opt(parse_label), alt((floats, doubles))), multispace0),
+        |(label, data)| Data {
+            label: label.map(String::from),
+            data,
+            size: DataSize::Byte,
+            exprs: vec![],
+            location: Location::default(),
         },
     )(i)
 }
 
-fn parse_uj_instr(i: &str) -> IResult<&str, Text, VerboseError<&str>> {
-    let label_p = opt(parse_label);
-    let mne_p = terminated(
+fn parse_datalist(i: &str) -> IResult<&str, Vec<Expr>, Error<&str>> {
+    terminated(
         preceded(
             space0,
-            alt((tag_no_case("lui"), tag_no_case("auipc"), tag_no_case("jal"))),
+            separated_list1(preceded(space0, terminated(char(','), space0)), parse_imm),
         ),
-        space1,
-    );
-    let rd_p = reg;
-    let imm_p = terminated(preceded(space0, parse_imm), multispace0);
-    map(
-        tuple((label_p, mne_p, rd_p, imm_p)),
-        |(label, mne, rd, (imm1, imm2))| Text {
-            instruction: InstructionData {
-                mne: mne.to_string(),
-                rs1: None,
-                rs2: None,
-                rd: str_to_reg(rd),
-                imm: match imm1 {
-                    Some("0x") => Some(u32::from_str_radix(imm2, 16).unwrap()),
-                    Some("0b") => Some(u32::from_str_radix(imm2, 2).unwrap()),
-                    None => Some(imm2.parse::<Imm>().unwrap()),
-                    _ => unreachable!(),
-                },
-            },
-            label: label.map(|s| String::from(s)),
-            label_dst: None,
-        },
+        multispace0,
     )(i)
 }
 
-fn parse_jal_pseudo_instr(i: &str) -> IResult<&str, Text, VerboseError<&str>> {
+fn parse_dataline(i: &str) -> IResult<&str, Data, Error<&str>> {
     let label_p = opt(parse_label);
-    let mne_p = terminated(preceded(space0, tag_no_case("jal")), space1);
-    let rd_p = reg;
-    let label_dst_p = terminated(preceded(space0, is_not(" \n\t\r:")), multispace0);
+    let size_p = terminated(parse_datasize, space1);
     map(
-        tuple((label_p, mne_p, rd_p, label_dst_p)),
-        |(label, mne, rd, label_dst)| Text {
-            instruction: InstructionData {
-                mne: mne.to_string(),
-                rs1: None,
-                rs2: None,
-                rd: str_to_reg(rd),
-                imm: None,
-            },
-            label: label.map(|s| String::from(s)),
-            label_dst: Some(label_dst.to_string()),
+        tuple((label_p, size_p, parse_datalist)),
+        |(label, size, exprs)| Data {
+            label: label.map(String::from),
+            data: vec![],
+            size,
+            exprs,
+            location: Location::default(),
         },
     )(i)
 }
 
-fn parse_jalr_instr(i: &str) -> IResult<&str, Text, VerboseError<&str>> {
-    let label_p = opt(parse_label);
-    let mne_p = terminated(preceded(space0, tag_no_case("jalr")), space1);
-    let rd_p = reg;
-    let imm_p = preceded(
-        // Remove beginning whitespace and comma
-        preceded(preceded(space0, tag(",")), space0),
-        // Gets numbers
-        parse_imm,
-    );
-    let rs1_p = terminated(inside_par, multispace0);
-    map(
-        tuple((label_p, mne_p, rd_p, imm_p, rs1_p)),
-        |(label, mne, rd, (imm1, imm2), rs1)| Text {
-            instruction: InstructionData {
-                mne: mne.to_string(),
-                rs1: str_to_reg(rs1),
-                rs2: None,
-                rd: str_to_reg(rd),
-                imm: match imm1 {
-                    Some("0x") => Some(u32::from_str_radix(imm2, 16).unwrap()),
-                    Some("0b") => Some(u32::from_str_radix(imm2, 2).unwrap()),
-                    None => Some(imm2.parse::<Imm>().unwrap()),
-                    _ => unreachable!(),
+/// Where the `#` comment on `line` starts, if it has one outside a string
+/// or character.
+pub fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escape = false;
+    for (idx, c) in line.char_indices() {
+        match quote {
+            Some(_) if escape => escape = false,
+            Some(_) if c == '\\' => escape = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return Some(idx),
+            None => {}
+        }
+    }
+    None
+}
+
+/// Removes `#` comments, keeping line breaks so that line numbers in errors
+/// still match the source.
+fn strip_comments(i: &str) -> String {
+    i.lines()
+        .map(|line| &line[..comment_start(line).unwrap_or(line.len())])
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A `.if` block: whether the lines in it are assembled, and whether one of
+/// its branches already was.
+struct Conditional {
+    line: usize,
+    /// The block around this one is assembled.
+    enclosing: bool,
+    taken: bool,
+    active: bool,
+    seen_else: bool,
+}
+
+/// Where a line's condition is decided: the value of a constant known so
+/// far, or `None` for a label or a constant that isn't known yet.
+type Known = HashMap<String, Option<i64>>;
+
+fn condition(rest: &str, known: &Known) -> Result<bool, String> {
+    let expr = match terminated(parse_expr, space0)(rest) {
+        Ok(("", expr)) => expr,
+        _ => return Err(format!("can't parse the condition `{}`", rest.trim())),
+    };
+    let value = expr.eval(&|name: &str| match known.get(name) {
+        Some(Some(value)) => Ok(*value),
+        Some(None) => Err(format!("`{}` isn't a constant known before the `.if`", name)),
+        None => Err(format!("undefined symbol `{}`", name)),
+    })?;
+    Ok(value != 0)
+}
+
+fn is_defined(rest: &str, known: &Known) -> Result<bool, String> {
+    match delimited(space1, parse_symbol, space0)(rest) {
+        Ok(("", name)) => Ok(known.contains_key(name)),
+        _ => Err(format!("expected a symbol, not `{}`", rest.trim())),
+    }
+}
+
+/// Notes the labels and constants a line defines, for later conditions.
+fn define_known(line: &str, known: &mut Known) {
+    let line = match split_label(line) {
+        Some((label, rest)) => {
+            known.insert(label.to_string(), None);
+            rest
+        }
+        None => line,
+    };
+    if !line.trim_start().starts_with('.') {
+        return;
+    }
+    if let Ok((_, constant)) = parse_constant(line) {
+        let value = constant.expr.eval(&|name: &str| {
+            known.get(name).copied().flatten().ok_or_else(String::new)
+        });
+        known.insert(constant.name, value.ok());
+    }
+}
+
+/// Blanks out the lines that `.if`, `.ifdef`, `.ifndef`, `.elseif` and
+/// `.else` skip, along with the directives, keeping the line breaks like
+/// `strip_comments` does. Skipped lines don't have to parse. Conditions can
+/// use `defines` and the constants and labels defined above them.
+fn conditionals(source: &str, defines: &[(String, i64)]) -> Result<String, Diagnostic> {
+    let mut known: Known = defines
+        .iter()
+        .map(|(name, value)| (name.clone(), Some(*value)))
+        .collect();
+    let mut stack: Vec<Conditional> = vec![];
+    let mut lines = vec![];
+    for (n, line) in source.lines().enumerate() {
+        let active = stack.last().is_none_or(|c| c.active);
+        let mut keyword_p = alt((
+            directive(".ifdef"),
+            directive(".ifndef"),
+            directive(".if"),
+            directive(".elseif"),
+            directive(".else"),
+            directive(".endif"),
+        ));
+        // Most lines aren't directives, so don't try them all on each one
+        let keyword = match line.trim_start().starts_with('.') {
+            true => keyword_p(line).ok(),
+            false => None,
+        };
+        let (rest, keyword) = match keyword {
+            Some((rest, keyword)) => (rest, keyword.to_lowercase()),
+            None => {
+                if active {
+                    define_known(line, &mut known);
+                }
+                lines.push(if active { line } else { "" });
+                continue;
+            }
+        };
+        lines.push("");
+        let context = |e: String| Diagnostic::at(n + 1, e);
+        let outside = || context(format!("`{}` without `.if`", keyword));
+        match keyword.as_ref() {
+            ".if" | ".ifdef" | ".ifndef" => {
+                let taken = match keyword.as_ref() {
+                    _ if !active => false,
+                    ".if" => condition(rest, &known).map_err(context)?,
+                    ".ifdef" => is_defined(rest, &known).map_err(context)?,
+                    _ => !is_defined(rest, &known).map_err(context)?,
+                };
+                stack.push(Conditional {
+                    line: n + 1,
+                    enclosing: active,
+                    taken,
+                    active: taken,
+                    seen_else: false,
+                });
+            }
+            ".elseif" => {
+                let top = stack.last_mut().ok_or_else(outside)?;
+                if top.seen_else {
+                    return Err(context("`.elseif` after `.else`".to_string()));
+                }
+                top.active = top.enclosing && !top.taken && condition(rest, &known).map_err(context)?;
+                top.taken |= top.active;
+            }
+            ".else" => {
+                let top = stack.last_mut().ok_or_else(outside)?;
+                if top.seen_else {
+                    return Err(context("`.else` after `.else`".to_string()));
+                }
+                top.seen_else = true;
+                top.active = top.enclosing && !top.taken;
+                top.taken = true;
+            }
+            _ => {
+                stack.pop().ok_or_else(outside)?;
+            }
+        }
+    }
+    match stack.last() {
+        Some(open) => Err(Diagnostic::at(open.line, "`.if` without `.endif`".to_string())),
+        None => Ok(lines.join("\n")),
+    }
+}
+
+/// Parses a `-D NAME=value` definition from the command line. The value can
+/// be any constant expression and defaults to 1.
+pub fn parse_define(s: &str) -> Result<(String, i64), String> {
+    let (name, value) = s.split_once('=').unwrap_or((s, "1"));
+    match parse_symbol(name) {
+        Ok(("", _)) => {}
+        _ => return Err(format!("`{}` is not a symbol name", name)),
+    }
+    match terminated(parse_expr, space0)(value) {
+        Ok(("", expr)) => expr
+            .constant()
+            .map(|value| (name.to_string(), value))
+            .ok_or_else(|| format!("`{}` is not a constant", value)),
+        _ => Err(format!("can't parse `{}`", value)),
+    }
+}
+
+/// The label a statement defines and the symbols it refers to.
+fn statement_symbols(statement: &mut Statement) -> (Option<&mut String>, Vec<&mut String>) {
+    fn exprs(exprs: Vec<&mut Expr>) -> Vec<&mut String> {
+        exprs.into_iter().flat_map(Expr::symbols_mut).collect()
+    }
+    match statement {
+        Statement::Item(Item::Text(text)) => {
+            let mut refs: Vec<_> = text.label_dst.iter_mut().collect();
+            refs.extend(exprs(text.imm_expr.iter_mut().collect()));
+            (text.label.as_mut(), refs)
+        }
+        Statement::Item(Item::Data(data)) => (data.label.as_mut(), exprs(data.exprs.iter_mut().collect())),
+        Statement::Item(Item::Label(label, _)) => (Some(label), vec![]),
+        Statement::Item(Item::Align { bytes: size, fill, .. } | Item::Space { size, fill, .. }) => {
+            let mut refs = vec![size];
+            refs.extend(fill.iter_mut());
+            (None, exprs(refs))
+        }
+        Statement::Item(Item::Org(offset, _)) => (None, exprs(vec![offset])),
+        Statement::Item(Item::Fill { repeat, size, value, .. }) => (None, exprs(vec![repeat, size, value])),
+        Statement::Constant(constant) => (None, exprs(vec![&mut constant.expr])),
+        Statement::Section(_) | Statement::Binding(_) | Statement::Pseudo(_) => (None, vec![]),
+    }
+}
+
+/// The name given to the `n`th definition of numeric label `label`. It
+/// starts with `.L`, so it stays out of the symbol table.
+fn local_name(label: &str, n: usize) -> String {
+    format!(".L{}^{}", label, n)
+}
+
+/// Numeric labels like `1:` can be defined any number of times. Each
+/// definition gets a name of its own, `1b` refers to the closest one before
+/// it and `1f` to the closest one after.
+fn rename_local_labels(statements: &mut [Statement]) -> Result<(), Diagnostic> {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit());
+    let mut defined: HashMap<String, usize> = HashMap::new();
+    let mut forward = vec![];
+    for statement in statements {
+        let line = match statement {
+            Statement::Item(item) => item.location().line,
+            Statement::Constant(constant) => constant.location.line,
+            _ => 0,
+        };
+        let (label, refs) = statement_symbols(statement);
+        if let Some(label) = label.filter(|label| is_number(label)) {
+            let count = defined.entry(label.clone()).or_default();
+            *count += 1;
+            *label = local_name(label, *count);
+        }
+        for name in refs {
+            let direction = match name.chars().last() {
+                Some('b') => "b",
+                Some('f') => "f",
+                _ => continue,
+            };
+            let number = &name[..name.len() - 1];
+            if !is_number(number) {
+                continue;
+            }
+            let count = defined.get(number).copied().unwrap_or(0);
+            let renamed = match direction {
+                "b" if count == 0 => {
+                    return Err(Diagnostic::at(line, format!("`{}` has no `{}:` before it", name, number)));
+                }
+                "b" => local_name(number, count),
+                _ => {
+                    forward.push((number.to_string(), count + 1, name.clone(), line));
+                    local_name(number, count + 1)
+                }
+            };
+            *name = renamed;
+        }
+    }
+    for (number, n, name, line) in forward {
+        if defined.get(&number).copied().unwrap_or(0) < n {
+            return Err(Diagnostic::at(line, format!("`{}` has no `{}:` after it", name, number)));
+        }
+    }
+    Ok(())
+}
+
+/// What a file is parsed with, besides its text.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Constants defined before the first line, as by `-D`.
+    pub defines: Vec<(String, i64)>,
+    pub instructions: CustomInstructions,
+    /// Load addresses relative to the PC, so the program runs wherever it's
+    /// copied to.
+    pub pic: bool,
+}
+
+/// Parses a whole assembly file. The file starts in .text, and `.text`,
+/// `.data`, `.bss` and `.section` switch between sections.
+pub fn parse(i: &str) -> Result<FullFile, Diagnostic> {
+    parse_with(i, &Options::default())
+}
+
+pub fn parse_with(i: &str, options: &Options) -> Result<FullFile, Diagnostic> {
+    let defines = &options.defines;
+    let source = conditionals(&strip_comments(i), defines)?;
+    let mut file = FullFile {
+        sections: vec![Section::new(".text", None, None)],
+        consts: defines
+            .iter()
+            .map(|(name, value)| Constant {
+                name: name.clone(),
+                expr: Expr::Num(*value),
+                location: Location::default(),
+            })
+            .collect(),
+        ..FullFile::default()
+    };
+    let mut current = 0;
+
+    let mut statements = parse_lines(&source, options)?;
+    rename_local_labels(&mut statements)?;
+    for statement in statements {
+        match statement {
+            Statement::Section(section) => {
+                current = match file.sections.iter().position(|s| s.name == section.name) {
+                    Some(index) => index,
+                    None => {
+                        file.sections.push(section);
+                        file.sections.len() - 1
+                    }
+                }
+            }
+            Statement::Item(item) => file.sections[current].items.push(item),
+            Statement::Constant(constant) => file.consts.push(constant),
+            Statement::Binding(bindings) => file.bindings.extend(bindings),
+            // `parse_lines` has already expanded them
+            Statement::Pseudo(_) => unreachable!(),
+        }
+    }
+    if options.pic {
+        absolute_pseudos(&mut file);
+    }
+
+    Ok(file)
+}
+
+/// With `--pic`, a pseudo-instruction whose symbol is a number or a
+/// constant, like a device address from `.equ`, rather than a label goes
+/// back to `lui` and `%hi`/`%lo`, so it means the same wherever the program
+/// is loaded.
+fn absolute_pseudos(file: &mut FullFile) {
+    let mut absolute: HashSet<&str> = HashSet::new();
+    loop {
+        let known = absolute.len();
+        for constant in &file.consts {
+            if constant.expr.symbols().iter().all(|symbol| absolute.contains(symbol)) {
+                absolute.insert(&constant.name);
+            }
+        }
+        if absolute.len() == known {
+            break;
+        }
+    }
+    for section in &mut file.sections {
+        for n in 1..section.items.len() {
+            let target = match &section.items[n - 1] {
+                Item::Text(text) if text.pseudo && text.instruction.mne == "auipc" => match &text.imm_expr {
+                    Some(Expr::Reloc(Reloc::PcrelHi, target)) if target.symbols().iter().all(|symbol| absolute.contains(symbol)) => {
+                        (**target).clone()
+                    }
+                    _ => continue,
                 },
-            },
-            label: label.map(|s| String::from(s)),
-            label_dst: None,
-        },
-    )(i)
+                _ => continue,
+            };
+            if let Item::Text(text) = &mut section.items[n - 1] {
+                text.instruction.mne = "lui".to_string();
+                text.imm_expr = Some(Expr::Reloc(Reloc::Hi, Box::new(target.clone())));
+            }
+            if let Item::Text(text) = &mut section.items[n] {
+                text.imm_expr = Some(Expr::Reloc(Reloc::Lo, Box::new(target)));
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use nom::bytes::complete::take_while1;
+
+    fn parse_instr(i: &str) -> IResult<&str, Text, Error<&str>> {
+        let (i, label) = opt(parse_label)(i)?;
+        let (operands, mne) = preceded(space0, take_while1(|c: char| c.is_ascii_alphanumeric()))(i)?;
+        let (rest, mut text) = parse_instruction(mne, operands, &CustomInstructions::default())?;
+        text.label = label.map(String::from);
+        Ok((rest, text))
+    }
 
     #[test]
     fn parse_load_instr_test1() {
-        let (leftover, result) = parse_load_instr("label: lw s1, 123(s2)").unwrap();
+        let (_leftover, result) = parse_instr("label: lw s1, 123(s2)").unwrap();
         assert_eq!(
             result,
             Text {
@@ -491,16 +1358,20 @@ mod tests {
                     rs2: None,
                     rd: Some(9),
                     imm: Some(123),
+                    custom: None,
                 },
                 label: Some("label".to_string()),
                 label_dst: None,
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_load_instr_test2() {
-        let (leftover, result) = parse_load_instr("lb s1, 0x1b3(s2)\n").unwrap();
+        let (_leftover, result) = parse_instr("lb s1, 0x1b3(s2)\n").unwrap();
         assert_eq!(
             result,
             Text {
@@ -510,18 +1381,24 @@ mod tests {
                     rs2: None,
                     rd: Some(9),
                     imm: Some(0x1b3),
+                    custom: None,
                 },
                 label: None,
 
                 label_dst: None,
+
+                imm_expr: None,
+
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_load_instr_test3() {
-        let (leftover, result) =
-            parse_load_instr("\t label: \n lhu s1, 0b101 (  s2 )  \n").unwrap();
+        let (_leftover, result) =
+            parse_instr("\t label: \n lhu s1, 0b101 (  s2 )  \n").unwrap();
         assert_eq!(
             result,
             Text {
@@ -531,17 +1408,23 @@ mod tests {
                     rs2: None,
                     rd: Some(9),
                     imm: Some(0b101),
+                    custom: None,
                 },
                 label: Some("label".to_string()),
 
                 label_dst: None,
+
+                imm_expr: None,
+
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_store_instr_test1() {
-        let (leftover, result) = parse_store_instr("label: sw s1, 123(s2)").unwrap();
+        let (_leftover, result) = parse_instr("label: sw s1, 123(s2)").unwrap();
         assert_eq!(
             result,
             Text {
@@ -551,17 +1434,23 @@ mod tests {
                     rs2: Some(9),
                     rd: None,
                     imm: Some(123),
+                    custom: None,
                 },
                 label: Some("label".to_string()),
 
                 label_dst: None,
+
+                imm_expr: None,
+
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_branch_instr_test1() {
-        let (leftover, result) = parse_branch_instr("label: beq s1, s2, 123").unwrap();
+        let (_leftover, result) = parse_instr("label: beq s1, s2, 123").unwrap();
         assert_eq!(
             result,
             Text {
@@ -571,16 +1460,20 @@ mod tests {
                     rs2: Some(18),
                     rd: None,
                     imm: Some(123),
+                    custom: None,
                 },
                 label: Some("label".to_string()),
                 label_dst: None,
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_branch_instr_test2() {
-        let (leftover, result) = parse_branch_instr("\tblt s1, s2, 0xa23\n").unwrap();
+        let (_leftover, result) = parse_instr("\tblt s1, s2, 0xa23\n").unwrap();
         assert_eq!(
             result,
             Text {
@@ -590,17 +1483,21 @@ mod tests {
                     rs2: Some(18),
                     rd: None,
                     imm: Some(0xA23),
+                    custom: None,
                 },
                 label: None,
                 label_dst: None,
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_branch_pseudo_instr_test1() {
-        let (leftover, result) =
-            parse_branch_pseudo_instr("label:\n blt s1, s2, label2\n").unwrap();
+        let (_leftover, result) =
+            parse_instr("label:\n blt s1, s2, label2\n").unwrap();
         assert_eq!(
             result,
             Text {
@@ -610,16 +1507,20 @@ mod tests {
                     rs2: Some(18),
                     rd: None,
                     imm: None,
+                    custom: None,
                 },
                 label: Some("label".to_string()),
                 label_dst: Some("label2".to_string()),
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_branch_pseudo_instr_test2() {
-        let (leftover, result) = parse_branch_pseudo_instr("bne zero, s2, label2").unwrap();
+        let (_leftover, result) = parse_instr("bne zero, s2, label2").unwrap();
         assert_eq!(
             result,
             Text {
@@ -629,16 +1530,20 @@ mod tests {
                     rs2: Some(18),
                     rd: None,
                     imm: None,
+                    custom: None,
                 },
                 label: None,
                 label_dst: Some("label2".to_string()),
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_imm_instr_test1() {
-        let (leftover, result) = parse_imm_instr("hello: addi zero, ra, 0b101010").unwrap();
+        let (_leftover, result) = parse_instr("hello: addi zero, ra, 0b101010").unwrap();
         assert_eq!(
             result,
             Text {
@@ -648,16 +1553,20 @@ mod tests {
                     rs2: None,
                     rd: Some(0),
                     imm: Some(0b101010),
+                    custom: None,
                 },
                 label: Some("hello".to_string()),
                 label_dst: None,
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_reg_instr_test1() {
-        let (leftover, result) = parse_reg_instr("hello: add zero, ra, sp").unwrap();
+        let (_leftover, result) = parse_instr("hello: add zero, ra, sp").unwrap();
         assert_eq!(
             result,
             Text {
@@ -667,16 +1576,20 @@ mod tests {
                     rs2: Some(2),
                     rd: Some(0),
                     imm: None,
+                    custom: None,
                 },
                 label: Some("hello".to_string()),
                 label_dst: None,
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_reg_instr_test2() {
-        let (leftover, result) = parse_reg_instr("hello:\n sll zero, ra, sp").unwrap();
+        let (_leftover, result) = parse_instr("hello:\n sll zero, ra, sp").unwrap();
         assert_eq!(
             result,
             Text {
@@ -686,16 +1599,22 @@ mod tests {
                     rs2: Some(2),
                     rd: Some(0),
                     imm: None,
+                    custom: None,
                 },
                 label: Some("hello".to_string()),
                 label_dst: None,
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
+        let (_leftover, upper) = parse_instr("hello:\n sll ZERO, Ra, SP").unwrap();
+        assert_eq!(upper, result);
     }
 
     #[test]
     fn parse_reg_uj_test1() {
-        let (leftover, result) = parse_uj_instr("hello:\njal zero 0x12312A").unwrap();
+        let (_leftover, result) = parse_instr("hello:\njal zero 0x12312A").unwrap();
         assert_eq!(
             result,
             Text {
@@ -705,16 +1624,20 @@ mod tests {
                     rs2: None,
                     rd: Some(0),
                     imm: Some(0x12312A),
+                    custom: None,
                 },
                 label: Some("hello".to_string()),
                 label_dst: None,
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_reg_uj_test2() {
-        let (leftover, result) = parse_uj_instr("lui s1 0x12312A").unwrap();
+        let (_leftover, result) = parse_instr("lui s1 0x12312A").unwrap();
         assert_eq!(
             result,
             Text {
@@ -724,16 +1647,20 @@ mod tests {
                     rs2: None,
                     rd: Some(9),
                     imm: Some(0x12312A),
+                    custom: None,
                 },
                 label: None,
                 label_dst: None,
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_reg_jal_pseudo_test1() {
-        let (leftover, result) = parse_jal_pseudo_instr("jal s1 cool_label").unwrap();
+        let (_leftover, result) = parse_instr("jal s1 cool_label").unwrap();
         assert_eq!(
             result,
             Text {
@@ -743,16 +1670,20 @@ mod tests {
                     rs2: None,
                     rd: Some(9),
                     imm: None,
+                    custom: None,
                 },
                 label: None,
                 label_dst: Some("cool_label".to_string()),
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_reg_jal_pseudo_test2() {
-        let (leftover, result) = parse_jal_pseudo_instr("label: jal zero anotherLabel").unwrap();
+        let (_leftover, result) = parse_instr("label: jal zero anotherLabel").unwrap();
         assert_eq!(
             result,
             Text {
@@ -762,16 +1693,20 @@ mod tests {
                     rs2: None,
                     rd: Some(0),
                     imm: None,
+                    custom: None,
                 },
                 label: Some("label".to_string()),
                 label_dst: Some("anotherLabel".to_string()),
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
 
     #[test]
     fn parse_reg_jalr_test1() {
-        let (leftover, result) = parse_jalr_instr("label: jalr zero, 0xabc(ra)").unwrap();
+        let (_leftover, result) = parse_instr("label: jalr zero, 0xabc(ra)").unwrap();
         assert_eq!(
             result,
             Text {
@@ -781,87 +1716,414 @@ mod tests {
                     rs2: None,
                     rd: Some(0),
                     imm: Some(0xabc),
+                    custom: None,
                 },
                 label: Some("label".to_string()),
                 label_dst: None,
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
             }
         );
     }
-}
 
-fn parse_text(i: &str) -> IResult<&str, Vec<Text>, VerboseError<&str>> {
-    preceded(
-        multispace0,
-        preceded(
-            opt(tag(".text")),
-            preceded(
-                multispace0,
-                many0(alt((
-                    parse_load_instr,
-                    parse_store_instr,
-                    parse_branch_instr,
-                    parse_branch_pseudo_instr,
-                    parse_imm_instr,
-                    parse_reg_instr,
-                    parse_uj_instr,
-                    parse_jal_pseudo_instr,
-                    parse_jalr_instr,
-                ))),
+    #[test]
+    fn parse_imm_expr_test1() {
+        let (_leftover, result) = parse_instr("addi a0, a0, (1 << 4) - 'A'").unwrap();
+        assert_eq!(result.instruction.imm, Some((16 - 65) as Imm));
+        assert_eq!(result.imm_expr, None);
+    }
+
+    #[test]
+    fn parse_imm_expr_test2() {
+        let (_leftover, result) = parse_instr("lw a0, BUF + 4(gp)").unwrap();
+        assert_eq!(result.instruction.imm, None);
+        assert_eq!(result.instruction.rs1, Some(3));
+        assert_eq!(result.imm_expr.unwrap().symbols(), vec!["BUF"]);
+    }
+
+    #[test]
+    fn parse_test1() {
+        let file = parse(
+            "# startup
+            .equ UART_BASE, 0x10000000 # comment
+            main: lui t0, UART_BASE >> 12
+            jal zero main
+            done:
+            .data
+            msg: .word '#', done - main
+            ",
+        )
+        .unwrap();
+        let text = &file.sections[0].items;
+        assert_eq!(text.len(), 3);
+        assert!(matches!(&text[1], Item::Text(t) if t.label_dst == Some("main".to_string())));
+        assert_eq!(text[2], Item::Label("done".to_string(), Location { line: 5, column: 13 }));
+        assert_eq!(file.consts[0].name, "UART_BASE");
+        assert_eq!(file.sections[1].name, ".data");
+        assert!(matches!(&file.sections[1].items[0], Item::Data(d) if d.exprs.len() == 2));
+    }
+
+    #[test]
+    fn parse_section_test1() {
+        let file = parse(
+            "
+            .section .rodata, \"a\", @progbits
+            .balign 8, 0xFF
+            .section .init, \"ax\"
+            .p2align 2
+            .bss
+            buf: .space 16
+            .text
+            .org 0x100
+            .section .rodata
+            .zero 4
+            ",
+        )
+        .unwrap();
+        let names: Vec<_> = file.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec![".text", ".rodata", ".init", ".bss"]);
+        assert!(file.sections[0].executable && !file.sections[1].executable);
+        assert!(file.sections[2].executable && file.sections[3].nobits);
+        assert_eq!(
+            file.sections[0].items,
+            vec![Item::Org(Expr::Num(0x100), Location { line: 9, column: 13 })]
+        );
+        assert_eq!(
+            file.sections[1].items,
+            vec![
+                Item::Align {
+                    bytes: Expr::Num(8),
+                    fill: Some(Expr::Num(0xFF)),
+                    location: Location { line: 3, column: 13 },
+                },
+                Item::Space {
+                    size: Expr::Num(4),
+                    fill: None,
+                    location: Location { line: 11, column: 13 },
+                },
+            ]
+        );
+        assert_eq!(
+            file.sections[3].items,
+            vec![
+                Item::Label("buf".to_string(), Location { line: 7, column: 13 }),
+                Item::Space {
+                    size: Expr::Num(16),
+                    fill: None,
+                    location: Location { line: 7, column: 18 },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_error_test1() {
+        let err = parse("addi a0, a0, 1\n\nbogus a0\n").unwrap_err();
+        assert_eq!(err, Diagnostic::at(3, "can't parse `bogus a0`".to_string()));
+    }
+
+    #[test]
+    fn parse_binding_test1() {
+        let file = parse(".globl main, exit\n.local main\nmain:").unwrap();
+        assert_eq!(
+            file.bindings,
+            vec![
+                ("main".to_string(), Binding::Global),
+                ("exit".to_string(), Binding::Global),
+                ("main".to_string(), Binding::Local),
+            ]
+        );
+    }
+
+    #[test]
+    fn conditionals_test() {
+        let source = "
+            .equ DEBUG, 0
+            .if DEBUG
+            this isn't an instruction
+            .elseif UART == 2
+            addi a0, a0, 2
+            .else
+            addi a0, a0, 3
+            .endif
+            .ifndef UART
+            .ifdef DEBUG
+            nested, but skipped
+            .endif
+            .else
+            addi a1, a1, 1
+            .endif
+        ";
+        let options = Options {
+            defines: vec![("UART".to_string(), 2)],
+            ..Options::default()
+        };
+        let file = parse_with(source, &options).unwrap();
+        let imms: Vec<_> = file.sections[0]
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Text(text) => text.instruction.imm,
+                _ => None,
+            })
+            .collect();
+        assert_eq!(imms, vec![Some(2), Some(1)]);
+        assert_eq!(file.consts[0].name, "UART");
+
+        let err = parse(".if 1\nnop").unwrap_err();
+        assert_eq!(err, Diagnostic::at(1, "`.if` without `.endif`".to_string()));
+        let err = parse(".else").unwrap_err();
+        assert_eq!(err, Diagnostic::at(1, "`.else` without `.if`".to_string()));
+        let err = parse(".if 1\n.else\n.elseif 1\n.endif").unwrap_err();
+        assert_eq!(err, Diagnostic::at(3, "`.elseif` after `.else`".to_string()));
+        let err = parse(".if later\nlater: .endif").unwrap_err();
+        assert!(err.message.contains("undefined symbol `later`"));
+    }
+
+    #[test]
+    fn parse_define_test() {
+        assert_eq!(parse_define("UART_BASE=0x1000_0000"), Err("can't parse `0x1000_0000`".to_string()));
+        assert_eq!(parse_define("UART_BASE=0x10000000"), Ok(("UART_BASE".to_string(), 0x10000000)));
+        assert_eq!(parse_define("DEBUG"), Ok(("DEBUG".to_string(), 1)));
+        assert_eq!(parse_define("SIZE=4*4"), Ok(("SIZE".to_string(), 16)));
+        assert!(parse_define("2X=1").is_err());
+    }
+
+    #[test]
+    fn parse_lines_test() {
+        let file = parse("a: b:\n\n  addi a0, a0, 1\nc:\n.data\nd: .word 1\ne:").unwrap();
+        let text = &file.sections[0].items;
+        assert_eq!(text[0], Item::Label("a".to_string(), Location { line: 1, column: 1 }));
+        match &text[1] {
+            Item::Text(text) => assert_eq!(text.label.as_deref(), Some("b")),
+            _ => panic!("expected an instruction"),
+        }
+        // A label before a section switch stays in the section it was in
+        assert_eq!(text[2], Item::Label("c".to_string(), Location { line: 4, column: 1 }));
+        let data = &file.sections[1].items;
+        match &data[0] {
+            Item::Data(data) => assert_eq!(data.label.as_deref(), Some("d")),
+            _ => panic!("expected data"),
+        }
+        assert_eq!(data[1], Item::Label("e".to_string(), Location { line: 7, column: 1 }));
+
+        let err = parse("addi a0, a0, 1\nl: addi a0, a0, 1 addi\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: can't parse `addi a0, a0, 1 addi`");
+        let err = parse(".ascii \"open").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: can't parse `.ascii \"open`");
+    }
+
+    #[test]
+    fn parse_insn_test() {
+        let (_leftover, result) = parse_insn("op: .insn sb custom_1, 3, a0, a1, op").unwrap();
+        assert_eq!(
+            result,
+            Text {
+                instruction: InstructionData {
+                    mne: ".insn".to_string(),
+                    rs1: Some(10),
+                    rs2: Some(11),
+                    rd: None,
+                    imm: None,
+                    custom: Some(Encoding {
+                        format: Format::B,
+                        opcode: 0x2b,
+                        funct3: 3,
+                        funct7: 0,
+                    }),
+                },
+                label: Some("op".to_string()),
+                label_dst: Some("op".to_string()),
+                imm_expr: None,
+                location: Location::default(),
+                pseudo: false,
+            }
+        );
+        // funct7 has 7 bits and funct3 has 3
+        assert!(parse_insn(".insn r 0x0b, 0, 0x80, a0, a1, a2").is_err());
+        assert!(parse_insn(".insn i 0x0b, 8, a0, a1, 1").is_err());
+    }
+
+    #[test]
+    fn local_labels_test() {
+        let file = parse("1: beq a0, zero, 1f\n1: bne a0, a1, 1b\n.word 1b, 1f\n1:").unwrap();
+        let items = &file.sections[0].items;
+        match (&items[0], &items[1]) {
+            (Item::Text(first), Item::Text(second)) => {
+                assert_eq!(first.label.as_deref(), Some(".L1^1"));
+                assert_eq!(first.label_dst.as_deref(), Some(".L1^2"));
+                assert_eq!(second.label.as_deref(), Some(".L1^2"));
+                assert_eq!(second.label_dst.as_deref(), Some(".L1^2"));
+            }
+            _ => panic!("expected two instructions"),
+        }
+        match &items[2] {
+            Item::Data(data) => assert_eq!(
+                data.exprs,
+                vec![Expr::Sym(".L1^2".to_string()), Expr::Sym(".L1^3".to_string())]
             ),
-        ),
-    )(i)
-}
+            _ => panic!("expected .word"),
+        }
+        assert_eq!(items[3], Item::Label(".L1^3".to_string(), Location { line: 4, column: 1 }));
 
-fn parse_string(i: &str) -> IResult<&str, Data, VerboseError<&str>> {
-    let label_p = opt(parse_label);
-    let dir = terminated(
-        preceded(space0, alt((tag_no_case(".string"), tag_no_case(".asciz")))),
-        multispace0,
-    );
-    let string = terminated(
-        preceded(
-            char('\"'),
-            cut(terminated(
-                escaped(alphanumeric1, '\\', one_of("\"n\\")),
-                char('\"'),
-            )),
-        ),
-        multispace0,
-    );
+        let err = parse("beq a0, zero, 1b").unwrap_err();
+        assert_eq!(err, Diagnostic::at(1, "`1b` has no `1:` before it".to_string()));
+        let err = parse("2: jal zero, 2f\n.word 2f").unwrap_err();
+        assert_eq!(err, Diagnostic::at(1, "`2f` has no `2:` after it".to_string()));
+        // Found by fuzzing: a label ending in a multibyte character
+        assert!(parse("1: beq a0, zero, ϙ\n.word 1b").is_ok());
+    }
 
-   map(
-       tuple((label_p, dir, string)),
-       |(label, _, string)| Data {
-           label: label.map(|s| String::from(s)),
-           size: DataSize::Byte,
-           data: string.to_string().into_bytes()
-       },
-   )(i)
-}
+    #[test]
+    fn pseudo_test() {
+        let source = "start: la a0, msg\n1: call 1b\n";
+        let mnemonics = |file: &FullFile| -> Vec<(Option<String>, String, Option<Expr>)> {
+            file.sections[0]
+                .items
+                .iter()
+                .map(|item| match item {
+                    Item::Text(text) => (text.label.clone(), text.instruction.mne.clone(), text.imm_expr.clone()),
+                    Item::Label(label, _) => (Some(label.clone()), String::new(), None),
+                    _ => panic!("expected instructions and labels"),
+                })
+                .collect()
+        };
+        let reloc = |reloc, name: &str| Some(Expr::Reloc(reloc, Box::new(Expr::Sym(name.to_string()))));
+        let label = |name: &str| Some(name.to_string());
 
-fn parse_datasize(i: &str) -> IResult<&str, DataSize, VerboseError<&str>> {
-    alt((
-        map(tag(".word"), |_| DataSize::Word),
-        map(tag(".half"), |_| DataSize::Half),
-        map(tag(".byte"), |_| DataSize::Byte),
-    ))(i)
-}
+        let file = parse(source).unwrap();
+        assert_eq!(
+            mnemonics(&file),
+            vec![
+                (label("start"), "lui".to_string(), reloc(Reloc::Hi, "msg")),
+                (None, "addi".to_string(), reloc(Reloc::Lo, "msg")),
+                (label(".L1^1"), "lui".to_string(), reloc(Reloc::Hi, ".L1^1")),
+                (None, "jalr".to_string(), reloc(Reloc::Lo, ".L1^1")),
+            ]
+        );
+        match &file.sections[0].items[3] {
+            Item::Text(text) => {
+                assert_eq!((text.instruction.rd, text.instruction.rs1), (Some(1), Some(1)));
+                assert_eq!(text.location.line, 2);
+                assert!(text.pseudo);
+            }
+            _ => unreachable!(),
+        }
 
-fn parse_datalist(i: &str) -> IResult<&str, Vec<u8>, VerboseError<&str>> {
-    map(
-    terminated(preceded(multispace0, separated_list1(preceded(multispace0, terminated(char(','), multispace0)), parse_imm)), multispace0)
-    |(pre, num)| {
+        let options = Options {
+            pic: true,
+            ..Options::default()
+        };
+        let file = parse_with(source, &options).unwrap();
+        assert_eq!(
+            mnemonics(&file),
+            vec![
+                (label("start"), String::new(), None),
+                (label(".Lpcrel^1"), "auipc".to_string(), reloc(Reloc::PcrelHi, "msg")),
+                (None, "addi".to_string(), reloc(Reloc::PcrelLo, ".Lpcrel^1")),
+                (label(".L1^1"), String::new(), None),
+                (label(".Lpcrel^2"), "auipc".to_string(), reloc(Reloc::PcrelHi, ".L1^1")),
+                (None, "jalr".to_string(), reloc(Reloc::PcrelLo, ".Lpcrel^2")),
+            ]
+        );
+        assert!(parse("la msg").is_err());
+    }
 
+    #[test]
+    fn symbol_access_test() {
+        let file = parse("lw a0, msg\nSB a1, msg + 1, t0\nlw a2, 4(a3)\nlhu a4, msg(a5)").unwrap();
+        let instructions: Vec<_> = file.sections[0]
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Text(text) => {
+                    let InstructionData { mne, rd, rs1, rs2, .. } = &text.instruction;
+                    (mne.as_str(), *rd, *rs1, *rs2, text.pseudo)
+                }
+                _ => panic!("expected instructions"),
+            })
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![
+                ("lui", Some(10), None, None, true),
+                ("lw", Some(10), Some(10), None, true),
+                ("lui", Some(5), None, None, true),
+                ("sb", None, Some(5), Some(11), true),
+                ("lw", Some(12), Some(13), None, false),
+                ("lhu", Some(14), Some(15), None, false),
+            ]
+        );
+        assert!(parse("sw a1, msg").is_err());
+        assert!(parse("lw a0, msg, t0").is_err());
     }
-    )
-}
 
-fn parse_dataline (i: &str) -> IResult<&str, Data, VerboseError<&str>> {
+    #[test]
+    fn parse_string_test1() {
+        let (_leftover, result) =
+            parse_string("msg: .string \"Hello, world!\\n\", \"\\x41\\101\\t\\\"\"").unwrap();
+        assert_eq!(result.label, Some("msg".to_string()));
+        assert_eq!(result.data, b"Hello, world!\n\0AA\t\"\0".to_vec());
+    }
 
-}
-//fn parse_data(i: &str) -> IResult<&str, Vec<Text>, VerboseError<&str>> {
-//    preceded(multispace0, preceded(tag(".data"), ))
+    #[test]
+    fn parse_string_test2() {
+        let (_leftover, result) = parse_string(".ascii \"a # b\"").unwrap();
+        assert_eq!(result.data, b"a # b".to_vec());
+        let (_leftover, result) = parse_string(".ascii \"é\\\\x\\n\"").unwrap();
+        assert_eq!(result.data, "é\\x\n".as_bytes().to_vec());
+        assert!(parse_string(".ascii \"a\\q\"").is_err());
+        assert!(parse_string(".ascii \"open").is_err());
+        let file = parse(".asciz \"\"\n.ascii \"x\"").unwrap();
+        assert_eq!(
+            file.sections[0].items,
+            vec![
+                Item::Data(Data {
+                    label: None,
+                    data: vec![0],
+                    size: DataSize::Byte,
+                    exprs: vec![],
+                    location: Location { line: 1, column: 1 },
+                }),
+                Item::Data(Data {
+                    label: None,
+                    data: b"x".to_vec(),
+                    size: DataSize::Byte,
+                    exprs: vec![],
+                    location: Location { line: 2, column: 1 },
+                }),
+            ]
+        );
+        assert!(parse(".string \"unterminated").is_err());
+    }
+
+    #[test]
+    fn num_to_bytes_test() {
+        assert_eq!(num_to_bytes(0x1234, 2), Ok(vec![0x12, 0x34]));
+        assert_eq!(num_to_bytes(0xAB, 1), Ok(vec![0xAB]));
+        assert_eq!(num_to_bytes(-1, 1), Ok(vec![0xFF]));
+        assert_eq!(num_to_bytes(-2, 8), Ok(vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFE]));
+        assert!(num_to_bytes(256, 1).is_err());
+        assert!(num_to_bytes(-32769, 2).is_err());
+    }
 
-//fn parse(i: &str) -> IResult<&str, Vec<Text>, VerboseError<&str>> {
-//    many0(alt((parse_text, parse_data)))(i)
-//}
+    #[test]
+    fn parse_dataline_test1() {
+        let (_leftover, result) = parse_dataline("x: .8byte -1, 2").unwrap();
+        assert_eq!(result.size, DataSize::Dword);
+        assert_eq!(result.exprs[0], Expr::Neg(Box::new(Expr::Num(1))));
+        let (_leftover, result) = parse_dataline(".2byte 1").unwrap();
+        assert_eq!(result.size, DataSize::Half);
+        assert!(parse_dataline(".words 1").is_err());
+    }
+
+    #[test]
+    fn parse_floatline_test1() {
+        let (_leftover, result) = parse_floatline(".float 1.5, -2").unwrap();
+        assert_eq!(result.data, [1.5f32.to_be_bytes(), (-2f32).to_be_bytes()].concat());
+        let (_leftover, result) = parse_floatline("e: .double 2.5e-3").unwrap();
+        assert_eq!(result.label, Some("e".to_string()));
+        assert_eq!(result.data, 2.5e-3f64.to_be_bytes().to_vec());
+    }
+}
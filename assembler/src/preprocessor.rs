@@ -0,0 +1,797 @@
+//! The C preprocessor that `.S` files are written for, built in so that no
+//! `cpp` is needed: `#define` and `#undef` of object-like and function-like
+//! macros, `#include`, and `#if`, `#ifdef`, `#ifndef`, `#elif`, `#else` and
+//! `#endif`. C comments are removed. Other lines starting with `#` are left
+//! alone, since they're assembler comments.
+//!
+//! The output keeps one line for each line it came from, leaving directives
+//! and skipped lines blank, so errors can be traced back to the file and
+//! line they came from.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::character::complete::{char, space0};
+use nom::combinator::{all_consuming, map, map_res};
+use nom::error::Error;
+use nom::multi::fold_many0;
+use nom::sequence::{delimited, pair, preceded, terminated};
+use nom::IResult;
+
+use crate::expr::parse_expr;
+use crate::parser::{comment_start, FullFile};
+use crate::Diagnostic;
+
+/// How deep `#include`s can nest, to stop a file that includes itself.
+const MAX_INCLUDE_DEPTH: usize = 64;
+
+/// Defined in every file, as `cpp` does for assembly.
+const PREDEFINED: [(&str, &str); 3] = [("__ASSEMBLER__", "1"), ("__riscv", "1"), ("__riscv_xlen", "32")];
+
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Macros to define first, like `-D`.
+    pub defines: Vec<(String, i64)>,
+    /// Where to look for `#include <file>`, and for `#include "file"` after
+    /// the directory of the file including it.
+    pub include_dirs: Vec<PathBuf>,
+}
+
+/// Where a line of preprocessed source came from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Origin {
+    pub file: String,
+    pub line: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Preprocessed {
+    /// The name of the file that was preprocessed.
+    pub file: String,
+    pub source: String,
+    /// Where each line of `source` came from.
+    pub origins: Vec<Origin>,
+}
+
+impl Preprocessed {
+    /// Where line `line` of `source`, counting from 1, came from.
+    pub fn origin(&self, line: usize) -> Option<&Origin> {
+        self.origins.get(line.checked_sub(1)?)
+    }
+
+    /// `diagnostic`, about a line of `source`, with the file and line it came
+    /// from in front, like `inc.h: line 3: can't parse ...`.
+    pub fn locate(&self, diagnostic: &Diagnostic) -> String {
+        match diagnostic.line.and_then(|line| self.origin(line)) {
+            Some(origin) => format!("{}: line {}: {}", origin.file, origin.line, diagnostic.message),
+            None => format!("{}: {}", self.file, diagnostic.message),
+        }
+    }
+
+    /// Gives `file`, parsed from `source`, the file and line each of its
+    /// lines came from, for tracing its code back to them.
+    pub fn add_origins(&self, file: &mut FullFile) {
+        file.origins = self.origins.clone();
+    }
+
+    /// `source` with a `# LINE "FILE"` line marker, as `cpp -E` writes them,
+    /// at the start and wherever it goes in or out of an included file. They
+    /// are comments to the assembler, so the output still assembles.
+    pub fn with_line_markers(&self) -> String {
+        let mut out = String::new();
+        let mut previous: Option<&Origin> = None;
+        for (line, origin) in self.source.lines().zip(&self.origins) {
+            let follows = previous.is_some_and(|p| p.file == origin.file && p.line + 1 == origin.line);
+            if !follows {
+                out.push_str(&format!("# {} \"{}\"\n", origin.line, origin.file));
+            }
+            out.push_str(line);
+            out.push('\n');
+            previous = Some(origin);
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Macro {
+    Object(String),
+    Function(Function),
+}
+
+#[derive(Debug, Clone)]
+struct Function {
+    params: Vec<String>,
+    /// Ends in `...`, with the rest of the arguments in `__VA_ARGS__`.
+    variadic: bool,
+    body: String,
+}
+
+/// An `#if` and the `#elif`s and `#else` that go with it.
+struct Conditional {
+    line: usize,
+    /// Whether the lines are kept, which they never are if the lines
+    /// around the `#if` aren't.
+    active: bool,
+    enclosing: bool,
+    /// Whether a branch has been kept already, so the rest aren't.
+    taken: bool,
+    seen_else: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Identifier,
+    Number,
+    Literal,
+    Space,
+    Punct,
+}
+
+/// Splits `text` into identifiers, numbers, quoted literals, runs of spaces
+/// and single punctuation characters, but with `##` as one.
+fn tokens(text: &str) -> Vec<(Kind, &str)> {
+    let mut tokens = vec![];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let end = |pred: fn(char) -> bool| rest.find(|c: char| !pred(c)).unwrap_or(rest.len());
+        let (kind, len) = match c {
+            '"' | '\'' => (Kind::Literal, literal_len(rest)),
+            '0'..='9' => (Kind::Number, end(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')),
+            c if c.is_ascii_alphabetic() || c == '_' => (Kind::Identifier, end(|c| c.is_ascii_alphanumeric() || c == '_')),
+            c if c.is_whitespace() => (Kind::Space, end(char::is_whitespace)),
+            '#' if rest.starts_with("##") => (Kind::Punct, 2),
+            c => (Kind::Punct, c.len_utf8()),
+        };
+        tokens.push((kind, &rest[..len]));
+        rest = &rest[len..];
+    }
+    tokens
+}
+
+/// The length of the quoted literal `text` starts with, or all of it if the
+/// quote isn't closed.
+fn literal_len(text: &str) -> usize {
+    let quote = text.chars().next().unwrap();
+    let mut escape = false;
+    for (idx, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escape => escape = false,
+            '\\' => escape = true,
+            c if c == quote => return idx + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Replaces C comments with spaces, keeping the line breaks of `/* */` ones.
+/// Fails with the line of a comment that isn't closed.
+fn strip_c_comments(source: &str) -> Result<String, usize> {
+    let mut out = String::new();
+    let mut line = 1;
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("/*") {
+            let end = rest[2..].find("*/").ok_or(line)? + 4;
+            let breaks = rest[..end].matches('\n').count();
+            out.push(' ');
+            out.push_str(&"\n".repeat(breaks));
+            line += breaks;
+            rest = &rest[end..];
+        } else if rest.starts_with("//") {
+            rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+        } else {
+            // A quote that isn't closed, like the one in `# don't`, ends
+            // with the line
+            let len = match c {
+                '"' | '\'' => literal_len(&rest[..rest.find('\n').unwrap_or(rest.len())]),
+                c => c.len_utf8(),
+            };
+            line += rest[..len].matches('\n').count();
+            out.push_str(&rest[..len]);
+            rest = &rest[len..];
+        }
+    }
+    Ok(out)
+}
+
+/// The lines of `source`, with those ending in `\` joined to the next and
+/// blank lines left in their place.
+fn join_continuations(source: &str) -> Vec<String> {
+    let mut lines = vec![];
+    let mut joined = String::new();
+    let mut continued = 0;
+    for line in source.lines() {
+        match line.strip_suffix('\\') {
+            Some(line) => {
+                joined.push_str(line);
+                continued += 1;
+            }
+            None => {
+                joined.push_str(line);
+                lines.push(std::mem::take(&mut joined));
+                lines.extend(std::iter::repeat_n(String::new(), continued));
+                continued = 0;
+            }
+        }
+    }
+    if continued > 0 {
+        lines.push(joined);
+        lines.extend(std::iter::repeat_n(String::new(), continued - 1));
+    }
+    lines
+}
+
+/// The name and the rest of a directive line, like `("define", " N 4")` for
+/// `#define N 4`.
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let rest = line.trim_start().strip_prefix('#')?.trim_start();
+    let end = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+    let name = &rest[..end];
+    let known = [
+        "define", "undef", "include", "if", "ifdef", "ifndef", "elif", "else", "endif", "error", "pragma", "line",
+    ];
+    known.contains(&name).then(|| (name, &rest[end..]))
+}
+
+fn is_identifier(name: &str) -> bool {
+    matches!(tokens(name)[..], [(Kind::Identifier, _)])
+}
+
+/// The index of the first token from `from` on that isn't spaces.
+fn non_space(tokens: &[(Kind, &str)], from: usize) -> Option<usize> {
+    (from..tokens.len()).find(|&i| tokens[i].0 != Kind::Space)
+}
+
+/// The arguments of a macro call whose `(` is at `open`, and where its `)`
+/// is, or `None` if it isn't closed.
+fn arguments(tokens: &[(Kind, &str)], open: usize) -> Option<(Vec<String>, usize)> {
+    let mut args = vec![String::new()];
+    let mut depth = 0;
+    for (i, &(_, token)) in tokens.iter().enumerate().skip(open) {
+        match token {
+            "(" => depth += 1,
+            ")" => depth -= 1,
+            _ => {}
+        }
+        match token {
+            _ if depth == 0 => return Some((args.iter().map(|arg| arg.trim().to_string()).collect(), i)),
+            "(" if depth == 1 => {}
+            "," if depth == 1 => args.push(String::new()),
+            token => args.last_mut().unwrap().push_str(token),
+        }
+    }
+    None
+}
+
+/// `text` as a string literal, for `#param`.
+fn stringify(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The value of an `#if` once its macros are expanded: `defined` already
+/// replaced, identifiers that are left 0 and `U` and `L` suffixes dropped,
+/// as in C.
+fn evaluate(expanded: &str) -> Option<bool> {
+    let text: String = tokens(expanded)
+        .into_iter()
+        .map(|(kind, token)| match kind {
+            Kind::Identifier => "0",
+            Kind::Number => token.trim_end_matches(['u', 'U', 'l', 'L']),
+            _ => token,
+        })
+        .collect();
+    let (_, value) = all_consuming(terminated(logical_or, space0))(&text).ok()?;
+    Some(value != 0)
+}
+
+/// `||` over `logical_and`. The arithmetic, bitwise operators and
+/// comparisons underneath are the assembler's own.
+fn logical_or(i: &str) -> IResult<&str, i64, Error<&str>> {
+    let (i, first) = logical_and(i)?;
+    fold_many0(
+        preceded(pair(space0, tag("||")), logical_and),
+        move || first,
+        |a, b| (a != 0 || b != 0) as i64,
+    )(i)
+}
+
+fn logical_and(i: &str) -> IResult<&str, i64, Error<&str>> {
+    let (i, first) = logical_not(i)?;
+    fold_many0(
+        preceded(pair(space0, tag("&&")), logical_not),
+        move || first,
+        |a, b| (a != 0 && b != 0) as i64,
+    )(i)
+}
+
+fn logical_not(i: &str) -> IResult<&str, i64, Error<&str>> {
+    preceded(
+        space0,
+        alt((
+            map(preceded(char('!'), logical_not), |value| (value == 0) as i64),
+            map_res(parse_expr, |expr| expr.eval(&|_: &str| Ok(0))),
+            delimited(char('('), logical_or, preceded(space0, char(')'))),
+        )),
+    )(i)
+}
+
+struct Preprocessor<'a> {
+    macros: HashMap<String, Macro>,
+    include_dirs: &'a [PathBuf],
+    read: &'a dyn Fn(&Path) -> Result<String, String>,
+    lines: Vec<String>,
+    origins: Vec<Origin>,
+}
+
+impl Preprocessor<'_> {
+    /// Adds the lines of `source`, read from `path`, to the output.
+    fn file(&mut self, source: &str, path: &Path, depth: usize) -> Result<(), String> {
+        let name = path.display().to_string();
+        let at = |line: usize, message: String| format!("{}: line {}: {}", name, line, message);
+        let source = strip_c_comments(source).map_err(|line| at(line, "unterminated comment".to_string()))?;
+
+        let mut conditionals: Vec<Conditional> = vec![];
+        for (n, line) in join_continuations(&source).iter().enumerate() {
+            let active = conditionals.last().is_none_or(|c| c.active);
+            let origin = Origin {
+                file: name.clone(),
+                line: n + 1,
+            };
+            let (directive, rest) = match directive(line) {
+                Some(("include", rest)) if active => {
+                    let (source, path) = self.find_include(rest, path, depth).map_err(|e| at(n + 1, e))?;
+                    self.file(&source, &path, depth + 1)?;
+                    continue;
+                }
+                Some(directive) => directive,
+                None => {
+                    let line = match active {
+                        true => self.expand_line(line).map_err(|e| at(n + 1, e))?,
+                        false => String::new(),
+                    };
+                    self.lines.push(line);
+                    self.origins.push(origin);
+                    continue;
+                }
+            };
+            self.directive(directive, rest, n + 1, active, &mut conditionals).map_err(|e| at(n + 1, e))?;
+            self.lines.push(String::new());
+            self.origins.push(origin);
+        }
+        match conditionals.last() {
+            Some(conditional) => Err(at(conditional.line, "`#if` without `#endif`".to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn directive(
+        &mut self,
+        directive: &str,
+        rest: &str,
+        line: usize,
+        active: bool,
+        conditionals: &mut Vec<Conditional>,
+    ) -> Result<(), String> {
+        let without_if = || format!("`#{}` without `#if`", directive);
+        match directive {
+            "if" | "ifdef" | "ifndef" => {
+                let value = active
+                    && match directive {
+                        "if" => self.condition(rest)?,
+                        _ => self.is_defined(rest)? == (directive == "ifdef"),
+                    };
+                conditionals.push(Conditional {
+                    line,
+                    active: value,
+                    enclosing: active,
+                    taken: value,
+                    seen_else: false,
+                });
+            }
+            "elif" => {
+                let conditional = conditionals.last().ok_or_else(without_if)?;
+                if conditional.seen_else {
+                    return Err("`#elif` after `#else`".to_string());
+                }
+                let value = conditional.enclosing && !conditional.taken && self.condition(rest)?;
+                let conditional = conditionals.last_mut().unwrap();
+                conditional.active = value;
+                conditional.taken |= value;
+            }
+            "else" => {
+                let conditional = conditionals.last_mut().ok_or_else(without_if)?;
+                if conditional.seen_else {
+                    return Err("`#else` after `#else`".to_string());
+                }
+                conditional.active = conditional.enclosing && !conditional.taken;
+                conditional.taken = true;
+                conditional.seen_else = true;
+            }
+            "endif" => {
+                conditionals.pop().ok_or_else(without_if)?;
+            }
+            _ if !active => {}
+            "define" => self.define(rest)?,
+            "undef" => {
+                let name = rest.trim();
+                if !is_identifier(name) {
+                    return Err(format!("`{}` isn't a macro name", name));
+                }
+                self.macros.remove(name);
+            }
+            "error" => return Err(format!("#error {}", rest.trim())),
+            // `#pragma` and `#line` mean nothing to the assembler
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The contents and path of the file an `#include` names.
+    fn find_include(&self, rest: &str, from: &Path, depth: usize) -> Result<(String, PathBuf), String> {
+        let rest = rest.trim();
+        let (name, quoted) = match (rest.strip_prefix('"'), rest.strip_prefix('<')) {
+            (Some(name), _) => (name.strip_suffix('"'), true),
+            (_, Some(name)) => (name.strip_suffix('>'), false),
+            _ => (None, false),
+        };
+        let name = name.ok_or("`#include` expects \"FILE\" or <FILE>")?;
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(format!("`#include` nested more than {} deep", MAX_INCLUDE_DEPTH));
+        }
+        let here = from.parent().filter(|_| quoted).map(|dir| dir.join(name));
+        let paths = here.into_iter().chain(self.include_dirs.iter().map(|dir| dir.join(name)));
+        paths
+            .filter_map(|path| Some(((self.read)(&path).ok()?, path)))
+            .next()
+            .ok_or_else(|| format!("can't find `{}` to include", name))
+    }
+
+    fn define(&mut self, rest: &str) -> Result<(), String> {
+        let rest = rest.trim();
+        let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        let (name, after) = rest.split_at(end);
+        if !is_identifier(name) {
+            return Err(format!("`{}` isn't a macro name", name));
+        }
+        // Only a `(` right after the name makes it function-like
+        let value = match after.strip_prefix('(') {
+            Some(after) => {
+                let (params, body) = after
+                    .split_once(')')
+                    .ok_or_else(|| format!("missing `)` after the parameters of `{}`", name))?;
+                let mut params: Vec<String> = params.split(',').map(|param| param.trim().to_string()).collect();
+                if params == [""] {
+                    params.clear();
+                }
+                let variadic = params.last().is_some_and(|param| param == "...");
+                if variadic {
+                    params.pop();
+                }
+                if let Some(param) = params.iter().find(|param| !is_identifier(param)) {
+                    return Err(format!("`{}` isn't a parameter name", param));
+                }
+                Macro::Function(Function {
+                    params,
+                    variadic,
+                    body: body.trim().to_string(),
+                })
+            }
+            None => Macro::Object(after.trim().to_string()),
+        };
+        self.macros.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    fn is_defined(&self, rest: &str) -> Result<bool, String> {
+        let name = rest.trim();
+        match is_identifier(name) {
+            true => Ok(self.macros.contains_key(name)),
+            false => Err(format!("`{}` isn't a macro name", name)),
+        }
+    }
+
+    fn condition(&self, rest: &str) -> Result<bool, String> {
+        let tokens = tokens(rest);
+        let mut replaced = String::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            let (kind, token) = tokens[i];
+            i += 1;
+            if (kind, token) != (Kind::Identifier, "defined") {
+                replaced.push_str(token);
+                continue;
+            }
+            let next = non_space(&tokens, i);
+            let (name, end) = match next.map(|j| tokens[j]) {
+                Some((Kind::Identifier, name)) => (Some(name), next),
+                Some((_, "(")) => {
+                    let name = non_space(&tokens, next.unwrap() + 1);
+                    let close = name.and_then(|j| non_space(&tokens, j + 1));
+                    match (name.map(|j| tokens[j]), close.map(|j| tokens[j].1)) {
+                        (Some((Kind::Identifier, name)), Some(")")) => (Some(name), close),
+                        _ => (None, None),
+                    }
+                }
+                _ => (None, None),
+            };
+            let name = name.ok_or("`defined` needs a macro name")?;
+            replaced.push_str(if self.macros.contains_key(name) { " 1 " } else { " 0 " });
+            i = end.unwrap() + 1;
+        }
+        let expanded = self.expand(&replaced, &[])?;
+        evaluate(&expanded).ok_or_else(|| format!("can't evaluate `#if {}`", rest.trim()))
+    }
+
+    /// Expands the macros of an assembly line, up to any `#` comment.
+    fn expand_line(&self, line: &str) -> Result<String, String> {
+        let (code, comment) = line.split_at(comment_start(line).unwrap_or(line.len()));
+        Ok(self.expand(code, &[])? + comment)
+    }
+
+    /// Expands the macros in `text`, except for those in `disabled`, which
+    /// are being expanded already.
+    fn expand(&self, text: &str, disabled: &[&str]) -> Result<String, String> {
+        let tokens = tokens(text);
+        let mut out = String::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            let (kind, token) = tokens[i];
+            i += 1;
+            let found = match kind {
+                Kind::Identifier if !disabled.contains(&token) => self.macros.get(token),
+                _ => None,
+            };
+            let inner = [disabled, &[token]].concat();
+            match found {
+                Some(Macro::Object(body)) => out.push_str(&self.expand(body, &inner)?),
+                Some(Macro::Function(function)) => {
+                    let open = non_space(&tokens, i).filter(|&j| tokens[j].1 == "(");
+                    let open = match open {
+                        Some(open) => open,
+                        // Just the name, like C
+                        None => {
+                            out.push_str(token);
+                            continue;
+                        }
+                    };
+                    let (args, close) = arguments(&tokens, open)
+                        .ok_or_else(|| format!("unterminated arguments to `{}`", token))?;
+                    let body = self.substitute(token, function, args, disabled)?;
+                    out.push_str(&self.expand(&body, &inner)?);
+                    i = close + 1;
+                }
+                None => out.push_str(token),
+            }
+        }
+        Ok(out)
+    }
+
+    /// The body of `function` with its parameters replaced by `args`, which
+    /// are expanded first unless they're next to `#` or `##`.
+    fn substitute(&self, name: &str, function: &Function, mut args: Vec<String>, disabled: &[&str]) -> Result<String, String> {
+        if function.params.is_empty() && args == [""] {
+            args.clear();
+        }
+        let count = function.params.len();
+        if args.len() < count || args.len() > count && !function.variadic {
+            return Err(format!("`{}` takes {} argument(s), not {}", name, count, args.len()));
+        }
+        let mut values: Vec<(&str, String)> = function.params.iter().map(String::as_str).zip(args.iter().cloned()).collect();
+        if function.variadic {
+            values.push(("__VA_ARGS__", args[count..].join(", ")));
+        }
+        let value = |token: &str| values.iter().find(|(param, _)| *param == token).map(|(_, value)| value);
+
+        let tokens = tokens(&function.body);
+        let mut out = String::new();
+        let mut pasting = false;
+        let mut i = 0;
+        while i < tokens.len() {
+            let (kind, token) = tokens[i];
+            let next = non_space(&tokens, i + 1);
+            if token == "##" {
+                out.truncate(out.trim_end().len());
+                pasting = true;
+                i = next.unwrap_or(tokens.len());
+                continue;
+            }
+            let stringified = next.filter(|_| token == "#").and_then(|j| value(tokens[j].1));
+            if let Some(stringified) = stringified {
+                out.push_str(&stringify(stringified));
+                pasting = false;
+                i = next.unwrap() + 1;
+                continue;
+            }
+            let pasted = pasting || next.is_some_and(|j| tokens[j].1 == "##");
+            match value(token).filter(|_| kind == Kind::Identifier) {
+                Some(value) if pasted => out.push_str(value),
+                Some(value) => out.push_str(&self.expand(value, disabled)?),
+                None => out.push_str(token),
+            }
+            pasting = false;
+            i += 1;
+        }
+        Ok(out)
+    }
+}
+
+/// Preprocesses `source`, which was read from `name`, reading the files it
+/// includes with `read`.
+pub fn preprocess_with(
+    source: &str,
+    name: &str,
+    options: &Options,
+    read: &dyn Fn(&Path) -> Result<String, String>,
+) -> Result<Preprocessed, String> {
+    let predefined = PREDEFINED.iter().map(|&(name, value)| (name.to_string(), value.to_string()));
+    let defines = options.defines.iter().map(|(name, value)| (name.clone(), value.to_string()));
+    let mut preprocessor = Preprocessor {
+        macros: predefined.chain(defines).map(|(name, value)| (name, Macro::Object(value))).collect(),
+        include_dirs: &options.include_dirs,
+        read,
+        lines: vec![],
+        origins: vec![],
+    };
+    preprocessor.file(source, Path::new(name), 0)?;
+    let mut source = preprocessor.lines.join("\n");
+    source.push('\n');
+    Ok(Preprocessed {
+        file: name.to_string(),
+        source,
+        origins: preprocessor.origins,
+    })
+}
+
+/// Preprocesses `source`, which was read from `name`, reading the files it
+/// includes from disk.
+pub fn preprocess(source: &str, name: &str, options: &Options) -> Result<Preprocessed, String> {
+    preprocess_with(source, name, options, &|path| fs::read_to_string(path).map_err(|e| e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse, Item};
+
+    fn run(source: &str) -> Result<String, String> {
+        preprocess(source, "main.S", &Options::default()).map(|preprocessed| preprocessed.source)
+    }
+
+    #[test]
+    fn macro_test() {
+        let source = "\
+#define N 4
+#define ADD(rd, a, b) add rd, a, b
+#define CAT(a, b) a##b
+#define STR(x) #x
+#define ARGS(first, ...) first: .word __VA_ARGS__
+    addi a0, zero, N # N stays in comments
+    ADD(a0, a1, CAT(a, 2))
+    .ascii STR(N + 1)
+ARGS(table, 1, N)
+    .ascii \"N\"
+#undef N
+    li a0, N
+";
+        assert_eq!(
+            run(source).unwrap(),
+            "\n\n\n\n\n    addi a0, zero, 4 # N stays in comments\n    add a0, a1, a2\n    .ascii \"N + 1\"\ntable: .word 1, 4\n    .ascii \"N\"\n\n    li a0, N\n"
+        );
+        assert_eq!(run("#define A B\n#define B A\nA").unwrap(), "\n\nA\n");
+        assert_eq!(run("#define F(x) x\nF(1, 2)").unwrap_err(), "main.S: line 2: `F` takes 1 argument(s), not 2");
+        assert_eq!(run("#define F(x) x\nF(1").unwrap_err(), "main.S: line 2: unterminated arguments to `F`");
+    }
+
+    #[test]
+    fn conditional_test() {
+        let source = "\
+#define XLEN 32
+#if defined(XLEN) && XLEN == 32 && !defined MISSING
+one
+#elif 1
+two
+#else
+three
+#endif
+#ifndef XLEN
+four
+#elif (XLEN + 0x20UL) / 2 == 32 || UNKNOWN
+five
+#endif
+#ifdef __ASSEMBLER__
+six
+#endif
+#if 0
+#error not reached
+#if 1
+seven
+#endif
+#else
+eight
+#endif
+";
+        let lines: Vec<_> = run(source).unwrap().lines().filter(|line| !line.is_empty()).map(str::to_string).collect();
+        assert_eq!(lines, vec!["one", "five", "six", "eight"]);
+
+        assert_eq!(run("#if 1\n").unwrap_err(), "main.S: line 1: `#if` without `#endif`");
+        assert_eq!(run("#endif\n").unwrap_err(), "main.S: line 1: `#endif` without `#if`");
+        assert_eq!(run("#if 1\n#else\n#elif 1\n#endif").unwrap_err(), "main.S: line 3: `#elif` after `#else`");
+        assert_eq!(run("\n#error no\n").unwrap_err(), "main.S: line 2: #error no");
+        assert_eq!(run("#if 1 +\n#endif").unwrap_err(), "main.S: line 1: can't evaluate `#if 1 +`");
+    }
+
+    #[test]
+    fn comment_test() {
+        // `#` lines that aren't directives are assembler comments, and C
+        // comments keep their line breaks
+        let source = "\
+# a comment, don't touch
+addi a0, a0, 1 /* one */ // add
+/* two
+   lines */ nop
+#define LONG addi a0, a0, \\
+             2
+LONG
+";
+        assert_eq!(
+            run(source).unwrap(),
+            "# a comment, don't touch\naddi a0, a0, 1   \n \n nop\n\n\naddi a0, a0,              2\n"
+        );
+        assert_eq!(run("nop\n/* open").unwrap_err(), "main.S: line 2: unterminated comment");
+    }
+
+    #[test]
+    fn include_test() {
+        let files = HashMap::from([
+            ("dir/regs.h", "#pragma once\n#define RET jalr zero, 0(ra)\n#include <common.h>\n"),
+            ("inc/common.h", "#define N 3\nbogus\n"),
+            ("dir/self.h", "#include \"self.h\"\n"),
+        ]);
+        let read = |path: &Path| files.get(path.to_str().unwrap()).map(|s| s.to_string()).ok_or_else(|| "missing".to_string());
+        let options = Options {
+            defines: vec![("M".to_string(), 7)],
+            include_dirs: vec![PathBuf::from("inc")],
+        };
+        let source = "addi a0, zero, M\n#include \"regs.h\"\naddi a0, zero, N\nRET\n";
+        let preprocessed = preprocess_with(source, "dir/main.S", &options, &read).unwrap();
+        assert_eq!(preprocessed.source, "addi a0, zero, 7\n\n\n\nbogus\naddi a0, zero, 3\njalr zero, 0(ra)\n");
+        let origin = |file: &str, line| Origin {
+            file: file.to_string(),
+            line,
+        };
+        assert_eq!(preprocessed.origin(5), Some(&origin("inc/common.h", 2)));
+        assert_eq!(preprocessed.origin(6), Some(&origin("dir/main.S", 3)));
+        assert_eq!(
+            preprocessed.with_line_markers(),
+            "# 1 \"dir/main.S\"\naddi a0, zero, 7\n# 1 \"dir/regs.h\"\n\n\n# 1 \"inc/common.h\"\n\nbogus\n# 3 \"dir/main.S\"\naddi a0, zero, 3\njalr zero, 0(ra)\n"
+        );
+
+        let err = crate::parse(&preprocessed.source, &crate::Options::default()).unwrap_err();
+        assert_eq!(preprocessed.locate(&err.0[0]), "inc/common.h: line 2: can't parse `bogus`");
+
+        let mut file = parse("addi a0, zero, 7\n\n\n\naddi zero, zero, 0\naddi a0, zero, 3\n").unwrap();
+        preprocessed.add_origins(&mut file);
+        let lines: Vec<_> = file.sections[0]
+            .items
+            .iter()
+            .map(|item| match item {
+                Item::Text(text) => file.origin(text.location.line),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            lines,
+            vec![(Some("dir/main.S"), 1), (Some("inc/common.h"), 2), (Some("dir/main.S"), 3)]
+        );
+
+        let err = |source| preprocess_with(source, "dir/main.S", &options, &read).unwrap_err();
+        assert_eq!(err("#include <regs.h>"), "dir/main.S: line 1: can't find `regs.h` to include");
+        assert_eq!(err("#include regs.h"), "dir/main.S: line 1: `#include` expects \"FILE\" or <FILE>");
+        assert!(err("#include \"self.h\"").starts_with("dir/self.h: line 1: `#include` nested more than 64 deep"));
+    }
+}
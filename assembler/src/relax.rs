@@ -0,0 +1,151 @@
+use std::fmt;
+
+use crate::expr::{hi, lo};
+use crate::instructions::types::Imm;
+use crate::instructions::InstructionData;
+
+/// Register `jal x0` uses for the address when it becomes `auipc`+`jalr`,
+/// the same one the `tail` pseudo-instruction uses.
+const SCRATCH: u32 = 6;
+
+/// A branch or jump that was rewritten because its target was out of reach.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Relaxation {
+    pub section: String,
+    pub offset: u32,
+    pub mne: String,
+    /// From the instruction to its target.
+    pub distance: i64,
+    /// Bytes it takes now.
+    pub size: u32,
+}
+
+impl fmt::Display for Relaxation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}+{:#x} `{}` to a target {} bytes away",
+            self.section, self.offset, self.mne, self.distance
+        )
+    }
+}
+
+pub fn is_branch(mne: &str) -> bool {
+    matches!(
+        mne.to_lowercase().as_ref(),
+        "beq" | "bne" | "blt" | "bge" | "bltu" | "bgeu"
+    )
+}
+
+/// Whether a branch (13 bit) or `jal` (21 bit) offset can be encoded.
+pub fn reaches(branch: bool, distance: i64) -> bool {
+    let bits = if branch { 13 } else { 21 };
+    distance % 2 == 0 && (-(1 << (bits - 1))..1 << (bits - 1)).contains(&distance)
+}
+
+/// Bytes a branch or `jal` needs to get `distance` away: 4 when it reaches,
+/// 8 for a branch over a `jal` or for `auipc`+`jalr`, and 12 for a branch
+/// over `auipc`+`jalr`.
+pub fn size_for(mne: &str, distance: i64) -> u32 {
+    let branch = is_branch(mne);
+    match (branch, reaches(branch, distance)) {
+        (_, true) => 4,
+        (true, false) if reaches(false, distance - 4) => 8,
+        (true, false) => 12,
+        (false, false) => 8,
+    }
+}
+
+fn inverted(mne: &str) -> &'static str {
+    match mne.to_lowercase().as_ref() {
+        "beq" => "bne",
+        "bne" => "beq",
+        "blt" => "bge",
+        "bge" => "blt",
+        "bltu" => "bgeu",
+        _ => "bltu",
+    }
+}
+
+fn instruction(mne: &str, rd: Option<u32>, rs1: Option<u32>, rs2: Option<u32>, imm: i64) -> InstructionData {
+    InstructionData {
+        mne: mne.to_string(),
+        rd,
+        rs1,
+        rs2,
+        imm: Some(imm as Imm),
+        custom: None,
+    }
+}
+
+/// A `jal rd` over `distance` in `size` bytes.
+fn jump(rd: u32, distance: i64, size: u32) -> Vec<InstructionData> {
+    match size {
+        4 => vec![instruction("jal", Some(rd), None, None, distance)],
+        _ => {
+            let base = if rd == 0 { SCRATCH } else { rd };
+            vec![
+                instruction("auipc", Some(base), None, None, hi(distance)),
+                instruction("jalr", Some(rd), Some(base), None, lo(distance)),
+            ]
+        }
+    }
+}
+
+/// The instructions a branch or `jal`, with its offset in `imm`, turns into
+/// to fill `size` bytes. A branch skips over a jump to the target when its
+/// condition is false.
+pub fn expand(branch: InstructionData, size: u32) -> Vec<InstructionData> {
+    let distance = branch.imm.unwrap_or(0) as i32 as i64;
+    match (size, is_branch(&branch.mne)) {
+        (4, _) => vec![branch],
+        (_, true) => {
+            let skip = instruction(
+                inverted(&branch.mne),
+                None,
+                branch.rs1,
+                branch.rs2,
+                size as i64,
+            );
+            let mut instructions = vec![skip];
+            instructions.extend(jump(0, distance - 4, size - 4));
+            instructions
+        }
+        (_, false) => jump(branch.rd.unwrap_or(0), distance, size),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_for_test() {
+        assert_eq!(size_for("beq", 4094), 4);
+        assert_eq!(size_for("beq", -4096), 4);
+        assert_eq!(size_for("beq", 4096), 8);
+        assert_eq!(size_for("bltu", 0x100004), 12);
+        assert_eq!(size_for("jal", -0x100000), 4);
+        assert_eq!(size_for("jal", 0x100000), 8);
+    }
+
+    #[test]
+    fn expand_test() {
+        let beq = instruction("beq", None, Some(10), Some(11), 0x2000);
+        assert_eq!(
+            expand(beq, 8),
+            vec![
+                instruction("bne", None, Some(10), Some(11), 8),
+                instruction("jal", Some(0), None, None, 0x1FFC),
+            ]
+        );
+        let jal = instruction("jal", Some(0), None, None, 0x123800);
+        assert_eq!(
+            expand(jal, 8),
+            vec![
+                instruction("auipc", Some(6), None, None, 0x124),
+                instruction("jalr", Some(0), Some(6), None, -0x800),
+            ]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::assembler::{self, item_label, linked_words, Word};
+use crate::instructions::Format;
+use crate::parser::{FullFile, Item};
+use crate::Diagnostic;
+
+/// A summary of an assembled program, for keeping an eye on its size.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Stats {
+    pub instructions: usize,
+    pub by_format: BTreeMap<String, usize>,
+    pub by_mnemonic: BTreeMap<String, usize>,
+    /// In the order they were placed.
+    pub sections: Vec<SectionSize>,
+    /// Bytes in executable sections.
+    pub code_size: u32,
+    /// Bytes in the others, including ones that only reserve space.
+    pub data_size: u32,
+    /// Pseudo-instructions, like `la` or `call`, which each became two
+    /// instructions.
+    pub pseudo: usize,
+    /// Branches and jumps rewritten into longer sequences to reach their
+    /// targets.
+    pub relaxed: usize,
+    pub labels: usize,
+    pub longest_block: Option<Block>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SectionSize {
+    pub name: String,
+    /// `code`, `data` or `bss`.
+    pub kind: &'static str,
+    pub size: u32,
+}
+
+/// A run of instructions only entered at the top and only left at the
+/// bottom.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Block {
+    pub address: u32,
+    pub instructions: usize,
+    /// The last label at or before its start.
+    pub label: Option<String>,
+}
+
+impl Stats {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// Whether a word can go somewhere other than the next one.
+fn ends_block(word: &Word) -> bool {
+    matches!(word.format, Format::B | Format::J) || word.mne == "jalr"
+}
+
+/// The longest basic block of `words`. Blocks start at labels, since
+/// anything could jump there, and end after branches and jumps.
+fn longest_block(file: &FullFile, words: &[Word]) -> Option<Block> {
+    // For each item, whether it's the first instruction after a label, and
+    // the last label so far
+    let mut marks = vec![];
+    for section in &file.sections {
+        let mut label = None;
+        let mut pending = false;
+        let section_marks: Vec<_> = section
+            .items
+            .iter()
+            .map(|item| {
+                if let Some(name) = item_label(item) {
+                    label = Some(name.to_string());
+                    pending = true;
+                }
+                let leader = pending && matches!(item, Item::Text(_));
+                pending &= !leader;
+                (leader, label.clone())
+            })
+            .collect();
+        marks.push(section_marks);
+    }
+
+    let mut longest = None;
+    let mut current: Option<Block> = None;
+    let mut previous = None;
+    for word in words {
+        let first = previous != Some((word.section, word.item));
+        let new_section = previous.map(|(section, _)| section) != Some(word.section);
+        previous = Some((word.section, word.item));
+        let (leader, label) = &marks[word.section][word.item];
+        if new_section || (first && *leader) {
+            longest = longer(longest, current.take());
+        }
+        let block = current.get_or_insert_with(|| Block {
+            address: word.address,
+            instructions: 0,
+            label: label.clone(),
+        });
+        block.instructions += 1;
+        if ends_block(word) {
+            longest = longer(longest, current.take());
+        }
+    }
+    longer(longest, current)
+}
+
+fn longer(a: Option<Block>, b: Option<Block>) -> Option<Block> {
+    match (a, b) {
+        (Some(a), Some(b)) if b.instructions > a.instructions => Some(b),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The statistics of `file` linked on its own.
+pub fn stats(file: &FullFile, options: &assembler::Options) -> Result<Stats, Diagnostic> {
+    let (image, words) = linked_words(file, options)?;
+    let (_, relaxed) = assembler::object(file, options)?;
+
+    let mut by_format = BTreeMap::new();
+    let mut by_mnemonic = BTreeMap::new();
+    for word in &words {
+        *by_format.entry(format!("{:?}", word.format)).or_insert(0) += 1;
+        *by_mnemonic.entry(word.mne.clone()).or_insert(0) += 1;
+    }
+    let sections: Vec<_> = image
+        .sections
+        .iter()
+        .map(|section| SectionSize {
+            name: section.name.clone(),
+            kind: match (section.executable, section.nobits) {
+                (true, _) => "code",
+                (false, false) => "data",
+                (false, true) => "bss",
+            },
+            size: section.size,
+        })
+        .collect();
+    let size_of = |code: bool| {
+        sections
+            .iter()
+            .filter(|section| (section.kind == "code") == code)
+            .map(|section| section.size)
+            .sum()
+    };
+    // Each pseudo-instruction expands to two instructions
+    let pseudo = file
+        .sections
+        .iter()
+        .flat_map(|section| &section.items)
+        .filter(|item| matches!(item, Item::Text(text) if text.pseudo))
+        .count()
+        / 2;
+    let labels = file
+        .sections
+        .iter()
+        .flat_map(|section| &section.items)
+        .filter(|item| item_label(item).is_some())
+        .count();
+    Ok(Stats {
+        instructions: words.len(),
+        by_format,
+        by_mnemonic,
+        code_size: size_of(true),
+        data_size: size_of(false),
+        sections,
+        pseudo,
+        relaxed: relaxed.len(),
+        labels,
+        longest_block: longest_block(file, &words),
+    })
+}
+
+/// Lines up `rows` under `headers`, left aligned.
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|i| rows.iter().map(|row| row[i].len()).chain([headers[i].len()]).max().unwrap())
+        .collect();
+    let headers = [headers.iter().map(|s| s.to_string()).collect()];
+    headers
+        .iter()
+        .chain(rows)
+        .map(|row| {
+            let cells: Vec<_> = row.iter().zip(&widths).map(|(cell, &width)| format!("{:width$}", cell, width = width)).collect();
+            format!("{}\n", cells.join("  ").trim_end())
+        })
+        .collect()
+}
+
+impl std::fmt::Display for Stats {
+    /// The sections, the instruction counts by format and by mnemonic, most
+    /// used first, and then the totals.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let sections: Vec<_> = self
+            .sections
+            .iter()
+            .map(|section| vec![section.name.clone(), section.kind.to_string(), section.size.to_string()])
+            .collect();
+        writeln!(f, "{}", table(&["Section", "Kind", "Size"], &sections))?;
+
+        let counts = |counts: &BTreeMap<String, usize>| {
+            let mut counts: Vec<_> = counts.iter().collect();
+            counts.sort_by_key(|&(name, count)| (std::cmp::Reverse(*count), name));
+            counts
+                .into_iter()
+                .map(|(name, count)| vec![name.clone(), count.to_string()])
+                .collect::<Vec<_>>()
+        };
+        writeln!(f, "{}", table(&["Format", "Count"], &counts(&self.by_format)))?;
+        writeln!(f, "{}", table(&["Mnemonic", "Count"], &counts(&self.by_mnemonic)))?;
+
+        let longest = match &self.longest_block {
+            Some(block) => format!(
+                "{} instructions at {:#010x}{}",
+                block.instructions,
+                block.address,
+                block.label.as_ref().map_or(String::new(), |label| format!(" (`{}`)", label))
+            ),
+            None => "none".to_string(),
+        };
+        let totals = [
+            ("instructions", self.instructions.to_string()),
+            ("code size", self.code_size.to_string()),
+            ("data size", self.data_size.to_string()),
+            ("pseudo", self.pseudo.to_string()),
+            ("relaxed", self.relaxed.to_string()),
+            ("labels", self.labels.to_string()),
+            ("longest block", longest),
+        ];
+        let totals: Vec<_> = totals.iter().map(|(name, value)| vec![name.to_string(), value.clone()]).collect();
+        write!(f, "{}", table(&["Total", ""], &totals))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    #[test]
+    fn stats_test() {
+        let source = "
+_start: addi a0, zero, 10
+        addi a1, zero, 0
+loop:
+        add a1, a1, a0
+        addi a0, a0, -1
+        bne a0, zero, loop
+        jal zero, .Ldone
+.Ldone: lui t0, %hi(result)
+        sw a1, %lo(result)(t0)
+        .data
+result: .word 0
+        .bss
+buffer: .space 64
+";
+        let stats = stats(&parse(source).unwrap(), &assembler::Options::default()).unwrap();
+        assert_eq!(stats.instructions, 8);
+        assert_eq!(stats.by_format["I"], 3);
+        assert_eq!(stats.by_format["U"], 1);
+        assert_eq!(stats.by_mnemonic["addi"], 3);
+        let sections: Vec<_> = stats.sections.iter().map(|s| (s.name.as_str(), s.kind, s.size)).collect();
+        assert_eq!(sections, vec![(".text", "code", 32), (".data", "data", 4), (".bss", "bss", 64)]);
+        assert_eq!((stats.code_size, stats.data_size), (32, 68));
+        assert_eq!((stats.relaxed, stats.labels), (0, 5));
+        assert_eq!(
+            stats.longest_block,
+            Some(Block {
+                address: 8,
+                instructions: 3,
+                label: Some("loop".to_string()),
+            })
+        );
+        let table = stats.to_string();
+        assert!(table.contains("Mnemonic  Count\naddi      3\n"), "{}", table);
+        assert!(table.contains("longest block  3 instructions at 0x00000008 (`loop`)"), "{}", table);
+    }
+
+    #[test]
+    fn pseudo_test() {
+        let source = "
+_start: la a0, value
+        call f
+        lui t0, %hi(value)
+        addi t0, t0, %lo(value)
+f:      jalr zero, 0(ra)
+value:  .word 1
+";
+        let stats = stats(&parse(source).unwrap(), &assembler::Options::default()).unwrap();
+        assert_eq!((stats.instructions, stats.pseudo), (7, 2));
+        assert!(stats.to_string().contains("pseudo         2\n"), "{}", stats);
+    }
+}
@@ -0,0 +1,339 @@
+//! The executable format the assembler writes and the simulator loads: a
+//! header, the sections with where they go, an optional symbol table and a
+//! checksum, so a loader can tell a program for it from anything else and
+//! refuse one that's been cut short or damaged on the way.
+//!
+//! Everything is big endian, like the programs themselves, and every part
+//! starts at a multiple of 4 bytes:
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 4 | Magic, `7f 52 56 58` (`\x7fRVX`) |
+//! | 4 | 2 | Version, 1 |
+//! | 6 | 2 | Flags, bit 0 set if there's a symbol table |
+//! | 8 | 4 | Entry point |
+//! | 12 | 2 | Number of sections |
+//! | 14 | 2 | Length of the ISA string |
+//! | 16 | 4 | Number of symbols |
+//! | 20 | | ISA string, like `rv32i`, padded with zeros |
+//! | | 16 each | Section table: address, size, flags (bit 0 executable, bit 1 no bits), offset of the data in the file |
+//! | | | Symbol table: value (4), length of the name (2), 0 (2), the name padded with zeros |
+//! | | | Section data, each padded with zeros |
+//! | | 4 | CRC-32 of everything before it |
+
+pub const MAGIC: [u8; 4] = *b"\x7fRVX";
+pub const VERSION: u16 = 1;
+
+const HAS_SYMBOLS: u16 = 1;
+const EXECUTABLE: u32 = 1;
+const NOBITS: u32 = 2;
+const HEADER_SIZE: usize = 20;
+const SECTION_ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Executable {
+    /// The instructions it needs, like `rv32i`.
+    pub isa: String,
+    pub entry: u32,
+    pub sections: Vec<Section>,
+    /// `None` if it was left out.
+    pub symbols: Option<Vec<Symbol>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Section {
+    /// Where to load it.
+    pub address: u32,
+    pub size: u32,
+    pub executable: bool,
+    /// Only reserves space, which is zeroed, and has no data.
+    pub nobits: bool,
+    /// `size` bytes, or none if `nobits`.
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u32,
+}
+
+/// The CRC-32 of `bytes`, as used by zlib and Ethernet.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn pad(bytes: &mut Vec<u8>) {
+    bytes.resize((bytes.len() + 3) & !3, 0);
+}
+
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Reads the big endian fields of an executable, failing on any that run
+/// past the end.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .offset
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.offset..end))
+            .ok_or_else(|| format!("truncated at {:#x}", self.offset))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self, len: usize) -> Result<String, String> {
+        let at = self.offset;
+        let bytes = self.take(padded(len))?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|_| format!("string at {:#x} isn't UTF-8", at))
+    }
+}
+
+impl Executable {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_be_bytes());
+        let flags = if self.symbols.is_some() { HAS_SYMBOLS } else { 0 };
+        bytes.extend(flags.to_be_bytes());
+        bytes.extend(self.entry.to_be_bytes());
+        bytes.extend((self.sections.len() as u16).to_be_bytes());
+        bytes.extend((self.isa.len() as u16).to_be_bytes());
+        let symbols = self.symbols.as_deref().unwrap_or_default();
+        bytes.extend((symbols.len() as u32).to_be_bytes());
+        bytes.extend(self.isa.as_bytes());
+        pad(&mut bytes);
+
+        let mut symbol_table = vec![];
+        for symbol in symbols {
+            symbol_table.extend(symbol.value.to_be_bytes());
+            symbol_table.extend((symbol.name.len() as u16).to_be_bytes());
+            symbol_table.extend(0u16.to_be_bytes());
+            symbol_table.extend(symbol.name.as_bytes());
+            pad(&mut symbol_table);
+        }
+        let mut offset = bytes.len() + self.sections.len() * SECTION_ENTRY_SIZE + symbol_table.len();
+        for section in &self.sections {
+            let flags = if section.executable { EXECUTABLE } else { 0 } | if section.nobits { NOBITS } else { 0 };
+            let data_offset = if section.nobits { 0 } else { offset };
+            for field in [section.address, section.size, flags, data_offset as u32] {
+                bytes.extend(field.to_be_bytes());
+            }
+            offset += padded(section.data.len());
+        }
+        bytes.extend(symbol_table);
+        for section in &self.sections {
+            bytes.extend(&section.data);
+            pad(&mut bytes);
+        }
+        bytes.extend(crc32(&bytes).to_be_bytes());
+        bytes
+    }
+
+    /// Reads an executable, checking that it is one, that this version can
+    /// read it and that it's in one piece.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Executable, String> {
+        if bytes.get(..4) != Some(&MAGIC) {
+            return Err("not an executable, the magic number is wrong".to_string());
+        }
+        if bytes.len() < HEADER_SIZE + 4 {
+            return Err("truncated header".to_string());
+        }
+        let (contents, crc) = bytes.split_at(bytes.len() - 4);
+        let mut reader = Reader {
+            bytes: contents,
+            offset: 4,
+        };
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(format!("version {} isn't supported, only {}", version, VERSION));
+        }
+        let expected = u32::from_be_bytes(crc.try_into().unwrap());
+        let actual = crc32(contents);
+        if actual != expected {
+            return Err(format!("corrupt, the CRC-32 is {:#010x} but should be {:#010x}", actual, expected));
+        }
+        let flags = reader.u16()?;
+        let entry = reader.u32()?;
+        let section_count = reader.u16()?;
+        let isa_len = reader.u16()?;
+        let symbol_count = reader.u32()?;
+        let isa = reader.string(isa_len as usize)?;
+
+        let mut sections = vec![];
+        for _ in 0..section_count {
+            let address = reader.u32()?;
+            let size = reader.u32()?;
+            let flags = reader.u32()?;
+            let offset = reader.u32()? as usize;
+            if address.checked_add(size.saturating_sub(1)).is_none() {
+                return Err(format!("the section at {:#010x} runs past the end of memory", address));
+            }
+            let nobits = flags & NOBITS != 0;
+            let data = match nobits {
+                true => vec![],
+                false => contents
+                    .get(offset..)
+                    .and_then(|data| data.get(..size as usize))
+                    .ok_or_else(|| format!("the data of the section at {:#010x} is past the end", address))?
+                    .to_vec(),
+            };
+            sections.push(Section {
+                address,
+                size,
+                executable: flags & EXECUTABLE != 0,
+                nobits,
+                data,
+            });
+        }
+
+        let mut symbols = vec![];
+        for _ in 0..symbol_count {
+            let value = reader.u32()?;
+            let len = reader.u16()?;
+            reader.u16()?;
+            let name = reader.string(len as usize)?;
+            symbols.push(Symbol { name, value });
+        }
+        Ok(Executable {
+            isa,
+            entry,
+            sections,
+            symbols: (flags & HAS_SYMBOLS != 0).then_some(symbols),
+        })
+    }
+
+    /// Copies the sections into `memory`, which starts at address 0, and
+    /// zeroes the ones that only reserve space.
+    pub fn load(&self, memory: &mut [u8]) -> Result<(), String> {
+        for section in &self.sections {
+            let start = section.address as usize;
+            let memory = memory.get_mut(start..start + section.size as usize).ok_or_else(|| {
+                format!(
+                    "the section at {:#010x} of {:#x} bytes doesn't fit in memory",
+                    section.address, section.size
+                )
+            })?;
+            match section.nobits {
+                true => memory.fill(0),
+                false => memory.copy_from_slice(&section.data),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn executable() -> Executable {
+        Executable {
+            isa: "rv32i".to_string(),
+            entry: 4,
+            sections: vec![
+                Section {
+                    address: 0,
+                    size: 8,
+                    executable: true,
+                    nobits: false,
+                    data: vec![0x00, 0x00, 0x00, 0x13, 0x00, 0x00, 0x00, 0x6F],
+                },
+                Section {
+                    address: 8,
+                    size: 3,
+                    executable: false,
+                    nobits: false,
+                    data: vec![1, 2, 3],
+                },
+                Section {
+                    address: 12,
+                    size: 16,
+                    executable: false,
+                    nobits: true,
+                    data: vec![],
+                },
+            ],
+            symbols: Some(vec![Symbol {
+                name: "_start".to_string(),
+                value: 4,
+            }]),
+        }
+    }
+
+    #[test]
+    fn crc32_test() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let executable = executable();
+        let bytes = executable.to_bytes();
+        assert_eq!(&bytes[..4], b"\x7fRVX");
+        assert_eq!(bytes.len() % 4, 0);
+        assert_eq!(Executable::from_bytes(&bytes), Ok(executable.clone()));
+
+        let stripped = Executable {
+            symbols: None,
+            ..executable
+        };
+        assert_eq!(Executable::from_bytes(&stripped.to_bytes()), Ok(stripped));
+    }
+
+    #[test]
+    fn reject_test() {
+        let bytes = executable().to_bytes();
+        let err = |bytes: &[u8]| Executable::from_bytes(bytes).unwrap_err();
+        assert!(err(b"\x00\x00\x00\x13").contains("magic number"));
+        assert!(err(&bytes[..bytes.len() - 1]).contains("corrupt"));
+        assert!(err(&bytes[..12]).contains("truncated"));
+
+        let mut flipped = bytes.clone();
+        flipped[40] ^= 1;
+        assert!(err(&flipped).contains("corrupt"));
+
+        let mut newer = bytes.clone();
+        newer[5] = 2;
+        assert!(err(&newer).contains("version 2"));
+
+        // A section that says it's bigger than the file, with a good CRC. The
+        // table starts after the header and `rv32i` padded to 8 bytes
+        let mut long = bytes[..bytes.len() - 4].to_vec();
+        long[28 + 4..28 + 8].copy_from_slice(&0x1000u32.to_be_bytes());
+        long.extend(crc32(&long).to_be_bytes());
+        assert!(err(&long).contains("past the end"));
+    }
+
+    #[test]
+    fn load_test() {
+        let mut memory = vec![0xFF; 32];
+        executable().load(&mut memory).unwrap();
+        assert_eq!(&memory[..12], &[0, 0, 0, 0x13, 0, 0, 0, 0x6F, 1, 2, 3, 0xFF]);
+        assert!(memory[12..28].iter().all(|&b| b == 0));
+        assert_eq!(memory[28], 0xFF);
+        assert!(executable().load(&mut memory[..16]).is_err());
+    }
+}
@@ -1,3 +1,34 @@
+use riscv_executable::Executable;
+use std::fmt::Display;
+use std::{env, fs, process};
+
+/// Bytes of memory, from address 0.
+const MEMORY_SIZE: usize = 1 << 20;
+
+/// The instructions the simulator runs.
+const ISA: &str = "rv32i";
+
 fn main() {
-    println!("Hello, world!");
+    let path = env::args().nth(1).unwrap_or_else(|| {
+        eprintln!("usage: riscv-simulator EXECUTABLE");
+        process::exit(2);
+    });
+    let bytes = fs::read(&path).unwrap_or_else(|e| fail(&path, e));
+    let executable = Executable::from_bytes(&bytes).unwrap_or_else(|e| fail(&path, e));
+    if executable.isa != ISA {
+        fail(&path, format!("needs {}, but this only runs {}", executable.isa, ISA));
+    }
+    let mut memory = vec![0; MEMORY_SIZE];
+    executable.load(&mut memory).unwrap_or_else(|e| fail(&path, e));
+    println!(
+        "{}: loaded {} section(s), entry {:#010x}",
+        path,
+        executable.sections.len(),
+        executable.entry
+    );
+}
+
+fn fail(path: &str, e: impl Display) -> ! {
+    eprintln!("{}: {}", path, e);
+    process::exit(1);
 }